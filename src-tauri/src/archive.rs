@@ -0,0 +1,482 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{Read, Write},
+    path::Path,
+};
+
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use thiserror::Error;
+
+use crate::{
+    device::{
+        capabilities::{self, DeviceCapabilities},
+        device_manager::DeviceManager,
+    },
+    profile::{metadata::unix_time_ms, profile_manager::ProfileManager},
+    storage_manager::{
+        file_format::{FileFormat, FileFormatError},
+        schema, StorageManager, Store,
+    },
+};
+
+/// The newest version of the archive format. Archives of newer versions are refused.
+pub(crate) const ARCHIVE_VERSION: u32 = 1;
+
+/// Configuration archives, whose header is their manifest.
+const ARCHIVE_FILE: FileFormat = FileFormat {
+    name: "crabby-archive",
+    description: "configuration archive",
+    extension: "crabby-archive",
+    version: ARCHIVE_VERSION,
+    header: "/manifest",
+};
+
+/// Entries holding user model definitions are stored under this prefix.
+const MODELS_PREFIX: &str = "models/";
+
+#[derive(Debug, Error, Serialize, Deserialize, Type)]
+pub(crate) enum ArchiveError {
+    #[error("IOError: {0}")]
+    IOError(String),
+
+    #[error("ParseError: {0}")]
+    ParseError(String),
+
+    #[error("UnsupportedVersion: Version {0} is newer than this application supports")]
+    UnsupportedVersion(u32),
+
+    #[error("ChecksumMismatch: Entry {0} is damaged")]
+    ChecksumMismatch(String),
+
+    #[error("MissingEntry: {0}")]
+    MissingEntry(String),
+
+    #[error("InvalidEntry: {0}")]
+    InvalidEntry(String),
+}
+
+impl From<FileFormatError> for ArchiveError {
+    fn from(e: FileFormatError) -> Self {
+        match e {
+            FileFormatError::Malformed(e) => Self::ParseError(e),
+            FileFormatError::UnsupportedVersion(version) => Self::UnsupportedVersion(version),
+        }
+    }
+}
+
+/// A file of the archive as listed in the manifest.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize, Type)]
+pub(crate) struct ArchiveEntry {
+    pub name: String,
+    pub size: u64,
+    /// CRC-32 of the contents.
+    pub checksum: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize, Type)]
+pub(crate) struct ArchiveManifest {
+    pub format: String,
+    pub format_version: u32,
+    /// The version of the application that made the archive.
+    pub app_version: String,
+    pub created_ms: u64,
+    pub entries: Vec<ArchiveEntry>,
+}
+
+/// The whole configuration of an installation in one file: the device and profile stores,
+/// which include the macros, and the user model definitions. Stored as gzip compressed JSON,
+/// each entry checked against the manifest when read.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub(crate) struct ConfigurationArchive {
+    manifest: ArchiveManifest,
+    files: BTreeMap<String, String>,
+}
+
+/// What a restore adds, removes and replaces, by the names the user knows things by.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Type)]
+pub(crate) struct Changes {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<String>,
+}
+
+/// The effect restoring an archive would have on the current configuration.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Type)]
+pub(crate) struct RestorePreview {
+    pub manifest: ArchiveManifest,
+    pub devices: Changes,
+    pub profiles: Changes,
+    pub macros: Changes,
+    /// Model definitions are only added or replaced, and take effect after a restart.
+    pub model_definitions: Changes,
+}
+
+impl ConfigurationArchive {
+    /// Archive the given stores and the user model definitions kept by `storage_manager`.
+    pub(crate) fn new(
+        device_manager: &DeviceManager,
+        profile_manager: &ProfileManager,
+        storage_manager: &StorageManager,
+    ) -> Result<Self, ArchiveError> {
+        let mut files = BTreeMap::new();
+        files.insert(
+            DeviceManager::storage_path().to_string(),
+            schema::to_document(device_manager)
+                .map_err(|e| ArchiveError::ParseError(e.to_string()))?,
+        );
+        files.insert(
+            ProfileManager::storage_path().to_string(),
+            schema::to_document(profile_manager)
+                .map_err(|e| ArchiveError::ParseError(e.to_string()))?,
+        );
+        for (file_name, contents) in read_model_definitions(storage_manager)? {
+            files.insert(format!("{MODELS_PREFIX}{file_name}"), contents);
+        }
+
+        let entries = files
+            .iter()
+            .map(|(name, contents)| ArchiveEntry {
+                name: name.to_string(),
+                size: contents.len() as u64,
+                checksum: crc32fast::hash(contents.as_bytes()),
+            })
+            .collect();
+
+        Ok(Self {
+            manifest: ArchiveManifest {
+                format: ARCHIVE_FILE.name.to_string(),
+                format_version: ARCHIVE_VERSION,
+                app_version: env!("CARGO_PKG_VERSION").to_string(),
+                created_ms: unix_time_ms(),
+                entries,
+            },
+            files,
+        })
+    }
+
+    pub(crate) fn get_manifest(&self) -> &ArchiveManifest {
+        &self.manifest
+    }
+
+    pub(crate) fn to_bytes(&self) -> Result<Vec<u8>, ArchiveError> {
+        let json = serde_json::to_vec(self).map_err(|e| ArchiveError::ParseError(e.to_string()))?;
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder
+            .write_all(&json)
+            .and_then(|_| encoder.finish())
+            .map_err(|e| ArchiveError::IOError(e.to_string()))
+    }
+
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Self, ArchiveError> {
+        let mut json = String::new();
+        GzDecoder::new(bytes)
+            .read_to_string(&mut json)
+            .map_err(|e| ArchiveError::ParseError(e.to_string()))?;
+
+        let archive: Self = ARCHIVE_FILE.parse(&json)?;
+        archive.verify()?;
+        Ok(archive)
+    }
+
+    /// Write the archive, adding the archive extension to paths without one.
+    pub(crate) fn write(&self, path: &Path) -> Result<(), ArchiveError> {
+        ARCHIVE_FILE
+            .write(path, &self.to_bytes()?)
+            .map_err(|e| ArchiveError::IOError(e.to_string()))
+    }
+
+    pub(crate) fn read(path: &Path) -> Result<Self, ArchiveError> {
+        let bytes = fs::read(path).map_err(|e| ArchiveError::IOError(e.to_string()))?;
+        Self::from_bytes(&bytes)
+    }
+
+    /// Check that the files match the manifest, one for one.
+    fn verify(&self) -> Result<(), ArchiveError> {
+        for entry in &self.manifest.entries {
+            let contents = self
+                .files
+                .get(&entry.name)
+                .ok_or_else(|| ArchiveError::MissingEntry(entry.name.to_string()))?;
+            if contents.len() as u64 != entry.size
+                || crc32fast::hash(contents.as_bytes()) != entry.checksum
+            {
+                return Err(ArchiveError::ChecksumMismatch(entry.name.to_string()));
+            }
+        }
+        if let Some(name) = self.files.keys().find(|name| {
+            !self
+                .manifest
+                .entries
+                .iter()
+                .any(|entry| entry.name == **name)
+        }) {
+            return Err(ArchiveError::InvalidEntry(format!(
+                "{name} is not listed in the manifest"
+            )));
+        }
+        Ok(())
+    }
+
+    /// The device and profile stores held by the archive.
+    pub(crate) fn stores(&self) -> Result<(DeviceManager, ProfileManager), ArchiveError> {
+        let device_manager = self.parse_entry::<DeviceManager>(DeviceManager::storage_path())?;
+        let profile_manager = self.parse_entry::<ProfileManager>(ProfileManager::storage_path())?;
+        Ok((device_manager, profile_manager))
+    }
+
+    /// Stores are migrated like the stored files, so archives outlive schema changes.
+    fn parse_entry<T: Store + serde::de::DeserializeOwned>(
+        &self,
+        name: &str,
+    ) -> Result<T, ArchiveError> {
+        let contents = self
+            .files
+            .get(name)
+            .ok_or_else(|| ArchiveError::MissingEntry(name.to_string()))?;
+        schema::from_document(contents)
+            .map_err(|e| ArchiveError::InvalidEntry(format!("{name}: {e}")))
+    }
+
+    /// The user model definitions held by the archive, by file name. Every definition is
+    /// checked, so that a restore never writes one the application would skip.
+    pub(crate) fn model_definitions(&self) -> Result<Vec<(&str, &str)>, ArchiveError> {
+        self.files
+            .iter()
+            .filter_map(|(name, contents)| {
+                name.strip_prefix(MODELS_PREFIX)
+                    .map(|file_name| (name, file_name, contents))
+            })
+            .map(|(name, file_name, contents)| {
+                let extension = Path::new(file_name)
+                    .extension()
+                    .map(|extension| extension.to_string_lossy().to_lowercase())
+                    .unwrap_or_default();
+                let is_plain_name = Path::new(file_name)
+                    .file_name()
+                    .is_some_and(|plain| plain == file_name);
+                if !is_plain_name {
+                    return Err(ArchiveError::InvalidEntry(name.to_string()));
+                }
+                DeviceCapabilities::parse(contents, &extension)
+                    .map_err(|e| ArchiveError::InvalidEntry(format!("{name}: {e}")))?;
+                Ok((file_name, contents.as_str()))
+            })
+            .collect()
+    }
+
+    /// Compare the archive with the current configuration.
+    pub(crate) fn preview(
+        &self,
+        device_manager: &DeviceManager,
+        profile_manager: &ProfileManager,
+        storage_manager: &StorageManager,
+    ) -> Result<RestorePreview, ArchiveError> {
+        let (restored_devices, restored_profiles) = self.stores()?;
+
+        let devices = |dm: &DeviceManager| {
+            dm.get_devices()
+                .into_iter()
+                .map(|device| {
+                    (
+                        device.get_id().to_string(),
+                        format!("{} {}", device.get_model(), device.get_id()),
+                        // Connection and sync states say nothing about the configuration.
+                        serde_json::json!([device.get_model(), device.get_active_profile()]),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        let profiles = |pm: &ProfileManager| {
+            pm.get_profiles()
+                .into_iter()
+                .map(|profile| {
+                    (
+                        profile.get_id().to_string(),
+                        profile.get_metadata().name.to_string(),
+                        serde_json::to_value(profile).unwrap_or_default(),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        let macros = |pm: &ProfileManager| {
+            pm.get_macros()
+                .into_iter()
+                .map(|recorded| {
+                    (
+                        recorded.get_id().to_string(),
+                        recorded.get_name().to_string(),
+                        serde_json::to_value(recorded).unwrap_or_default(),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let current_models = read_model_definitions(storage_manager)?
+            .into_iter()
+            .map(|(file_name, contents)| (file_name.to_string(), file_name, contents.into()))
+            .collect::<Vec<_>>();
+        let restored_models = self
+            .model_definitions()?
+            .into_iter()
+            .map(|(file_name, contents)| {
+                (
+                    file_name.to_string(),
+                    file_name.to_string(),
+                    contents.into(),
+                )
+            })
+            .collect::<Vec<_>>();
+        let mut model_definitions = changes(current_models, restored_models);
+        model_definitions.removed.clear();
+
+        Ok(RestorePreview {
+            manifest: self.manifest.clone(),
+            devices: changes(devices(device_manager), devices(&restored_devices)),
+            profiles: changes(profiles(profile_manager), profiles(&restored_profiles)),
+            macros: changes(macros(profile_manager), macros(&restored_profiles)),
+            model_definitions,
+        })
+    }
+}
+
+/// Compare two sets of `(id, name, value)`, reporting names in alphabetical order.
+fn changes(
+    current: Vec<(String, String, serde_json::Value)>,
+    restored: Vec<(String, String, serde_json::Value)>,
+) -> Changes {
+    let current: BTreeMap<_, _> = current
+        .into_iter()
+        .map(|(id, name, value)| (id, (name, value)))
+        .collect();
+    let restored: BTreeMap<_, _> = restored
+        .into_iter()
+        .map(|(id, name, value)| (id, (name, value)))
+        .collect();
+
+    let mut changes = Changes::default();
+    for (id, (name, value)) in &restored {
+        match current.get(id) {
+            None => changes.added.push(name.to_string()),
+            Some((_, existing)) if existing != value => changes.changed.push(name.to_string()),
+            Some(_) => {}
+        }
+    }
+    for (id, (name, _)) in &current {
+        if !restored.contains_key(id) {
+            changes.removed.push(name.to_string());
+        }
+    }
+
+    changes.added.sort();
+    changes.removed.sort();
+    changes.changed.sort();
+    changes
+}
+
+/// The `toml` and `json` files of the user model directory, by file name. A missing directory
+/// holds no definitions.
+fn read_model_definitions(
+    storage_manager: &StorageManager,
+) -> Result<Vec<(String, String)>, ArchiveError> {
+    let file_names = storage_manager
+        .list_files(capabilities::USER_MODELS_DIR)
+        .map_err(|e| ArchiveError::IOError(e.to_string()))?;
+
+    let mut definitions = vec![];
+    for file_name in file_names {
+        let is_definition = Path::new(&file_name)
+            .extension()
+            .is_some_and(|extension| extension == "toml" || extension == "json");
+        if !is_definition {
+            continue;
+        }
+        let contents = storage_manager
+            .read_file(&format!("{}/{file_name}", capabilities::USER_MODELS_DIR))
+            .map_err(|e| ArchiveError::IOError(format!("Could not read {file_name}: {e}")))?;
+        definitions.push((file_name, contents));
+    }
+    definitions.sort();
+    Ok(definitions)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{ArchiveError, ConfigurationArchive};
+    use crate::{
+        device::{device_manager::DeviceManager, mouse::Mouse, Device, DeviceModel, DeviceType},
+        profile::{
+            metadata::ProfileMetadata, mouse_profile::MouseProfile,
+            profile_manager::ProfileManager, Profile, ProfileConfiguration,
+        },
+        storage_manager::StorageManager,
+    };
+
+    fn configuration() -> (DeviceManager, ProfileManager) {
+        let mut pm = ProfileManager::default();
+        let device_id = "device".to_string();
+        let profile_id = pm
+            .register_device(&device_id, DeviceModel::new("M1"))
+            .unwrap();
+
+        let device = Device::builder()
+            .with_id(device_id.to_string())
+            .with_model(DeviceModel::new("M1"))
+            .with_device_type(DeviceType::Mouse(Mouse { dpi: 800 }))
+            .with_active_profile(profile_id)
+            .build();
+        let dm = DeviceManager::new(HashMap::from([(device_id, device)]));
+        (dm, pm)
+    }
+
+    #[test]
+    fn round_trips_and_previews_changes() {
+        let (dm, mut pm) = configuration();
+        let storage_manager = StorageManager::in_memory();
+        storage_manager
+            .write_file("models/m1.json", include_bytes!("../models/m1.json"))
+            .unwrap();
+        let archive = ConfigurationArchive::new(&dm, &pm, &storage_manager).unwrap();
+        let archive = ConfigurationArchive::from_bytes(&archive.to_bytes().unwrap()).unwrap();
+        assert_eq!(archive.get_manifest().entries.len(), 3);
+
+        let (restored_dm, restored_pm) = archive.stores().unwrap();
+        assert_eq!(restored_dm, dm);
+        assert_eq!(restored_pm.get_profiles().len(), 1);
+
+        let gaming = Profile::new(ProfileConfiguration::Mouse(MouseProfile::new(1600)))
+            .with_metadata(ProfileMetadata::new("Gaming"));
+        pm.insert_profile(&"device".to_string(), gaming).unwrap();
+
+        let preview = archive
+            .preview(&DeviceManager::default(), &pm, &StorageManager::in_memory())
+            .unwrap();
+        assert_eq!(preview.devices.added.len(), 1);
+        assert_eq!(preview.profiles.removed, vec!["Gaming".to_string()]);
+        assert!(preview.profiles.added.is_empty() && preview.profiles.changed.is_empty());
+        assert_eq!(preview.model_definitions.added, vec!["m1.json".to_string()]);
+    }
+
+    #[test]
+    fn detects_damaged_archives() {
+        let (dm, pm) = configuration();
+        let mut archive =
+            ConfigurationArchive::new(&dm, &pm, &StorageManager::in_memory()).unwrap();
+        archive
+            .files
+            .insert("profiles.json".to_string(), "{}".to_string());
+
+        assert!(matches!(
+            ConfigurationArchive::from_bytes(&archive.to_bytes().unwrap()),
+            Err(ArchiveError::ChecksumMismatch(_))
+        ));
+        assert!(matches!(
+            ConfigurationArchive::from_bytes(b"not an archive"),
+            Err(ArchiveError::ParseError(_))
+        ));
+    }
+}
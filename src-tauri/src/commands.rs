@@ -3,11 +3,18 @@ use specta::{collect_types, functions::FunctionDataType, ExportError, Type, Type
 use thiserror::Error;
 
 use crate::{
-    commands, device::device_manager::DeviceManagerError, profile::profile_manager::ProfileManagerError
+    archive::ArchiveError,
+    commands, device::device_manager::DeviceManagerError,
+    profile::{portable::ProfileFileError, profile_manager::ProfileManagerError, ConfigurationError},
+    state::StateError,
 };
 
+pub mod archive_commands;
 pub mod device_commands;
+pub mod lighting_commands;
+pub mod macro_commands;
 pub mod profile_commands;
+pub mod storage_commands;
 
 #[derive(Debug, Error, Serialize, Type)]
 pub(crate) enum CommandError {
@@ -17,6 +24,15 @@ pub(crate) enum CommandError {
     #[error(transparent)]
     DeviceManagerError(#[from] DeviceManagerError),
 
+    #[error(transparent)]
+    ConfigurationError(#[from] ConfigurationError),
+
+    #[error(transparent)]
+    ProfileFileError(#[from] ProfileFileError),
+
+    #[error(transparent)]
+    ArchiveError(#[from] ArchiveError),
+
     #[error("StorageManagerError: {message}")]
     StorageManagerError { message: String },
 
@@ -24,17 +40,52 @@ pub(crate) enum CommandError {
     StateAcessError { message: String },
 }
 
+impl From<StateError> for CommandError {
+    fn from(e: StateError) -> Self {
+        match e {
+            StateError::DeviceManagerError(e) => Self::DeviceManagerError(e),
+            StateError::ProfileManagerError(e) => Self::ProfileManagerError(e),
+            StateError::ArchiveError(e) => Self::ArchiveError(e),
+            StateError::StorageManagerError(e) => Self::StorageManagerError {
+                message: e.to_string(),
+            },
+        }
+    }
+}
+
 type CommandType = Result<(Vec<FunctionDataType>, TypeDefs), ExportError>;
 
 pub(crate) fn export_commands() -> CommandType {
     collect_types![
         device_commands::delete_device,
         device_commands::get_connected_devices,
+        device_commands::get_unregistered_devices,
+        device_commands::register_device,
+        lighting_commands::preview_lighting,
+        macro_commands::get_macros,
+        macro_commands::insert_macro,
+        macro_commands::record_macro,
+        macro_commands::delete_macro,
         profile_commands::get_profile,
         profile_commands::get_active_profile,
+        profile_commands::set_active_profile,
         profile_commands::get_device_profile_ids,
         profile_commands::insert_profile,
         profile_commands::overwrite_profile,
         profile_commands::delete_profile,
+        profile_commands::get_library_profiles,
+        profile_commands::add_library_profile,
+        profile_commands::assign_profile,
+        profile_commands::delete_library_profile,
+        profile_commands::export_profile,
+        profile_commands::import_profile,
+        archive_commands::export_configuration_archive,
+        archive_commands::preview_configuration_archive,
+        archive_commands::restore_configuration_archive,
+        storage_commands::take_recovery_notices,
+        storage_commands::list_backups,
+        storage_commands::restore_backup,
+        storage_commands::get_backup_policy,
+        storage_commands::set_backup_policy,
     ]
 }
@@ -0,0 +1,69 @@
+use std::path::PathBuf;
+
+use tauri::{async_runtime::RwLock, AppHandle};
+
+use super::CommandError;
+use crate::{
+    archive::{ConfigurationArchive, RestorePreview},
+    device::ConnectionState,
+    events,
+    state::ApplicationState,
+};
+
+/// Write the devices, profiles, macros and user model definitions to a single
+/// `.crabby-archive` file, for moving the configuration to another installation.
+#[tauri::command]
+#[specta::specta]
+pub(crate) async fn export_configuration_archive(
+    state: tauri::State<'_, RwLock<ApplicationState>>,
+    path: PathBuf,
+) -> Result<(), CommandError> {
+    let guard = state.read().await;
+    ConfigurationArchive::new(
+        guard.get_device_manager(),
+        guard.get_profile_manager(),
+        guard.get_storage_manager(),
+    )?
+    .write(&path)?;
+    Ok(())
+}
+
+/// What restoring an archive would change, without changing anything.
+#[tauri::command]
+#[specta::specta]
+pub(crate) async fn preview_configuration_archive(
+    state: tauri::State<'_, RwLock<ApplicationState>>,
+    path: PathBuf,
+) -> Result<RestorePreview, CommandError> {
+    let archive = ConfigurationArchive::read(&path)?;
+
+    let guard = state.read().await;
+    let preview = archive.preview(
+        guard.get_device_manager(),
+        guard.get_profile_manager(),
+        guard.get_storage_manager(),
+    )?;
+    Ok(preview)
+}
+
+/// Replace the whole configuration with the one held by an archive. Attached devices are synced
+/// with their restored profiles.
+#[tauri::command]
+#[specta::specta]
+pub(crate) async fn restore_configuration_archive(
+    app_handle: AppHandle,
+    state: tauri::State<'_, RwLock<ApplicationState>>,
+    path: PathBuf,
+) -> Result<RestorePreview, CommandError> {
+    let archive = ConfigurationArchive::read(&path)?;
+
+    let mut guard = state.write().await;
+    let preview = guard.restore_archive(&archive)?;
+
+    for device in guard.get_device_manager().get_devices() {
+        if device.get_connection_state() == ConnectionState::Connected {
+            events::emit_device_event(&app_handle, events::DEVICE_SYNC_STATUS_CHANGED, device);
+        }
+    }
+    Ok(preview)
+}
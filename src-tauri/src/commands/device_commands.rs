@@ -2,9 +2,8 @@ use tauri::async_runtime::RwLock;
 
 use super::CommandError;
 use crate::{
-    device::{Device, DeviceId},
+    device::{discovery::DiscoveredDevice, Device, DeviceId},
     state::ApplicationState,
-    storage_manager::Store,
 };
 
 #[tauri::command]
@@ -23,30 +22,58 @@ pub(crate) async fn get_connected_devices(
     Ok(devices)
 }
 
+/// Attached devices that have not been registered yet.
 #[tauri::command]
 #[specta::specta]
-pub(crate) async fn delete_device(
+pub(crate) async fn get_unregistered_devices(
+    state: tauri::State<'_, RwLock<ApplicationState>>,
+) -> Result<Vec<DiscoveredDevice>, CommandError> {
+    let guard = state.read().await;
+    let devices = guard
+        .get_device_manager()
+        .get_unregistered_devices()
+        .into_iter()
+        .cloned()
+        .collect();
+
+    Ok(devices)
+}
+
+/// Register an attached device, creating its default profile. The device is connected and
+/// synced by the discovery service shortly after.
+#[tauri::command]
+#[specta::specta]
+pub(crate) async fn register_device(
     state: tauri::State<'_, RwLock<ApplicationState>>,
     device_id: DeviceId,
-) -> Result<(), CommandError> {
+) -> Result<Device, CommandError> {
     let mut guard = state.write().await;
+    let discovered = guard
+        .get_device_manager()
+        .get_unregistered_device(&device_id)?
+        .clone();
 
-    guard.get_profile_manager_mut().delete_device(&device_id)?;
-    guard.get_device_manager_mut().delete_device(&device_id)?;
+    Ok(guard.register_device(&discovered)?)
+}
 
-    guard
-        .get_profile_manager()
-        .to_storage(&guard.get_storage_manager())
-        .map_err(|e| CommandError::StorageManagerError {
-            message: e.to_string(),
-        })?;
+#[tauri::command]
+#[specta::specta]
+pub(crate) async fn delete_device(
+    state: tauri::State<'_, RwLock<ApplicationState>>,
+    device_id: DeviceId,
+) -> Result<(), CommandError> {
+    let mut guard = state.write().await;
 
+    let model = guard
+        .get_device_manager()
+        .get_device(&device_id)?
+        .get_model()
+        .clone();
     guard
-        .get_profile_manager()
-        .to_storage(&guard.get_storage_manager())
-        .map_err(|e| CommandError::StorageManagerError {
-            message: e.to_string(),
-        })?;
+        .get_profile_manager_mut()
+        .delete_device(&device_id, &model)?;
+    guard.get_device_manager_mut().delete_device(&device_id)?;
 
+    guard.persist()?;
     Ok(())
 }
@@ -0,0 +1,34 @@
+use tauri::async_runtime::RwLock;
+
+use super::CommandError;
+use crate::{
+    device::DeviceId,
+    profile::lighting::{LedPress, Lighting, LightingFrame, LightingLayout, LightingRenderer},
+    state::ApplicationState,
+};
+
+/// Render `frame_count` frames of a lighting effect on the lights of a device, starting at
+/// `start_ms` and `interval_ms` apart. Key presses drive the reactive effect.
+#[tauri::command]
+#[specta::specta]
+pub(crate) async fn preview_lighting(
+    state: tauri::State<'_, RwLock<ApplicationState>>,
+    device_id: DeviceId,
+    lighting: Lighting,
+    presses: Vec<LedPress>,
+    start_ms: u64,
+    interval_ms: u64,
+    frame_count: usize,
+) -> Result<Vec<LightingFrame>, CommandError> {
+    let guard = state.read().await;
+    let model = guard
+        .get_device_manager()
+        .get_device(&device_id)?
+        .get_model();
+    let layout = LightingLayout::for_model(model);
+    lighting.validate(&layout)?;
+
+    let mut renderer = LightingRenderer::new(&lighting, &layout);
+    presses.into_iter().for_each(|press| renderer.press(press));
+    Ok(renderer.frames(start_ms, interval_ms, frame_count)?)
+}
@@ -0,0 +1,139 @@
+use tauri::{async_runtime::RwLock, AppHandle};
+
+use super::CommandError;
+use crate::{
+    device::DeviceId,
+    events,
+    profile::{
+        macros::{
+            recorder::{DelayMode, MacroRecorder, RecordedEvent},
+            Macro, MacroId, MacroLimits, RepeatMode,
+        },
+        profile_manager::ProfileManagerError,
+    },
+    state::ApplicationState,
+    storage_manager::Store,
+};
+
+/// Every macro in the library.
+#[tauri::command]
+#[specta::specta]
+pub(crate) async fn get_macros(
+    state: tauri::State<'_, RwLock<ApplicationState>>,
+) -> Result<Vec<Macro>, CommandError> {
+    let guard = state.read().await;
+    let macros = guard
+        .get_profile_manager()
+        .get_macros()
+        .into_iter()
+        .cloned()
+        .collect();
+
+    Ok(macros)
+}
+
+/// Store a macro in the library, replacing the macro with the same id. The macro must fit
+/// the device it is edited for. Devices whose active profile plays the macro are synced again.
+#[tauri::command]
+#[specta::specta]
+pub(crate) async fn insert_macro(
+    app_handle: AppHandle,
+    state: tauri::State<'_, RwLock<ApplicationState>>,
+    device_id: DeviceId,
+    new_macro: Macro,
+) -> Result<(), CommandError> {
+    store_macro(&app_handle, &state, &device_id, new_macro).await?;
+    Ok(())
+}
+
+/// Turn a sequence of captured input events into a macro and store it in the library. The
+/// macro must fit the device it is recorded for.
+#[tauri::command]
+#[specta::specta]
+pub(crate) async fn record_macro(
+    app_handle: AppHandle,
+    state: tauri::State<'_, RwLock<ApplicationState>>,
+    device_id: DeviceId,
+    name: String,
+    repeat: RepeatMode,
+    delay_mode: DelayMode,
+    events: Vec<RecordedEvent>,
+) -> Result<Macro, CommandError> {
+    let mut recorder = MacroRecorder::new(delay_mode);
+    events.into_iter().for_each(|event| recorder.push(event));
+    let recorded = recorder.finish(name, repeat);
+
+    store_macro(&app_handle, &state, &device_id, recorded.clone()).await?;
+    Ok(recorded)
+}
+
+#[tauri::command]
+#[specta::specta]
+pub(crate) async fn delete_macro(
+    state: tauri::State<'_, RwLock<ApplicationState>>,
+    macro_id: MacroId,
+) -> Result<(), CommandError> {
+    let mut guard = state.write().await;
+    guard.get_profile_manager_mut().delete_macro(&macro_id)?;
+
+    guard
+        .get_profile_manager()
+        .to_storage(&guard.get_storage_manager())
+        .map_err(|e| CommandError::StorageManagerError {
+            message: e.to_string(),
+        })?;
+
+    Ok(())
+}
+
+async fn store_macro(
+    app_handle: &AppHandle,
+    state: &RwLock<ApplicationState>,
+    device_id: &DeviceId,
+    recorded: Macro,
+) -> Result<(), CommandError> {
+    let mut guard = state.write().await;
+    let macro_id = recorded.get_id().to_string();
+    let model = guard
+        .get_device_manager()
+        .get_device(device_id)?
+        .get_model();
+    let limits = MacroLimits::for_model(model)
+        .ok_or_else(|| ProfileManagerError::UnknownModel(model.to_string()))?;
+
+    guard
+        .get_profile_manager_mut()
+        .insert_macro(recorded, &limits)?;
+
+    guard
+        .get_profile_manager()
+        .to_storage(&guard.get_storage_manager())
+        .map_err(|e| CommandError::StorageManagerError {
+            message: e.to_string(),
+        })?;
+
+    let playing: Vec<DeviceId> = guard
+        .get_device_manager()
+        .get_devices()
+        .into_iter()
+        .filter(|device| {
+            guard
+                .get_profile_manager()
+                .get_profile(device.get_active_profile())
+                .map(|profile| {
+                    profile
+                        .get_configuration()
+                        .get_macro_ids()
+                        .contains(&&macro_id)
+                })
+                .unwrap_or(false)
+        })
+        .map(|device| device.get_id().to_string())
+        .collect();
+    for device_id in playing {
+        let device = guard.sync_active_profile(&device_id)?;
+        events::emit_device_event(app_handle, events::DEVICE_SYNC_STATUS_CHANGED, &device);
+    }
+
+    Ok(())
+}
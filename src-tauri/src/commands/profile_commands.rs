@@ -1,27 +1,33 @@
-use tauri::async_runtime::RwLock;
+use std::path::PathBuf;
+
+use tauri::{async_runtime::RwLock, AppHandle};
 
 use super::CommandError;
 use crate::{
-    device::DeviceId,
+    device::{Device, DeviceId},
+    events,
     profile::{
-        profile_manager::{ProfileManagerError, ProfileTetrad},
+        macros::MacroLimits,
+        portable::{ImportReport, ProfileFile},
+        profile_manager::{ProfileManagerError, ProfileSelector},
         Profile, ProfileId,
     },
     state::ApplicationState,
     storage_manager::Store,
 };
 
+/// The profile in each of the device's slots, `None` for empty slots.
 #[tauri::command]
 #[specta::specta]
 pub(crate) async fn get_device_profile_ids(
     state: tauri::State<'_, RwLock<ApplicationState>>,
     device_id: String,
-) -> Result<ProfileTetrad, CommandError> {
+) -> Result<Vec<Option<ProfileId>>, CommandError> {
     let guard = state.read().await;
     let profiles = guard
         .get_profile_manager()
         .get_device_profile_ids(&device_id)?;
-    Ok(profiles.clone())
+    Ok(profiles.get_slots().to_vec())
 }
 
 #[tauri::command]
@@ -37,6 +43,39 @@ pub(crate) async fn get_active_profile(
     Ok(profile.clone())
 }
 
+/// Make one of the device's profiles active, persist the change and apply the profile to the
+/// hardware. Returns the device with its updated sync status.
+#[tauri::command]
+#[specta::specta]
+pub(crate) async fn set_active_profile(
+    app_handle: AppHandle,
+    state: tauri::State<'_, RwLock<ApplicationState>>,
+    device_id: DeviceId,
+    selector: ProfileSelector,
+) -> Result<Device, CommandError> {
+    let mut guard = state.write().await;
+    let profile_id = guard
+        .get_profile_manager()
+        .resolve_device_profile(&device_id, &selector)?;
+
+    guard
+        .get_device_manager_mut()
+        .get_device_mut(&device_id)?
+        .set_active_profile(profile_id);
+
+    guard
+        .get_device_manager()
+        .to_storage(&guard.get_storage_manager())
+        .map_err(|e| CommandError::StorageManagerError {
+            message: e.to_string(),
+        })?;
+
+    let device = guard.sync_active_profile(&device_id)?;
+    events::emit_device_event(&app_handle, events::PROFILE_ACTIVATED, &device);
+    events::emit_device_event(&app_handle, events::DEVICE_SYNC_STATUS_CHANGED, &device);
+    Ok(device)
+}
+
 #[tauri::command]
 #[specta::specta]
 pub(crate) async fn get_profile(
@@ -56,6 +95,12 @@ pub(crate) async fn insert_profile(
     profile: Profile,
 ) -> Result<(), CommandError> {
     let mut guard = state.write().await;
+    let model = guard.get_device_manager().get_device(&device_id)?.get_model();
+    profile.get_configuration().validate(model)?;
+    guard
+        .get_profile_manager()
+        .check_macro_references(model, profile.get_configuration())?;
+
     guard
         .get_profile_manager_mut()
         .insert_profile(&device_id, profile)?;
@@ -73,12 +118,20 @@ pub(crate) async fn insert_profile(
 #[tauri::command]
 #[specta::specta]
 pub(crate) async fn overwrite_profile(
+    app_handle: AppHandle,
     state: tauri::State<'_, RwLock<ApplicationState>>,
     device_id: DeviceId,
     profile_id: ProfileId,
     profile: Profile,
 ) -> Result<(), CommandError> {
     let mut guard = state.write().await;
+    let model = guard.get_device_manager().get_device(&device_id)?.get_model();
+    profile.get_configuration().validate(model)?;
+    guard
+        .get_profile_manager()
+        .check_macro_references(model, profile.get_configuration())?;
+
+    let new_profile_id = profile.get_id().to_string();
     guard
         .get_profile_manager_mut()
         .overwrite_profile(&device_id, &profile_id, profile)?;
@@ -88,12 +141,30 @@ pub(crate) async fn overwrite_profile(
         .map_err(|e| CommandError::StorageManagerError {
             message: e.to_string(),
         })?;
+
+    let device = guard.get_device_manager_mut().get_device_mut(&device_id)?;
+    if *device.get_active_profile() == profile_id {
+        device.set_active_profile(new_profile_id);
+
+        guard
+            .get_device_manager()
+            .to_storage(&guard.get_storage_manager())
+            .map_err(|e| CommandError::StorageManagerError {
+                message: e.to_string(),
+            })?;
+
+        let device = guard.sync_active_profile(&device_id)?;
+        events::emit_device_event(&app_handle, events::DEVICE_SYNC_STATUS_CHANGED, &device);
+    }
     Ok(())
 }
 
+/// Remove a profile from the slots of a device. The profile stays in the library. If it was
+/// the active profile, another profile of the device is activated.
 #[tauri::command]
 #[specta::specta]
 pub(crate) async fn delete_profile(
+    app_handle: AppHandle,
     state: tauri::State<'_, RwLock<ApplicationState>>,
     device_id: DeviceId,
     profile_id: ProfileId,
@@ -101,7 +172,7 @@ pub(crate) async fn delete_profile(
     let mut guard = state.write().await;
     guard
         .get_profile_manager_mut()
-        .delete_profile(&device_id, &profile_id)?;
+        .unassign_profile(&device_id, &profile_id)?;
 
     let active_profile = guard
         .get_device_manager()
@@ -145,6 +216,9 @@ pub(crate) async fn delete_profile(
             .map_err(|e| CommandError::StorageManagerError {
                 message: e.to_string(),
             })?;
+
+        let device = guard.sync_active_profile(&device_id)?;
+        events::emit_device_event(&app_handle, events::DEVICE_SYNC_STATUS_CHANGED, &device);
     }
 
     guard
@@ -156,3 +230,189 @@ pub(crate) async fn delete_profile(
 
     Ok(())
 }
+
+/// Every profile of the library, including profiles not assigned to any device.
+#[tauri::command]
+#[specta::specta]
+pub(crate) async fn get_library_profiles(
+    state: tauri::State<'_, RwLock<ApplicationState>>,
+) -> Result<Vec<Profile>, CommandError> {
+    let guard = state.read().await;
+    let profiles = guard
+        .get_profile_manager()
+        .get_profiles()
+        .into_iter()
+        .cloned()
+        .collect();
+    Ok(profiles)
+}
+
+/// Add a profile to the library without assigning it to a device.
+#[tauri::command]
+#[specta::specta]
+pub(crate) async fn add_library_profile(
+    state: tauri::State<'_, RwLock<ApplicationState>>,
+    profile: Profile,
+) -> Result<(), CommandError> {
+    let mut guard = state.write().await;
+    guard.get_profile_manager_mut().add_profile(profile)?;
+
+    guard
+        .get_profile_manager()
+        .to_storage(&guard.get_storage_manager())
+        .map_err(|e| CommandError::StorageManagerError {
+            message: e.to_string(),
+        })?;
+
+    Ok(())
+}
+
+/// Assign a profile of the library to a slot of a device. The profile must suit the device's
+/// model. If the slot held the active profile, the assigned profile becomes active.
+#[tauri::command]
+#[specta::specta]
+pub(crate) async fn assign_profile(
+    app_handle: AppHandle,
+    state: tauri::State<'_, RwLock<ApplicationState>>,
+    device_id: DeviceId,
+    profile_id: ProfileId,
+    slot: usize,
+) -> Result<(), CommandError> {
+    let mut guard = state.write().await;
+    let model = guard.get_device_manager().get_device(&device_id)?.get_model();
+    let configuration = guard
+        .get_profile_manager()
+        .get_profile(&profile_id)?
+        .get_configuration();
+    configuration.validate(model)?;
+    guard
+        .get_profile_manager()
+        .check_macro_references(model, configuration)?;
+
+    let replaced = guard
+        .get_profile_manager_mut()
+        .assign_profile(&device_id, &profile_id, slot)?;
+    guard
+        .get_profile_manager()
+        .to_storage(&guard.get_storage_manager())
+        .map_err(|e| CommandError::StorageManagerError {
+            message: e.to_string(),
+        })?;
+
+    let device = guard.get_device_manager_mut().get_device_mut(&device_id)?;
+    if replaced.as_ref() == Some(device.get_active_profile()) {
+        device.set_active_profile(profile_id);
+
+        guard
+            .get_device_manager()
+            .to_storage(&guard.get_storage_manager())
+            .map_err(|e| CommandError::StorageManagerError {
+                message: e.to_string(),
+            })?;
+
+        let device = guard.sync_active_profile(&device_id)?;
+        events::emit_device_event(&app_handle, events::DEVICE_SYNC_STATUS_CHANGED, &device);
+    }
+    Ok(())
+}
+
+/// Delete a profile from the library. The profile must not be assigned to any device.
+#[tauri::command]
+#[specta::specta]
+pub(crate) async fn delete_library_profile(
+    state: tauri::State<'_, RwLock<ApplicationState>>,
+    profile_id: ProfileId,
+) -> Result<(), CommandError> {
+    let mut guard = state.write().await;
+    guard.get_profile_manager_mut().delete_profile(&profile_id)?;
+
+    guard
+        .get_profile_manager()
+        .to_storage(&guard.get_storage_manager())
+        .map_err(|e| CommandError::StorageManagerError {
+            message: e.to_string(),
+        })?;
+
+    Ok(())
+}
+
+/// Write a profile of the library to a `.crabby-profile` file, along with the macros it plays
+/// back. The profile is described for the model of the given device.
+#[tauri::command]
+#[specta::specta]
+pub(crate) async fn export_profile(
+    state: tauri::State<'_, RwLock<ApplicationState>>,
+    device_id: DeviceId,
+    profile_id: ProfileId,
+    path: PathBuf,
+) -> Result<(), CommandError> {
+    let guard = state.read().await;
+    let model = guard
+        .get_device_manager()
+        .get_device(&device_id)?
+        .get_model();
+    let profile = guard.get_profile_manager().get_profile(&profile_id)?;
+    profile.get_configuration().validate(model)?;
+
+    let macros = guard
+        .get_profile_manager()
+        .get_configuration_macros(profile.get_configuration());
+    ProfileFile::new(profile, model, &macros).write(&path)?;
+    Ok(())
+}
+
+/// Read a `.crabby-profile` file and add its profile and macros for a device. Every reason the
+/// profile cannot be used on the device is reported. Ids are replaced, so a file can be
+/// imported any number of times.
+#[tauri::command]
+#[specta::specta]
+pub(crate) async fn import_profile(
+    state: tauri::State<'_, RwLock<ApplicationState>>,
+    device_id: DeviceId,
+    path: PathBuf,
+) -> Result<ImportReport, CommandError> {
+    let file = ProfileFile::read(&path)?;
+
+    let mut guard = state.write().await;
+    let model = guard
+        .get_device_manager()
+        .get_device(&device_id)?
+        .get_model()
+        .clone();
+    let limits = MacroLimits::for_model(&model)
+        .ok_or_else(|| ProfileManagerError::UnknownModel(model.to_string()))?;
+    file.check(&model, &limits)?;
+
+    let mut notes = vec![];
+    if *file.get_model() != model {
+        notes.push(format!("The profile was made for a {}", file.get_model()));
+    }
+
+    let (profile, macros) = file.into_profile();
+    let profile_id = profile.get_id().to_string();
+    let name = profile.get_metadata().name.trim().to_string();
+    let slot = guard
+        .get_profile_manager_mut()
+        .import_profile(&device_id, profile, macros, &limits)?;
+
+    guard
+        .get_profile_manager()
+        .to_storage(&guard.get_storage_manager())
+        .map_err(|e| CommandError::StorageManagerError {
+            message: e.to_string(),
+        })?;
+
+    let profile = guard.get_profile_manager().get_profile(&profile_id)?.clone();
+    if profile.get_metadata().name != name {
+        notes.push(format!("Renamed to {}", profile.get_metadata().name));
+    }
+    if slot.is_none() {
+        notes.push("The device has no free slot, the profile was added to the library".to_string());
+    }
+
+    Ok(ImportReport {
+        profile,
+        slot,
+        notes,
+    })
+}
@@ -0,0 +1,78 @@
+use tauri::{async_runtime::RwLock, AppHandle};
+
+use super::CommandError;
+use crate::{
+    device::ConnectionState,
+    events,
+    state::ApplicationState,
+    storage_manager::{BackupInfo, BackupPolicy, RecoveryNotice},
+};
+
+/// The stored files that were corrupt at start up and what could be recovered from them. The
+/// notices are handed out once, the interface asks for them when it has loaded since events
+/// sent during start up would be missed.
+#[tauri::command]
+#[specta::specta]
+pub(crate) async fn take_recovery_notices(
+    state: tauri::State<'_, RwLock<ApplicationState>>,
+) -> Result<Vec<RecoveryNotice>, CommandError> {
+    let guard = state.read().await;
+    Ok(guard.get_storage_manager().take_recovery_notices())
+}
+
+/// The previous versions of the device and profile stores, newest first.
+#[tauri::command]
+#[specta::specta]
+pub(crate) async fn list_backups(
+    state: tauri::State<'_, RwLock<ApplicationState>>,
+) -> Result<Vec<BackupInfo>, CommandError> {
+    let guard = state.read().await;
+    Ok(guard.list_backups()?)
+}
+
+/// Go back to a previous version of a store, as listed by [`list_backups`]. Attached devices
+/// are synced with their restored profiles.
+#[tauri::command]
+#[specta::specta]
+pub(crate) async fn restore_backup(
+    app_handle: AppHandle,
+    state: tauri::State<'_, RwLock<ApplicationState>>,
+    file: String,
+    created_ms: u64,
+) -> Result<(), CommandError> {
+    let mut guard = state.write().await;
+    guard.restore_backup(&file, created_ms)?;
+
+    for device in guard.get_device_manager().get_devices() {
+        if device.get_connection_state() == ConnectionState::Connected {
+            events::emit_device_event(&app_handle, events::DEVICE_SYNC_STATUS_CHANGED, device);
+        }
+    }
+    Ok(())
+}
+
+/// How many previous versions of each store are kept, and for how long.
+#[tauri::command]
+#[specta::specta]
+pub(crate) async fn get_backup_policy(
+    state: tauri::State<'_, RwLock<ApplicationState>>,
+) -> Result<BackupPolicy, CommandError> {
+    let guard = state.read().await;
+    Ok(guard.get_storage_manager().get_backup_policy())
+}
+
+/// Change how many previous versions of each store are kept, from the next write of each.
+#[tauri::command]
+#[specta::specta]
+pub(crate) async fn set_backup_policy(
+    state: tauri::State<'_, RwLock<ApplicationState>>,
+    backup_policy: BackupPolicy,
+) -> Result<(), CommandError> {
+    let mut guard = state.write().await;
+    guard
+        .get_storage_manager_mut()
+        .set_backup_policy(backup_policy)
+        .map_err(|e| CommandError::StorageManagerError {
+            message: e.to_string(),
+        })
+}
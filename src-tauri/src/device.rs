@@ -1,55 +1,69 @@
+use std::fmt;
+
 use serde::{Deserialize, Serialize};
 use specta::Type;
 
+pub mod capabilities;
 pub mod device_manager;
+pub mod discovery;
+pub mod identity;
 pub mod keyboard;
 pub mod mouse;
+pub mod protocol;
+pub mod simulated;
+pub mod sync;
+pub mod transport;
 
+use capabilities::DeviceCapabilities;
+use identity::HardwareIdentity;
 use keyboard::Keyboard;
 use mouse::Mouse;
+use sync::SyncStatus;
 use uuid::Uuid;
 
 use crate::profile::ProfileId;
 
 pub type DeviceId = String;
 
-#[derive(Clone, Debug, Type)]
-#[cfg_attr(test, derive(PartialEq))]
-pub enum DeviceModel {
-    M1,
-    K1,
-}
+/// USB vendor id shared by all crabby peripherals.
+pub const VENDOR_ID: u16 = 0x1209;
 
-impl ToString for DeviceModel {
-    fn to_string(&self) -> String {
-        match self {
-            Self::M1 => "M1",
-            Self::K1 => "K1",
-        }
-        .to_string()
+/// The name of a device model. What a model is and what it supports is described by its
+/// definition, see [`capabilities`]. Models without a definition are kept as they are, so that
+/// devices of unknown models survive in the configuration.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize, Type)]
+#[serde(transparent)]
+pub struct DeviceModel(String);
+
+impl DeviceModel {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self(name.into())
+    }
+
+    pub fn from_usb_id(vendor_id: u16, product_id: u16) -> Option<Self> {
+        DeviceCapabilities::for_usb_id(vendor_id, product_id)
+            .map(|capabilities| capabilities.model.clone())
     }
-}
 
-impl Serialize for DeviceModel {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: serde::Serializer,
-    {
-        serializer.serialize_str(self.to_string().as_str())
+    /// `None` for models without a definition.
+    pub(crate) fn get_capabilities(&self) -> Option<&'static DeviceCapabilities> {
+        DeviceCapabilities::for_model(self)
+    }
+
+    pub fn product_id(&self) -> Option<u16> {
+        self.get_capabilities()
+            .map(|capabilities| capabilities.product_id)
+    }
+
+    pub fn default_device_type(&self) -> Option<DeviceType> {
+        self.get_capabilities()
+            .map(|capabilities| capabilities.default_device_type())
     }
 }
 
-impl<'de> Deserialize<'de> for DeviceModel {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-    where
-        D: serde::Deserializer<'de>,
-    {
-        let s = String::deserialize(deserializer)?;
-        match s.as_str() {
-            "M1" => Ok(DeviceModel::M1),
-            "K1" => Ok(DeviceModel::K1),
-            _ => Err(serde::de::Error::unknown_variant(&s, &["M1", "K1"])),
-        }
+impl fmt::Display for DeviceModel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
     }
 }
 
@@ -60,6 +74,13 @@ pub enum DeviceType {
     Keyboard(Keyboard),
 }
 
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize, Type)]
+pub enum ConnectionState {
+    Connected,
+    #[default]
+    Disconnected,
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize, Type)]
 #[cfg_attr(test, derive(PartialEq))]
 pub struct Device {
@@ -67,6 +88,12 @@ pub struct Device {
     model: DeviceModel,
     device_type: DeviceType,
     active_profile: ProfileId,
+    #[serde(default)]
+    connection_state: ConnectionState,
+    #[serde(default)]
+    hardware_identity: Option<HardwareIdentity>,
+    #[serde(default)]
+    sync_status: SyncStatus,
 }
 
 impl Device {
@@ -90,6 +117,40 @@ impl Device {
         self.active_profile = profile_id;
     }
 
+    pub fn get_connection_state(&self) -> ConnectionState {
+        self.connection_state
+    }
+
+    pub fn set_connection_state(&mut self, connection_state: ConnectionState) {
+        self.connection_state = connection_state;
+    }
+
+    pub fn get_sync_status(&self) -> &SyncStatus {
+        &self.sync_status
+    }
+
+    pub fn set_sync_status(&mut self, sync_status: SyncStatus) {
+        self.sync_status = sync_status;
+    }
+
+    /// `None` for devices registered before ids were derived from the hardware.
+    pub fn get_hardware_identity(&self) -> Option<&HardwareIdentity> {
+        self.hardware_identity.as_ref()
+    }
+
+    /// Replace the identity of the device, deriving a new [`DeviceId`] from it.
+    pub(crate) fn set_hardware_identity(&mut self, identity: HardwareIdentity) {
+        self.id = identity.device_id();
+        self.hardware_identity = Some(identity);
+    }
+
+    /// Go back to an id that was not derived from the hardware, undoing
+    /// [`set_hardware_identity`][Self::set_hardware_identity].
+    pub(crate) fn clear_hardware_identity(&mut self, legacy_id: DeviceId) {
+        self.id = legacy_id;
+        self.hardware_identity = None;
+    }
+
     pub fn builder() -> DeviceBuilder {
         DeviceBuilder::new()
     }
@@ -100,9 +161,12 @@ pub struct DeviceBuilder {
     model: Option<DeviceModel>,
     device_type: Option<DeviceType>,
     acitve_profile: Option<ProfileId>,
+    hardware_identity: Option<HardwareIdentity>,
 }
 
 impl DeviceBuilder {
+    /// The builder starts out with a random [`DeviceId`]. Devices backed by real hardware
+    /// should be given a [`HardwareIdentity`] which replaces it with a stable id.
     fn new() -> Self {
         let id = Uuid::new_v4().to_string();
         Self {
@@ -110,6 +174,7 @@ impl DeviceBuilder {
             model: None,
             device_type: None,
             acitve_profile: None,
+            hardware_identity: None,
         }
     }
     
@@ -122,6 +187,12 @@ impl DeviceBuilder {
         self
     }
 
+    pub fn with_hardware_identity(mut self, identity: HardwareIdentity) -> Self {
+        self.id = identity.device_id();
+        self.hardware_identity = Some(identity);
+        self
+    }
+
     pub fn get_model(&self) -> &Option<DeviceModel> {
         &self.model
     }
@@ -147,6 +218,9 @@ impl DeviceBuilder {
             model: self.model.unwrap(),
             device_type: self.device_type.unwrap(),
             active_profile: self.acitve_profile.unwrap(),
+            connection_state: ConnectionState::default(),
+            hardware_identity: self.hardware_identity,
+            sync_status: SyncStatus::default(),
         }
     }
 }
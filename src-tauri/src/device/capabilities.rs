@@ -0,0 +1,398 @@
+use std::{path::Path, sync::OnceLock};
+
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use thiserror::Error;
+use tracing::{info, instrument, warn};
+
+use super::{keyboard::Keyboard, mouse::Mouse, DeviceModel, DeviceType, VENDOR_ID};
+use crate::{
+    profile::{
+        keyboard_profile::{KeyboardMatrix, KeyboardProfile},
+        macros::MacroLimits,
+        mouse_profile::{MouseLimits, MouseProfile},
+    },
+    storage_manager::{StorageManager, StorageManagerError},
+};
+
+/// Model definitions bundled with the application.
+const BUNDLED_MODELS: [(&str, &str); 2] = [
+    ("m1.json", include_str!("../../models/m1.json")),
+    ("k1.json", include_str!("../../models/k1.json")),
+];
+
+/// The subdirectory of the storage directory holding user supplied model definitions.
+pub(crate) const USER_MODELS_DIR: &str = "models";
+
+static REGISTRY: OnceLock<Vec<DeviceCapabilities>> = OnceLock::new();
+
+static BUNDLED: OnceLock<Vec<DeviceCapabilities>> = OnceLock::new();
+
+#[derive(Debug, Error)]
+pub(crate) enum ModelDefinitionError {
+    #[error("StorageManagerError: {0}")]
+    StorageManagerError(#[from] StorageManagerError),
+
+    #[error("UnsupportedFormat: {0}")]
+    UnsupportedFormat(String),
+
+    #[error("ParseError: {0}")]
+    ParseError(String),
+
+    #[error("Invalid: {0}")]
+    Invalid(String),
+}
+
+/// The kind of device a model is, which decides the profiles it accepts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, Type)]
+pub enum DeviceClass {
+    Mouse,
+    Keyboard,
+}
+
+fn default_vendor_id() -> u16 {
+    VENDOR_ID
+}
+
+/// A device model as described by its definition file: how to recognise it and what it
+/// supports. Profile validation and factory profiles are derived from it, so that a new
+/// model only needs a definition.
+#[derive(Clone, Debug, Deserialize)]
+pub(crate) struct DeviceCapabilities {
+    pub model: DeviceModel,
+    #[serde(default = "default_vendor_id")]
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub class: DeviceClass,
+    pub profile_slots: u8,
+    /// The slot holding the factory profile of a newly registered device.
+    #[serde(default)]
+    pub default_profile_slot: u8,
+    /// Storage available on the device for the active configuration and its macros.
+    pub onboard_memory_bytes: usize,
+    /// Versions of the wire protocol understood by the firmware.
+    pub protocol_versions: Vec<u8>,
+    pub macros: MacroLimits,
+    /// Required for mice.
+    #[serde(default)]
+    pub mouse: Option<MouseLimits>,
+    /// Required for keyboards.
+    #[serde(default)]
+    pub keyboard: Option<KeyboardMatrix>,
+}
+
+impl DeviceCapabilities {
+    /// The definition of a model. `None` for models without a definition, such as models
+    /// found in the configuration of a newer version of the application.
+    pub(crate) fn for_model(model: &DeviceModel) -> Option<&'static Self> {
+        registry()
+            .iter()
+            .find(|capabilities| capabilities.model == *model)
+    }
+
+    /// The definition bundled with the application, whatever user definitions replace it.
+    pub(crate) fn bundled(model: &DeviceModel) -> Option<&'static Self> {
+        bundled_definitions()
+            .iter()
+            .find(|capabilities| capabilities.model == *model)
+    }
+
+    pub(crate) fn for_usb_id(vendor_id: u16, product_id: u16) -> Option<&'static Self> {
+        registry().iter().find(|capabilities| {
+            capabilities.vendor_id == vendor_id && capabilities.product_id == product_id
+        })
+    }
+
+    /// Parse a definition, the format being given by the file extension (`toml` or `json`).
+    pub(crate) fn parse(contents: &str, extension: &str) -> Result<Self, ModelDefinitionError> {
+        let capabilities: Self = match extension {
+            "toml" => toml::from_str(contents)
+                .map_err(|e| ModelDefinitionError::ParseError(e.to_string())),
+            "json" => serde_json::from_str(contents)
+                .map_err(|e| ModelDefinitionError::ParseError(e.to_string())),
+            extension => Err(ModelDefinitionError::UnsupportedFormat(
+                extension.to_string(),
+            )),
+        }?;
+
+        capabilities.check()?;
+        Ok(capabilities)
+    }
+
+    /// Check that the definition is consistent and that its factory profile is valid.
+    fn check(&self) -> Result<(), ModelDefinitionError> {
+        let invalid = |e: &dyn ToString| ModelDefinitionError::Invalid(e.to_string());
+
+        if self.profile_slots == 0 {
+            return Err(invalid(&"A model needs at least one profile slot"));
+        }
+        if self.default_profile_slot >= self.profile_slots {
+            return Err(invalid(&format!(
+                "The default profile slot {} is not one of the {} slots",
+                self.default_profile_slot, self.profile_slots
+            )));
+        }
+
+        match (self.class, &self.mouse, &self.keyboard) {
+            (DeviceClass::Mouse, Some(limits), None) => {
+                check_mouse_limits(limits).map_err(|e| invalid(&e))?;
+                MouseProfile::factory(limits)
+                    .validate(limits, self.profile_slots)
+                    .map_err(|e| invalid(&e))
+            }
+            (DeviceClass::Keyboard, None, Some(matrix)) => KeyboardProfile::factory(matrix)
+                .validate(matrix)
+                .map_err(|e| invalid(&e)),
+            (class, _, _) => Err(invalid(&format!(
+                "A {class:?} needs exactly one {} section",
+                format!("{class:?}").to_lowercase()
+            ))),
+        }
+    }
+
+    pub(crate) fn supports_protocol(&self, version: u8) -> bool {
+        self.protocol_versions.contains(&version)
+    }
+
+    pub(crate) fn default_device_type(&self) -> DeviceType {
+        match self.class {
+            DeviceClass::Mouse => DeviceType::Mouse(Mouse::default()),
+            DeviceClass::Keyboard => DeviceType::Keyboard(Keyboard {}),
+        }
+    }
+}
+
+/// Check the limits profiles are validated against, which must not be empty. The DPI step
+/// divides every DPI.
+fn check_mouse_limits(limits: &MouseLimits) -> Result<(), String> {
+    if limits.dpi_step == 0 {
+        return Err("The DPI step must not be 0".to_string());
+    }
+    let empty = [
+        ("max_dpi_stages", limits.max_dpi_stages == 0),
+        ("dpi", limits.dpi.is_empty()),
+        ("polling_rates", limits.polling_rates.is_empty()),
+        (
+            "lift_off_distance_mm",
+            limits.lift_off_distance_mm.is_empty(),
+        ),
+        ("debounce_ms", limits.debounce_ms.is_empty()),
+    ];
+    match empty.iter().find(|(_, is_empty)| *is_empty) {
+        Some((field, _)) => Err(format!("The {field} limit must not be empty")),
+        None => Ok(()),
+    }
+}
+
+/// Load the bundled model definitions along with the definitions in the [`USER_MODELS_DIR`] of
+/// the storage. User definitions replace bundled definitions of the same model. Invalid user
+/// definitions are skipped. Has no effect once a model has been looked up.
+#[instrument(skip(storage_manager))]
+pub(crate) fn init_registry(storage_manager: &StorageManager) {
+    let definitions = load_definitions(storage_manager);
+    if REGISTRY.set(definitions).is_err() {
+        warn!("The model registry was already initialized. User definitions are ignored.");
+    }
+}
+
+fn registry() -> &'static [DeviceCapabilities] {
+    REGISTRY.get_or_init(|| bundled_definitions().to_vec())
+}
+
+fn bundled_definitions() -> &'static [DeviceCapabilities] {
+    BUNDLED.get_or_init(|| {
+        BUNDLED_MODELS
+            .iter()
+            .map(|(name, contents)| {
+                DeviceCapabilities::parse(contents, "json")
+                    .unwrap_or_else(|e| panic!("Invalid bundled model definition {name}: {e}"))
+            })
+            .collect()
+    })
+}
+
+/// User definitions may replace a bundled definition but not change the class of its model,
+/// which stored profiles of the model depend on.
+fn load_definitions(storage_manager: &StorageManager) -> Vec<DeviceCapabilities> {
+    let mut definitions = bundled_definitions().to_vec();
+
+    for (path, definition) in read_user_definitions(storage_manager) {
+        let definition = match definition {
+            Ok(definition) => definition,
+            Err(e) => {
+                warn!(e=%e, "Skipping the model definition at {path}.");
+                continue;
+            }
+        };
+
+        match definitions
+            .iter_mut()
+            .find(|existing| existing.model == definition.model)
+        {
+            Some(existing) if existing.class != definition.class => {
+                warn!(
+                    "Skipping the model definition at {path}, {} is a {:?}.",
+                    existing.model, existing.class
+                );
+            }
+            Some(existing) => {
+                info!("{path} replaces the definition of {}.", definition.model);
+                *existing = definition;
+            }
+            None => definitions.push(definition),
+        }
+    }
+
+    definitions
+}
+
+fn read_user_definitions(
+    storage_manager: &StorageManager,
+) -> Vec<(String, Result<DeviceCapabilities, ModelDefinitionError>)> {
+    let mut file_names = match storage_manager.list_files(USER_MODELS_DIR) {
+        Ok(file_names) => file_names,
+        Err(e) => {
+            warn!(e=%e, "Could not list the user model definitions.");
+            return vec![];
+        }
+    };
+    file_names.sort();
+
+    file_names
+        .into_iter()
+        .map(|file_name| {
+            let path = format!("{USER_MODELS_DIR}/{file_name}");
+            let extension = Path::new(&file_name)
+                .extension()
+                .map(|extension| extension.to_string_lossy().to_lowercase())
+                .unwrap_or_default();
+            let definition = storage_manager
+                .read_file(&path)
+                .map_err(ModelDefinitionError::from)
+                .and_then(|contents| DeviceCapabilities::parse(&contents, &extension));
+            (path, definition)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        load_definitions, DeviceCapabilities, DeviceClass, ModelDefinitionError, USER_MODELS_DIR,
+    };
+    use crate::{
+        device::{protocol::PROTOCOL_VERSION, DeviceModel},
+        profile::Profile,
+        storage_manager::StorageManager,
+    };
+
+    const M2: &str = r#"
+        model = "M2"
+        product_id = 0x4d32
+        class = "Mouse"
+        profile_slots = 6
+        onboard_memory_bytes = 32768
+        protocol_versions = [1]
+        macros = { max_macros = 8, max_steps = 32 }
+
+        [mouse]
+        max_dpi_stages = 3
+        dpi = { start = 200, end = 16000 }
+        dpi_step = 100
+        default_dpi_stages = [{ x = 800, y = 800, colour = { r = 255, g = 255, b = 255 } }]
+        default_dpi_stage = 0
+        polling_rates = ["Hz500", "Hz1000"]
+        lift_off_distance_mm = { start = 1, end = 3 }
+        debounce_ms = { start = 2, end = 10 }
+        buttons = ["Left", "Right", "Middle", "Dpi"]
+        lighting_zones = ["Logo"]
+    "#;
+
+    #[test]
+    fn every_model_has_a_valid_definition() {
+        for model in [DeviceModel::new("M1"), DeviceModel::new("K1")] {
+            let capabilities = DeviceCapabilities::for_model(&model).unwrap();
+            assert_eq!(capabilities.model, model);
+            assert!(capabilities.supports_protocol(PROTOCOL_VERSION));
+            assert_eq!(
+                DeviceCapabilities::for_usb_id(capabilities.vendor_id, capabilities.product_id)
+                    .unwrap()
+                    .model,
+                model
+            );
+
+            Profile::get_default_provide_configuration(model.clone())
+                .unwrap()
+                .validate(&model)
+                .unwrap();
+        }
+
+        assert!(DeviceCapabilities::for_model(&DeviceModel::new("X9")).is_none());
+    }
+
+    #[test]
+    fn parses_toml_definitions() {
+        let m2 = DeviceCapabilities::parse(M2, "toml").unwrap();
+        assert_eq!(m2.model, DeviceModel::new("M2"));
+        assert_eq!(m2.class, DeviceClass::Mouse);
+        assert_eq!(m2.mouse.unwrap().buttons.len(), 4);
+
+        assert!(matches!(
+            DeviceCapabilities::parse(M2, "yaml"),
+            Err(ModelDefinitionError::UnsupportedFormat(_))
+        ));
+        assert!(matches!(
+            DeviceCapabilities::parse(&M2.replace("\"Mouse\"", "\"Keyboard\""), "toml"),
+            Err(ModelDefinitionError::Invalid(_))
+        ));
+        assert!(matches!(
+            DeviceCapabilities::parse(&M2.replace("default_dpi_stage = 0", ""), "toml"),
+            Err(ModelDefinitionError::ParseError(_))
+        ));
+        assert!(matches!(
+            DeviceCapabilities::parse(&M2.replace("dpi_step = 100", "dpi_step = 0"), "toml"),
+            Err(ModelDefinitionError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn loads_user_definitions() {
+        let sm = StorageManager::in_memory();
+        sm.initailize_storage_directory().unwrap();
+        let m1 = M2.replace("\"M2\"", "\"M1\"");
+        let no_step = M2
+            .replace("\"M2\"", "\"M3\"")
+            .replace("dpi_step = 100", "dpi_step = 0");
+        for (file_name, contents) in [
+            ("m2.toml", M2),
+            ("m1.toml", &m1),
+            ("m3.toml", &no_step),
+            ("k1.toml", &M2.replace("\"M2\"", "\"K1\"")),
+            ("broken.json", "{"),
+        ] {
+            sm.write_file(
+                &format!("{USER_MODELS_DIR}/{file_name}"),
+                contents.as_bytes(),
+            )
+            .unwrap();
+        }
+
+        let definitions = load_definitions(&sm);
+
+        let find = |name: &str| {
+            definitions
+                .iter()
+                .find(|definition| definition.model == DeviceModel::new(name))
+                .unwrap()
+        };
+        // The definition with a DPI step of 0 is skipped rather than failing to divide by it,
+        // and so is the one turning the K1 into a mouse.
+        assert_eq!(definitions.len(), 3);
+        assert!(definitions
+            .iter()
+            .all(|definition| definition.model != DeviceModel::new("M3")));
+        assert_eq!(find("M2").profile_slots, 6);
+        assert_eq!(find("M1").profile_slots, 6);
+        assert_eq!(find("K1").class, DeviceClass::Keyboard);
+    }
+}
@@ -3,11 +3,23 @@ use std::collections::hash_map::HashMap;
 use serde::{Deserialize, Serialize};
 use specta::Type;
 use thiserror::Error;
-use tracing::{error, instrument, warn};
+use tracing::{instrument, warn};
 
-use crate::storage_manager::{StorageManager, StorageManagerError, Store};
+use crate::{
+    profile::{macros::Macro, ProfileConfiguration},
+    storage_manager::{
+        schema::Migration, Recover, Recovered, StorageManager, StorageManagerError, Store,
+    },
+};
 
-use super::{Device, DeviceId};
+use super::{
+    capabilities::DeviceCapabilities,
+    discovery::DiscoveredDevice,
+    identity::HardwareIdentity,
+    sync::{self, SyncError, SyncStatus},
+    transport::{DeviceTransport, TransportError},
+    ConnectionState, Device, DeviceId,
+};
 
 #[derive(Debug, Error, Serialize, Deserialize, Type)]
 pub(crate) enum DeviceManagerError {
@@ -16,18 +28,38 @@ pub(crate) enum DeviceManagerError {
 
     #[error("DeviceAlreadyRegistered: DeviceId {0}")]
     DeviceAlreadyRegistered(DeviceId),
+
+    #[error("TransportNotAttached: DeviceId {0}")]
+    TransportNotAttached(DeviceId),
+
+    #[error("DeviceNotDiscovered: DeviceId {0}")]
+    DeviceNotDiscovered(DeviceId),
+
+    #[error(transparent)]
+    TransportError(#[from] TransportError),
 }
 
-#[derive(Clone, Debug, Default, Deserialize, Serialize)]
-#[cfg_attr(test, derive(PartialEq))]
+/// The `DeviceManager` keeps track of every known [`Device`] and owns the [`DeviceTransport`]
+/// used to communicate with each attached device. It also lists the attached devices that have
+/// not been registered yet. Transports and unregistered devices are runtime only and are never
+/// serialized.
+#[derive(Debug, Default, Deserialize, Serialize)]
 pub(crate) struct DeviceManager {
     devices: HashMap<DeviceId, Device>,
+
+    #[serde(skip)]
+    transports: HashMap<DeviceId, Box<dyn DeviceTransport>>,
+
+    #[serde(skip)]
+    unregistered: HashMap<DeviceId, DiscoveredDevice>,
 }
 
 impl DeviceManager {
     pub(crate) fn new(known_devices: HashMap<DeviceId, Device>) -> Self {
         Self {
             devices: known_devices,
+            transports: HashMap::new(),
+            unregistered: HashMap::new(),
         }
     }
 
@@ -55,6 +87,7 @@ impl DeviceManager {
         device_id: &DeviceId,
         device: Device,
     ) -> Result<(), DeviceManagerError> {
+        self.unregistered.remove(device_id);
         self.devices
             .insert(device_id.to_string(), device)
             .map_or_else(
@@ -67,12 +100,215 @@ impl DeviceManager {
         self.devices
             .remove(device_id)
             .map(|_| ())
-            .ok_or_else(|| DeviceManagerError::DeviceNotFound(device_id.clone()))
+            .ok_or_else(|| DeviceManagerError::DeviceNotFound(device_id.clone()))?;
+
+        if self.transports.contains_key(device_id) {
+            self.detach_transport(device_id)?;
+        }
+        Ok(())
     }
 
     pub(crate) fn replace_devices(&mut self, devices: HashMap<DeviceId, Device>) {
         self.devices = devices;
     }
+
+    /// Replace the known devices with the devices of a restored store. Transports stay attached
+    /// to the devices that are still known, the others are closed.
+    pub(crate) fn restore_devices(&mut self, mut restored: DeviceManager) {
+        restored.reset_runtime_states();
+
+        let forgotten: Vec<DeviceId> = self
+            .transports
+            .keys()
+            .filter(|device_id| !restored.devices.contains_key(*device_id))
+            .cloned()
+            .collect();
+        for device_id in forgotten {
+            if let Err(e) = self.detach_transport(&device_id) {
+                warn!(e=%e, "Could not close the transport of {device_id}.");
+            }
+        }
+
+        for (device_id, device) in restored.devices.iter_mut() {
+            if self.transports.contains_key(device_id) {
+                device.set_connection_state(ConnectionState::Connected);
+            }
+        }
+        self.devices = restored.devices;
+        self.unregistered
+            .retain(|device_id, _| !self.devices.contains_key(device_id));
+    }
+
+    /// Assign a [`HardwareIdentity`] to a known device, moving it (and its transport) to the
+    /// [`DeviceId`] derived from the identity. Returns the new id.
+    pub(crate) fn rekey_device(
+        &mut self,
+        device_id: &DeviceId,
+        identity: HardwareIdentity,
+    ) -> Result<DeviceId, DeviceManagerError> {
+        let new_id = identity.device_id();
+        if new_id != *device_id && self.devices.contains_key(&new_id) {
+            return Err(DeviceManagerError::DeviceAlreadyRegistered(new_id));
+        }
+
+        let mut device = self
+            .devices
+            .remove(device_id)
+            .ok_or_else(|| DeviceManagerError::DeviceNotFound(device_id.to_string()))?;
+        device.set_hardware_identity(identity);
+        self.devices.insert(new_id.clone(), device);
+
+        if let Some(transport) = self.transports.remove(device_id) {
+            self.transports.insert(new_id.clone(), transport);
+        }
+        Ok(new_id)
+    }
+
+    /// Move a re-keyed device back to its legacy id and forget its [`HardwareIdentity`], for
+    /// when the rest of its migration fails.
+    pub(crate) fn restore_legacy_id(
+        &mut self,
+        device_id: &DeviceId,
+        legacy_id: &DeviceId,
+    ) -> Result<(), DeviceManagerError> {
+        if legacy_id != device_id && self.devices.contains_key(legacy_id) {
+            return Err(DeviceManagerError::DeviceAlreadyRegistered(
+                legacy_id.to_string(),
+            ));
+        }
+
+        let mut device = self
+            .devices
+            .remove(device_id)
+            .ok_or_else(|| DeviceManagerError::DeviceNotFound(device_id.to_string()))?;
+        device.clear_hardware_identity(legacy_id.to_string());
+        self.devices.insert(legacy_id.to_string(), device);
+
+        if let Some(transport) = self.transports.remove(device_id) {
+            self.transports.insert(legacy_id.to_string(), transport);
+        }
+        Ok(())
+    }
+
+    /// Open the given transport and associate it with a known [`Device`]. Any transport
+    /// previously attached to the device is closed and dropped.
+    #[instrument(skip(self, transport))]
+    pub(crate) fn attach_transport(
+        &mut self,
+        device_id: &DeviceId,
+        mut transport: Box<dyn DeviceTransport>,
+    ) -> Result<(), DeviceManagerError> {
+        self.get_device(device_id)?;
+        transport.open()?;
+
+        if let Some(mut previous) = self.transports.insert(device_id.to_string(), transport) {
+            if let Err(e) = previous.close() {
+                warn!(e=%e, "Could not close the previously attached transport.");
+            }
+        }
+        Ok(())
+    }
+
+    /// Close and drop the transport associated with a [`Device`].
+    pub(crate) fn detach_transport(&mut self, device_id: &DeviceId) -> Result<(), DeviceManagerError> {
+        let mut transport = self
+            .transports
+            .remove(device_id)
+            .ok_or_else(|| DeviceManagerError::TransportNotAttached(device_id.to_string()))?;
+        transport.close()?;
+        Ok(())
+    }
+
+    /// Mark every device as disconnected and pending synchronization. Connection and sync
+    /// states are only meaningful for the lifetime of the application so they are reset after
+    /// loading from storage.
+    pub(crate) fn reset_runtime_states(&mut self) {
+        self.devices.values_mut().for_each(|device| {
+            device.set_connection_state(ConnectionState::Disconnected);
+            device.set_sync_status(SyncStatus::Pending);
+        });
+    }
+
+    /// Push a configuration and the macros it references to a device through its transport
+    /// and record the outcome as the device's [`SyncStatus`]. A device without a transport is
+    /// left pending, to be synced once it is attached.
+    #[instrument(skip(self, configuration, macros))]
+    pub(crate) fn sync_device(
+        &mut self,
+        device_id: &DeviceId,
+        configuration: &ProfileConfiguration,
+        macros: &[Macro],
+    ) -> Result<SyncStatus, DeviceManagerError> {
+        let model = self.get_device(device_id)?.get_model();
+        let capabilities = DeviceCapabilities::for_model(model)
+            .ok_or_else(|| SyncError::UnknownModel(model.to_string()));
+
+        let status = match self.transports.get_mut(device_id) {
+            None => SyncStatus::Pending,
+            Some(transport) => {
+                let result = capabilities
+                    .and_then(|capabilities| {
+                        sync::check_capabilities(capabilities, configuration, macros)
+                    })
+                    .and_then(|_| {
+                        sync::apply_configuration(transport.as_mut(), configuration, macros)
+                    });
+
+                match result {
+                    Ok(()) => SyncStatus::InSync,
+                    Err(e) => {
+                        warn!(e=%e, "Could not apply configuration to device.");
+                        SyncStatus::Failed(e.to_string())
+                    }
+                }
+            }
+        };
+
+        self.get_device_mut(device_id)?
+            .set_sync_status(status.clone());
+        Ok(status)
+    }
+
+    pub(crate) fn get_unregistered_devices(&self) -> Vec<&DiscoveredDevice> {
+        self.unregistered.values().collect()
+    }
+
+    pub(crate) fn get_unregistered_device(
+        &self,
+        device_id: &DeviceId,
+    ) -> Result<&DiscoveredDevice, DeviceManagerError> {
+        self.unregistered
+            .get(device_id)
+            .ok_or_else(|| DeviceManagerError::DeviceNotDiscovered(device_id.clone()))
+    }
+
+    pub(crate) fn set_unregistered_devices(&mut self, devices: Vec<DiscoveredDevice>) {
+        self.unregistered = devices
+            .into_iter()
+            .map(|discovered| (discovered.device_id(), discovered))
+            .collect();
+    }
+
+    pub(crate) fn has_transport(&self, device_id: &DeviceId) -> bool {
+        self.transports.contains_key(device_id)
+    }
+
+    pub(crate) fn get_transport_mut(
+        &mut self,
+        device_id: &DeviceId,
+    ) -> Result<&mut (dyn DeviceTransport + 'static), DeviceManagerError> {
+        self.transports
+            .get_mut(device_id)
+            .map(|transport| transport.as_mut())
+            .ok_or_else(|| DeviceManagerError::TransportNotAttached(device_id.to_string()))
+    }
+}
+
+#[cfg(test)]
+impl PartialEq for DeviceManager {
+    fn eq(&self, other: &Self) -> bool {
+        self.devices == other.devices
+    }
 }
 
 impl Store for DeviceManager {
@@ -80,25 +316,183 @@ impl Store for DeviceManager {
         "device.json"
     }
 
+    /// Version 2 only wraps the devices in a versioned envelope.
+    fn migrations() -> &'static [Migration] {
+        &[Ok]
+    }
+
     #[instrument(skip_all)]
     fn from_storage(storage_manager: &StorageManager) -> Result<Self, StorageManagerError> {
-        let dm = storage_manager.read_from_storage::<DeviceManager>(Self::storage_path());
-
-        if let Err(StorageManagerError::CorruptionError(_)) = dm {
-            warn!("Corrupted device configuration file. Attempting to delete the file.");
-            storage_manager
-                .delete_from_storage(Self::storage_path())
-                .or_else(|e| {
-                    error!(e=%e, "Encountered a non-derializable file at {}. Unable to delete the file.", Self::storage_path());
-                    Err(e)
-                })?;
-        }
-        dm
+        let dm = storage_manager.read_or_recover::<DeviceManager>(Self::storage_path());
+
+        dm.map(|mut dm| {
+            dm.reset_runtime_states();
+            dm
+        })
     }
 
     #[instrument(skip(storage_manager))]
     fn to_storage(&self, storage_manager: &StorageManager) -> Result<(), StorageManagerError> {
-        let ser = serde_json::to_string(self)?;
-        storage_manager.write_to_storage(Self::storage_path(), ser)
+        storage_manager.write_store(self)
+    }
+}
+
+impl Recover for DeviceManager {
+    fn recover(value: &serde_json::Value) -> Recovered<Self> {
+        let mut devices = HashMap::new();
+        let mut discarded = 0;
+        for (device_id, device) in value["devices"].as_object().into_iter().flatten() {
+            match Device::deserialize(device) {
+                Ok(device) => {
+                    devices.insert(device_id.to_string(), device);
+                }
+                Err(e) => {
+                    warn!(e=%e, "Discarding the unreadable device {device_id}.");
+                    discarded += 1;
+                }
+            }
+        }
+
+        Recovered {
+            recovered: devices.len(),
+            discarded,
+            store: DeviceManager::new(devices),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, time::Duration};
+
+    use super::{DeviceManager, DeviceManagerError};
+    use crate::{
+        device::{
+            mouse::Mouse,
+            protocol::NackReason,
+            simulated::SimulatedDevice,
+            sync::SyncStatus,
+            transport::{loopback::LoopbackTransport, DeviceTransport},
+            Device, DeviceModel, DeviceType,
+        },
+        profile::{mouse_profile::MouseProfile, ProfileConfiguration},
+        storage_manager::schema,
+    };
+
+    fn manager_with_mouse() -> (DeviceManager, String) {
+        let device = Device::builder()
+            .with_model(DeviceModel::new("M1"))
+            .with_device_type(DeviceType::Mouse(Mouse::default()))
+            .with_active_profile("profile".to_string())
+            .build();
+        let id = device.get_id().to_string();
+        (DeviceManager::new(HashMap::from([(id.clone(), device)])), id)
+    }
+
+    #[test]
+    fn can_attach_and_use_transport() {
+        let (mut dm, id) = manager_with_mouse();
+        let loopback = LoopbackTransport::new();
+
+        dm.attach_transport(&id, Box::new(loopback.clone())).unwrap();
+        assert!(dm.has_transport(&id));
+
+        let transport = dm.get_transport_mut(&id).unwrap();
+        transport.write_report(&[1, 2, 3]).unwrap();
+        let report = transport.read_report(Duration::from_millis(10)).unwrap();
+
+        assert_eq!(report.as_ref(), &[1, 2, 3]);
+        assert_eq!(loopback.get_written_reports().len(), 1);
+    }
+
+    #[test]
+    fn cannot_attach_transport_to_unknown_device() {
+        let (mut dm, _) = manager_with_mouse();
+        let res = dm.attach_transport(&"unknown".to_string(), Box::new(LoopbackTransport::new()));
+        assert!(matches!(res, Err(DeviceManagerError::DeviceNotFound(_))));
+    }
+
+    #[test]
+    fn detach_closes_transport() {
+        let (mut dm, id) = manager_with_mouse();
+        let loopback = LoopbackTransport::new();
+
+        dm.attach_transport(&id, Box::new(loopback.clone())).unwrap();
+        assert!(loopback.is_open());
+
+        dm.delete_device(&id).unwrap();
+        assert!(!loopback.is_open());
+        assert!(matches!(
+            dm.get_transport_mut(&id),
+            Err(DeviceManagerError::TransportNotAttached(_))
+        ));
+    }
+
+    #[test]
+    fn sync_device_records_status() {
+        let (mut dm, id) = manager_with_mouse();
+        let configuration = ProfileConfiguration::Mouse(MouseProfile::new(1600));
+
+        let status = dm.sync_device(&id, &configuration, &[]).unwrap();
+        assert_eq!(status, SyncStatus::Pending);
+
+        let simulated = SimulatedDevice::new(DeviceModel::new("M1"), "serial");
+        dm.attach_transport(&id, Box::new(simulated.transport()))
+            .unwrap();
+        dm.sync_device(&id, &configuration, &[]).unwrap();
+        assert_eq!(
+            dm.get_device(&id).unwrap().get_sync_status(),
+            &SyncStatus::InSync
+        );
+        assert_eq!(simulated.get_configuration(), configuration);
+
+        simulated.respond_with_error(NackReason::DeviceError);
+        dm.sync_device(&id, &configuration, &[]).unwrap();
+        assert!(matches!(
+            dm.get_device(&id).unwrap().get_sync_status(),
+            SyncStatus::Failed(_)
+        ));
+    }
+
+    #[test]
+    fn keeps_devices_of_unknown_models() {
+        let device = Device::builder()
+            .with_model(DeviceModel::new("M2"))
+            .with_device_type(DeviceType::Mouse(Mouse::default()))
+            .with_active_profile("profile".to_string())
+            .build();
+        let id = device.get_id().to_string();
+        let dm = DeviceManager::new(HashMap::from([(id.clone(), device)]));
+
+        let json = serde_json::to_string(&dm).unwrap();
+        let mut dm: DeviceManager = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            dm.get_device(&id).unwrap().get_model(),
+            &DeviceModel::new("M2")
+        );
+
+        let simulated = SimulatedDevice::new(DeviceModel::new("M1"), "serial");
+        dm.attach_transport(&id, Box::new(simulated.transport()))
+            .unwrap();
+        let configuration = ProfileConfiguration::Mouse(MouseProfile::new(1600));
+        dm.sync_device(&id, &configuration, &[]).unwrap();
+        assert!(matches!(
+            dm.get_device(&id).unwrap().get_sync_status(),
+            SyncStatus::Failed(_)
+        ));
+    }
+
+    #[test]
+    fn loads_every_stored_format() {
+        let v1: DeviceManager =
+            schema::from_document(include_str!("../../fixtures/storage/device.v1.json")).unwrap();
+        let v2: DeviceManager =
+            schema::from_document(include_str!("../../fixtures/storage/device.v2.json")).unwrap();
+        assert_eq!(v1, v2);
+        assert_eq!(v1.get_devices().len(), 2);
+
+        let reloaded: DeviceManager =
+            schema::from_document(&schema::to_document(&v1).unwrap()).unwrap();
+        assert_eq!(reloaded, v1);
     }
 }
@@ -0,0 +1,358 @@
+use std::{collections::HashMap, fmt::Debug, thread, time::Duration};
+
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use tauri::{async_runtime::RwLock, AppHandle, Manager};
+use tracing::{debug, error, info, instrument, warn};
+
+#[cfg(target_os = "linux")]
+pub mod hidraw;
+
+use super::{
+    device_manager::DeviceManager,
+    identity::HardwareIdentity,
+    transport::{DeviceTransport, TransportError},
+    ConnectionState, Device, DeviceId, DeviceModel,
+};
+use crate::{events, state::ApplicationState};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A supported device found attached to the machine.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize, Type)]
+pub(crate) struct DiscoveredDevice {
+    model: DeviceModel,
+    identity: HardwareIdentity,
+    location: String,
+}
+
+impl DiscoveredDevice {
+    pub(crate) fn new(model: DeviceModel, identity: HardwareIdentity, location: String) -> Self {
+        Self {
+            model,
+            identity,
+            location,
+        }
+    }
+
+    pub(crate) fn get_model(&self) -> &DeviceModel {
+        &self.model
+    }
+
+    pub(crate) fn get_identity(&self) -> &HardwareIdentity {
+        &self.identity
+    }
+
+    /// Where the device is attached, e.g. its hidraw node.
+    pub(crate) fn get_location(&self) -> &str {
+        &self.location
+    }
+
+    pub(crate) fn device_id(&self) -> DeviceId {
+        self.identity.device_id()
+    }
+}
+
+/// A source of attached devices, such as the hidraw subsystem or the simulated backend.
+pub(crate) trait DeviceEnumerator: Debug + Send {
+    fn enumerate(&self) -> Vec<DiscoveredDevice>;
+
+    fn open(&self, device: &DiscoveredDevice) -> Result<Box<dyn DeviceTransport>, TransportError>;
+}
+
+/// The enumerator for real hardware on the current platform.
+#[cfg_attr(debug_assertions, allow(dead_code))]
+pub(crate) fn platform_enumerator() -> Box<dyn DeviceEnumerator> {
+    #[cfg(target_os = "linux")]
+    return Box::new(hidraw::HidrawEnumerator::new());
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        warn!("Hardware discovery is not supported on this platform.");
+        Box::new(super::simulated::SimulatedBackend::new())
+    }
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(test, derive(PartialEq))]
+pub(crate) enum DiscoveryEvent {
+    Connected(Device),
+    Disconnected(Device),
+    /// A supported device that has not been registered yet was plugged in.
+    Discovered(DiscoveredDevice),
+}
+
+impl DiscoveryEvent {
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Self::Connected(_) => events::DEVICE_CONNECTED,
+            Self::Disconnected(_) => events::DEVICE_DISCONNECTED,
+            Self::Discovered(_) => events::DEVICE_DISCOVERED,
+        }
+    }
+
+    pub(crate) fn get_device(&self) -> Option<&Device> {
+        match self {
+            Self::Connected(device) | Self::Disconnected(device) => Some(device),
+            Self::Discovered(_) => None,
+        }
+    }
+
+    fn emit(&self, app_handle: &AppHandle) {
+        let emitted = match self {
+            Self::Connected(device) | Self::Disconnected(device) => {
+                app_handle.emit_all(self.name(), device)
+            }
+            Self::Discovered(discovered) => app_handle.emit_all(self.name(), discovered),
+        };
+
+        if let Err(e) = emitted {
+            error!(e=%e, "Could not emit {} event.", self.name());
+        }
+    }
+}
+
+/// The `DiscoveryService` reconciles the devices reported by a [`DeviceEnumerator`] with the
+/// [`DeviceManager`], attaching transports to registered devices as they are plugged in and
+/// detaching them when they are removed. Attached devices that are not registered are handed
+/// to the `DeviceManager` so that the user can register them.
+#[derive(Debug)]
+pub(crate) struct DiscoveryService {
+    enumerator: Box<dyn DeviceEnumerator>,
+    attached: HashMap<DeviceId, DiscoveredDevice>,
+}
+
+impl DiscoveryService {
+    pub(crate) fn new(enumerator: Box<dyn DeviceEnumerator>) -> Self {
+        Self {
+            enumerator,
+            attached: HashMap::new(),
+        }
+    }
+
+    /// The supported devices currently attached. Enumerating can be slow, so it is kept apart
+    /// from [`reconcile`][Self::reconcile] and needs no access to the application state.
+    pub(crate) fn enumerate(&self) -> Vec<DiscoveredDevice> {
+        self.enumerator.enumerate()
+    }
+
+    #[cfg(test)]
+    pub(crate) fn poll(&mut self, device_manager: &mut DeviceManager) -> Vec<DiscoveryEvent> {
+        let present = self.enumerate();
+        self.reconcile(present, device_manager)
+    }
+
+    /// Bring the [`DeviceManager`] up to date with the devices found by
+    /// [`enumerate`][Self::enumerate].
+    #[instrument(skip_all)]
+    pub(crate) fn reconcile(
+        &mut self,
+        present: Vec<DiscoveredDevice>,
+        device_manager: &mut DeviceManager,
+    ) -> Vec<DiscoveryEvent> {
+        let mut events = vec![];
+
+        // Devices deleted by the user or whose transport was dropped are no longer tracked.
+        self.attached
+            .retain(|device_id, _| device_manager.has_transport(device_id));
+
+        let present = present
+            .into_iter()
+            .map(|discovered| (discovered.device_id(), discovered))
+            .collect::<HashMap<_, _>>();
+
+        let removed = self
+            .attached
+            .keys()
+            .filter(|device_id| !present.contains_key(*device_id))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        for device_id in removed {
+            self.attached.remove(&device_id);
+            if let Err(e) = device_manager.detach_transport(&device_id) {
+                warn!(e=%e, "Could not detach transport of removed device.");
+            }
+
+            if let Ok(device) = device_manager.get_device_mut(&device_id) {
+                device.set_connection_state(ConnectionState::Disconnected);
+                info!(device_id, "Device disconnected.");
+                events.push(DiscoveryEvent::Disconnected(device.clone()));
+            }
+        }
+
+        let mut unregistered = vec![];
+        for (device_id, discovered) in present {
+            if self.attached.contains_key(&device_id) {
+                continue;
+            }
+
+            if device_manager.get_device(&device_id).is_err() {
+                if device_manager.get_unregistered_device(&device_id).is_err() {
+                    debug!(device_id, "Discovered an unregistered device.");
+                    events.push(DiscoveryEvent::Discovered(discovered.clone()));
+                }
+                unregistered.push(discovered);
+                continue;
+            }
+
+            let transport = match self.enumerator.open(&discovered) {
+                Ok(transport) => transport,
+                Err(e) => {
+                    warn!(e=%e, device_id, "Could not open discovered device.");
+                    continue;
+                }
+            };
+
+            if let Err(e) = device_manager.attach_transport(&device_id, transport) {
+                warn!(e=%e, device_id, "Could not attach transport to discovered device.");
+                continue;
+            }
+
+            let device = device_manager.get_device_mut(&device_id).unwrap();
+            device.set_connection_state(ConnectionState::Connected);
+            info!(device_id, "Device connected.");
+            events.push(DiscoveryEvent::Connected(device.clone()));
+            self.attached.insert(device_id, discovered);
+        }
+
+        device_manager.set_unregistered_devices(unregistered);
+        events
+    }
+}
+
+/// Run the [`DiscoveryService`] on a background thread, emitting an event to the frontend
+/// whenever a device is connected or disconnected. Reconnected devices are brought back in
+/// sync with their active profile. The application state is only locked to apply what was
+/// found, and released between the syncs of each device, so commands are not held up by
+/// enumeration or by slow devices.
+pub(crate) fn spawn(app_handle: AppHandle, mut service: DiscoveryService) {
+    let spawned = thread::Builder::new()
+        .name("device-discovery".to_string())
+        .spawn(move || loop {
+            let state = app_handle.state::<RwLock<ApplicationState>>();
+            let present = service.enumerate();
+            let events = tauri::async_runtime::block_on(async {
+                let mut guard = state.write().await;
+                if let Err(e) = guard.migrate_legacy_devices(&present) {
+                    warn!(e=%e, "Could not persist migrated legacy devices.");
+                }
+                service.reconcile(present, guard.get_device_manager_mut())
+            });
+
+            for event in &events {
+                event.emit(&app_handle);
+            }
+
+            for device_id in events.iter().filter_map(|event| match event {
+                DiscoveryEvent::Connected(device) => Some(device.get_id()),
+                _ => None,
+            }) {
+                let synced = tauri::async_runtime::block_on(async {
+                    state.write().await.sync_active_profile(device_id)
+                });
+                match synced {
+                    Ok(device) => events::emit_device_event(
+                        &app_handle,
+                        events::DEVICE_SYNC_STATUS_CHANGED,
+                        &device,
+                    ),
+                    Err(e) => warn!(e=%e, device_id, "Could not sync device."),
+                }
+            }
+
+            thread::sleep(POLL_INTERVAL);
+        });
+
+    if let Err(e) = spawned {
+        error!(e=%e, "Could not start the device discovery service.");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{DiscoveryEvent, DiscoveryService};
+    use crate::device::{
+        device_manager::DeviceManager,
+        simulated::{SimulatedBackend, SimulatedDevice},
+        ConnectionState, Device, DeviceId, DeviceModel,
+    };
+
+    fn register(device_manager: &mut DeviceManager, simulated: &SimulatedDevice) -> DeviceId {
+        let model = simulated.get_model();
+        let device = Device::builder()
+            .with_hardware_identity(simulated.get_identity())
+            .with_device_type(model.default_device_type().unwrap())
+            .with_model(model)
+            .with_active_profile("profile".to_string())
+            .build();
+        let id = device.get_id().to_string();
+        device_manager.insert_device(&id, device).unwrap();
+        id
+    }
+
+    #[test]
+    fn tracks_connect_and_disconnect() {
+        let mut backend = SimulatedBackend::new();
+        let mouse = backend.add_device(SimulatedDevice::new(DeviceModel::new("M1"), "mouse"));
+        let mut device_manager = DeviceManager::new(HashMap::new());
+        let id = register(&mut device_manager, &mouse);
+
+        let mut service = DiscoveryService::new(Box::new(backend));
+
+        let events = service.poll(&mut device_manager);
+        assert!(matches!(events.as_slice(), [DiscoveryEvent::Connected(_)]));
+        assert!(device_manager.has_transport(&id));
+        assert_eq!(
+            device_manager
+                .get_device(&id)
+                .unwrap()
+                .get_connection_state(),
+            ConnectionState::Connected
+        );
+
+        // Nothing changed
+        assert!(service.poll(&mut device_manager).is_empty());
+
+        mouse.disconnect();
+        let events = service.poll(&mut device_manager);
+        assert!(matches!(
+            events.as_slice(),
+            [DiscoveryEvent::Disconnected(_)]
+        ));
+        assert!(!device_manager.has_transport(&id));
+        assert_eq!(
+            device_manager
+                .get_device(&id)
+                .unwrap()
+                .get_connection_state(),
+            ConnectionState::Disconnected
+        );
+
+        mouse.connect();
+        let events = service.poll(&mut device_manager);
+        assert!(matches!(events.as_slice(), [DiscoveryEvent::Connected(_)]));
+    }
+
+    #[test]
+    fn reports_unregistered_devices() {
+        let mut backend = SimulatedBackend::new();
+        let keyboard = backend.add_device(SimulatedDevice::new(DeviceModel::new("K1"), "keyboard"));
+        let mut device_manager = DeviceManager::new(HashMap::new());
+        let mut service = DiscoveryService::new(Box::new(backend));
+
+        let events = service.poll(&mut device_manager);
+        assert!(matches!(events.as_slice(), [DiscoveryEvent::Discovered(_)]));
+        assert_eq!(device_manager.get_unregistered_devices().len(), 1);
+
+        // Only reported once
+        assert!(service.poll(&mut device_manager).is_empty());
+
+        register(&mut device_manager, &keyboard);
+        let events = service.poll(&mut device_manager);
+        assert!(matches!(events.as_slice(), [DiscoveryEvent::Connected(_)]));
+        assert!(device_manager.get_unregistered_devices().is_empty());
+    }
+}
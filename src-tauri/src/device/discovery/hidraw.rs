@@ -0,0 +1,164 @@
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use tracing::{instrument, warn};
+
+use super::{DeviceEnumerator, DiscoveredDevice};
+use crate::device::{
+    identity::HardwareIdentity,
+    transport::{hidraw::HidrawTransport, DeviceTransport, TransportError},
+    DeviceModel,
+};
+
+const SYSFS_HIDRAW_DIR: &str = "/sys/class/hidraw";
+const DEV_DIR: &str = "/dev";
+
+/// Enumerates supported devices by walking the hidraw class in sysfs.
+#[derive(Debug)]
+pub(crate) struct HidrawEnumerator {
+    sysfs_dir: PathBuf,
+    dev_dir: PathBuf,
+}
+
+impl HidrawEnumerator {
+    pub(crate) fn new() -> Self {
+        Self::with_dirs(SYSFS_HIDRAW_DIR, DEV_DIR)
+    }
+
+    pub(crate) fn with_dirs(sysfs_dir: impl AsRef<Path>, dev_dir: impl AsRef<Path>) -> Self {
+        Self {
+            sysfs_dir: sysfs_dir.as_ref().to_path_buf(),
+            dev_dir: dev_dir.as_ref().to_path_buf(),
+        }
+    }
+}
+
+impl Default for HidrawEnumerator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct HidInfo {
+    vendor_id: u16,
+    product_id: u16,
+    serial: Option<String>,
+    physical_path: Option<String>,
+}
+
+/// Parse the `uevent` file of a hidraw parent device. The `HID_ID` entry has the form
+/// `BUS:VENDOR:PRODUCT` with each field in hexadecimal.
+fn parse_uevent(contents: &str) -> Option<HidInfo> {
+    let mut ids = None;
+    let mut serial = None;
+    let mut physical_path = None;
+
+    for line in contents.lines() {
+        match line.split_once('=') {
+            Some(("HID_ID", value)) => {
+                let mut parts = value.split(':').skip(1);
+                let vendor_id = u32::from_str_radix(parts.next()?, 16).ok()?;
+                let product_id = u32::from_str_radix(parts.next()?, 16).ok()?;
+                ids = Some((vendor_id as u16, product_id as u16));
+            }
+            Some(("HID_UNIQ", value)) if !value.is_empty() => serial = Some(value.to_string()),
+            Some(("HID_PHYS", value)) if !value.is_empty() => {
+                physical_path = Some(value.to_string())
+            }
+            _ => {}
+        }
+    }
+
+    ids.map(|(vendor_id, product_id)| HidInfo {
+        vendor_id,
+        product_id,
+        serial,
+        physical_path,
+    })
+}
+
+impl DeviceEnumerator for HidrawEnumerator {
+    #[instrument(skip(self))]
+    fn enumerate(&self) -> Vec<DiscoveredDevice> {
+        let entries = match fs::read_dir(&self.sysfs_dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                warn!(e=%e, "Could not read {:?}.", self.sysfs_dir);
+                return vec![];
+            }
+        };
+
+        let mut nodes = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .collect::<Vec<_>>();
+        nodes.sort();
+
+        // A device exposing several HID interfaces has one node per interface. The firmware
+        // listens for configuration reports on its first interface.
+        let mut seen = HashSet::new();
+        nodes
+            .into_iter()
+            .filter_map(|node| {
+                let uevent = fs::read_to_string(self.sysfs_dir.join(&node).join("device/uevent"));
+                let info = parse_uevent(&uevent.ok()?)?;
+                let model = DeviceModel::from_usb_id(info.vendor_id, info.product_id)?;
+
+                // The physical path without its interface suffix identifies the USB port,
+                // which is the best fingerprint available for devices without a serial.
+                let port = info
+                    .physical_path
+                    .as_deref()
+                    .map(|phys| phys.rsplit_once('/').map_or(phys, |(port, _)| port))
+                    .unwrap_or(&node)
+                    .to_string();
+
+                let identity =
+                    HardwareIdentity::new(info.vendor_id, info.product_id, info.serial, &port);
+                if !seen.insert(identity.device_id()) {
+                    return None;
+                }
+
+                Some(DiscoveredDevice::new(
+                    model,
+                    identity,
+                    self.dev_dir.join(&node).to_string_lossy().to_string(),
+                ))
+            })
+            .collect()
+    }
+
+    fn open(&self, device: &DiscoveredDevice) -> Result<Box<dyn DeviceTransport>, TransportError> {
+        Ok(Box::new(HidrawTransport::new(device.get_location())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_uevent;
+
+    #[test]
+    fn can_parse_uevent() {
+        let info = parse_uevent(
+            "DRIVER=hid-generic\nHID_ID=0003:00001209:00004D31\nHID_NAME=crabby M1\n\
+             HID_PHYS=usb-0000:00:14.0-2/input0\nHID_UNIQ=M1-000042\n",
+        )
+        .unwrap();
+
+        assert_eq!(info.vendor_id, 0x1209);
+        assert_eq!(info.product_id, 0x4d31);
+        assert_eq!(info.serial.as_deref(), Some("M1-000042"));
+        assert_eq!(
+            info.physical_path.as_deref(),
+            Some("usb-0000:00:14.0-2/input0")
+        );
+
+        let info = parse_uevent("HID_ID=0003:00001209:00004B31\nHID_UNIQ=\n").unwrap();
+        assert!(info.serial.is_none());
+
+        assert!(parse_uevent("DRIVER=hid-generic\n").is_none());
+    }
+}
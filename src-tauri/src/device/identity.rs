@@ -0,0 +1,290 @@
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use tracing::{error, info, instrument, warn};
+
+use super::{device_manager::DeviceManager, discovery::DiscoveredDevice, DeviceId};
+use crate::profile::profile_manager::{ProfileManager, ProfileManagerError};
+
+/// The identity of a physical device as reported by the hardware. A [`DeviceId`] derived from
+/// it stays the same across reboots and USB ports, so profiles remain attached to the device.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize, Type)]
+pub struct HardwareIdentity {
+    vendor_id: u16,
+    product_id: u16,
+    serial: Option<String>,
+    fingerprint: Option<String>,
+}
+
+impl HardwareIdentity {
+    /// Create an identity for a device. Devices without a serial number are identified by a
+    /// fingerprint of `fallback_source` instead, which should describe where the device is
+    /// attached (e.g. its physical port) as closely as possible.
+    pub fn new(
+        vendor_id: u16,
+        product_id: u16,
+        serial: Option<String>,
+        fallback_source: &str,
+    ) -> Self {
+        let serial = serial.filter(|serial| !serial.trim().is_empty());
+        let fingerprint = match serial {
+            Some(_) => None,
+            None => Some(format!("{:016x}", fnv1a(fallback_source.as_bytes()))),
+        };
+
+        Self {
+            vendor_id,
+            product_id,
+            serial,
+            fingerprint,
+        }
+    }
+
+    pub fn get_vendor_id(&self) -> u16 {
+        self.vendor_id
+    }
+
+    pub fn get_product_id(&self) -> u16 {
+        self.product_id
+    }
+
+    pub fn get_serial(&self) -> Option<&str> {
+        self.serial.as_deref()
+    }
+
+    /// `vvvv:pppp:SERIAL` for devices with a serial number and `vvvv:pppp:fp-FINGERPRINT`
+    /// otherwise.
+    pub fn device_id(&self) -> DeviceId {
+        match (&self.serial, &self.fingerprint) {
+            (Some(serial), _) => format!("{:04x}:{:04x}:{serial}", self.vendor_id, self.product_id),
+            (None, fingerprint) => format!(
+                "{:04x}:{:04x}:fp-{}",
+                self.vendor_id,
+                self.product_id,
+                fingerprint.as_deref().unwrap_or_default()
+            ),
+        }
+    }
+}
+
+/// 64-bit FNV-1a. Used instead of the standard library hasher, whose output is not guaranteed
+/// to be stable between releases.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Re-key devices registered before ids were derived from their [`HardwareIdentity`]. A legacy
+/// device is matched to a discovered device of the same model when its id is the discovered
+/// serial number or when it is the only legacy device of that model. Returns the `(old, new)`
+/// id of every migrated device.
+#[instrument(skip_all)]
+pub(crate) fn migrate_legacy_devices(
+    discovered: &[DiscoveredDevice],
+    device_manager: &mut DeviceManager,
+    profile_manager: &mut ProfileManager,
+) -> Vec<(DeviceId, DeviceId)> {
+    let mut migrated = vec![];
+
+    for discovered in discovered {
+        let identity = discovered.get_identity();
+        if device_manager.get_device(&identity.device_id()).is_ok() {
+            continue;
+        }
+
+        let candidates = device_manager
+            .get_devices()
+            .into_iter()
+            .filter(|device| {
+                device.get_hardware_identity().is_none()
+                    && device.get_model() == discovered.get_model()
+            })
+            .map(|device| device.get_id().to_string())
+            .collect::<Vec<_>>();
+
+        let legacy_id = candidates
+            .iter()
+            .find(|id| Some(id.as_str()) == identity.get_serial())
+            .or(if candidates.len() == 1 {
+                candidates.first()
+            } else {
+                None
+            });
+
+        let Some(legacy_id) = legacy_id else {
+            if candidates.len() > 1 {
+                warn!(
+                    "Found {} legacy {} devices. Unable to determine which one to migrate.",
+                    candidates.len(),
+                    discovered.get_model().to_string()
+                );
+            }
+            continue;
+        };
+
+        let new_id = match device_manager.rekey_device(legacy_id, identity.clone()) {
+            Ok(new_id) => new_id,
+            Err(e) => {
+                warn!(e=%e, "Could not migrate legacy device {legacy_id}.");
+                continue;
+            }
+        };
+
+        match profile_manager.rekey_device(legacy_id, &new_id) {
+            Ok(()) => {}
+            Err(ProfileManagerError::UnknownDevice(_)) => {
+                warn!("Legacy device {legacy_id} had no profiles to migrate.");
+            }
+            Err(e) => {
+                // The profiles stay under the legacy id, so the device has to as well.
+                warn!(e=%e, "Could not migrate the profiles of legacy device {legacy_id}.");
+                if let Err(e) = device_manager.restore_legacy_id(&new_id, legacy_id) {
+                    error!(e=%e, "Could not roll back the migration of {legacy_id}.");
+                }
+                continue;
+            }
+        }
+
+        info!("Migrated legacy device {legacy_id} to {new_id}.");
+        migrated.push((legacy_id.to_string(), new_id));
+    }
+
+    migrated
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{migrate_legacy_devices, HardwareIdentity};
+    use crate::{
+        device::{
+            device_manager::DeviceManager, discovery::DiscoveredDevice, Device, DeviceModel,
+            VENDOR_ID,
+        },
+        profile::profile_manager::ProfileManager,
+    };
+
+    fn discovered(serial: &str) -> DiscoveredDevice {
+        DiscoveredDevice::new(
+            DeviceModel::new("M1"),
+            HardwareIdentity::new(
+                VENDOR_ID,
+                DeviceModel::new("M1").product_id().unwrap(),
+                Some(serial.to_string()),
+                "usb-1",
+            ),
+            "/dev/hidraw0".to_string(),
+        )
+    }
+
+    fn register_legacy(
+        device_manager: &mut DeviceManager,
+        profile_manager: &mut ProfileManager,
+        id: &str,
+    ) {
+        let id = id.to_string();
+        let profile_id = profile_manager
+            .register_device(&id, DeviceModel::new("M1"))
+            .unwrap();
+        let device = Device::builder()
+            .with_id(id.clone())
+            .with_model(DeviceModel::new("M1"))
+            .with_device_type(DeviceModel::new("M1").default_device_type().unwrap())
+            .with_active_profile(profile_id)
+            .build();
+        device_manager.insert_device(&id, device).unwrap();
+    }
+
+    #[test]
+    fn derives_stable_ids() {
+        let with_serial = HardwareIdentity::new(0x1209, 0x4d31, Some("ABC".to_string()), "usb-1");
+        let moved = HardwareIdentity::new(0x1209, 0x4d31, Some("ABC".to_string()), "usb-2");
+        assert_eq!(with_serial.device_id(), "1209:4d31:ABC");
+        assert_eq!(with_serial.device_id(), moved.device_id());
+
+        let without_serial = HardwareIdentity::new(0x1209, 0x4d31, Some(" ".to_string()), "usb-1");
+        let same_port = HardwareIdentity::new(0x1209, 0x4d31, None, "usb-1");
+        let other_port = HardwareIdentity::new(0x1209, 0x4d31, None, "usb-2");
+        assert!(without_serial.device_id().starts_with("1209:4d31:fp-"));
+        assert_eq!(without_serial.device_id(), same_port.device_id());
+        assert_ne!(without_serial.device_id(), other_port.device_id());
+    }
+
+    #[test]
+    fn migrates_single_legacy_device() {
+        let mut device_manager = DeviceManager::new(HashMap::new());
+        let mut profile_manager = ProfileManager::default();
+        register_legacy(&mut device_manager, &mut profile_manager, "legacy");
+
+        let migrated = migrate_legacy_devices(
+            &[discovered("ABC")],
+            &mut device_manager,
+            &mut profile_manager,
+        );
+
+        let new_id = "1209:4d31:ABC".to_string();
+        assert_eq!(migrated, vec![("legacy".to_string(), new_id.clone())]);
+        assert!(device_manager.get_device(&"legacy".to_string()).is_err());
+
+        let device = device_manager.get_device(&new_id).unwrap();
+        assert_eq!(device.get_id(), &new_id);
+        assert!(device.get_hardware_identity().is_some());
+
+        let profile_ids = profile_manager.get_device_profile_ids(&new_id).unwrap();
+        assert_eq!(
+            profile_ids.get_slots()[0].as_ref(),
+            Some(device.get_active_profile())
+        );
+    }
+
+    #[test]
+    fn skips_ambiguous_legacy_devices() {
+        let mut device_manager = DeviceManager::new(HashMap::new());
+        let mut profile_manager = ProfileManager::default();
+        register_legacy(&mut device_manager, &mut profile_manager, "legacy-one");
+        register_legacy(&mut device_manager, &mut profile_manager, "legacy-two");
+        register_legacy(&mut device_manager, &mut profile_manager, "SERIAL");
+
+        // Only the device registered under its serial number can be matched.
+        let migrated = migrate_legacy_devices(
+            &[discovered("SERIAL"), discovered("OTHER")],
+            &mut device_manager,
+            &mut profile_manager,
+        );
+
+        assert_eq!(
+            migrated,
+            vec![("SERIAL".to_string(), "1209:4d31:SERIAL".to_string())]
+        );
+        assert!(device_manager.get_device(&"legacy-one".to_string()).is_ok());
+        assert!(device_manager.get_device(&"legacy-two".to_string()).is_ok());
+    }
+
+    #[test]
+    fn rolls_back_when_profiles_cannot_be_migrated() {
+        let mut device_manager = DeviceManager::new(HashMap::new());
+        let mut profile_manager = ProfileManager::default();
+        register_legacy(&mut device_manager, &mut profile_manager, "legacy");
+        // Profiles left behind under the new id by an earlier registration.
+        profile_manager
+            .register_device(&"1209:4d31:ABC".to_string(), DeviceModel::new("M1"))
+            .unwrap();
+
+        let migrated = migrate_legacy_devices(
+            &[discovered("ABC")],
+            &mut device_manager,
+            &mut profile_manager,
+        );
+
+        assert!(migrated.is_empty());
+        let device = device_manager.get_device(&"legacy".to_string()).unwrap();
+        assert!(device.get_hardware_identity().is_none());
+        assert!(device_manager
+            .get_device(&"1209:4d31:ABC".to_string())
+            .is_err());
+        assert!(profile_manager
+            .get_device_profile_ids(&"legacy".to_string())
+            .is_ok());
+    }
+}
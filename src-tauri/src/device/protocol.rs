@@ -0,0 +1,427 @@
+//! Wire format shared between the application and the M1/K1 firmware.
+//!
+//! Every report exchanged with a device is a single frame laid out as follows. All multi-byte
+//! integers are little endian.
+//!
+//! | Offset        | Size | Field                                         |
+//! |---------------|------|-----------------------------------------------|
+//! | 0             | 1    | Protocol version ([`PROTOCOL_VERSION`])       |
+//! | 1             | 1    | [`Command`] byte                              |
+//! | 2             | 2    | Payload length `n` (at most [`MAX_PAYLOAD_LEN`]) |
+//! | 4             | n    | Payload                                       |
+//! | 4 + n         | 4    | CRC-32 (IEEE) of bytes `0..4 + n`             |
+//!
+//! Bytes following the checksum are padding added by the HID layer and are ignored.
+
+use bytes::{BufMut, Bytes, BytesMut};
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use thiserror::Error;
+
+pub mod configuration;
+pub mod lighting;
+pub mod macros;
+
+pub(crate) const PROTOCOL_VERSION: u8 = 1;
+
+pub(crate) const HEADER_LEN: usize = 4;
+pub(crate) const CHECKSUM_LEN: usize = 4;
+pub(crate) const MAX_FRAME_LEN: usize = 4096;
+pub(crate) const MAX_PAYLOAD_LEN: usize = MAX_FRAME_LEN - HEADER_LEN - CHECKSUM_LEN;
+
+#[derive(Debug, Error, Serialize, Deserialize, Type)]
+#[cfg_attr(test, derive(PartialEq))]
+pub(crate) enum ProtocolError {
+    #[error("Truncated: Expected {expected} bytes but received {actual}")]
+    Truncated { expected: usize, actual: usize },
+
+    #[error("Oversized: {size} bytes exceeds the maximum of {max} bytes")]
+    Oversized { size: usize, max: usize },
+
+    #[error("ChecksumMismatch: Expected {expected:#010x} but computed {actual:#010x}")]
+    ChecksumMismatch { expected: u32, actual: u32 },
+
+    #[error("UnsupportedVersion: {0}")]
+    UnsupportedVersion(u8),
+
+    #[error("UnknownCommand: {0:#04x}")]
+    UnknownCommand(u8),
+
+    #[error("UnexpectedCommand: {0:?}")]
+    UnexpectedCommand(Command),
+
+    #[error("InvalidPayload: {0}")]
+    InvalidPayload(String),
+
+    #[error("TooLarge: {field} is {value}, a report holds at most {max}")]
+    TooLarge {
+        field: String,
+        value: usize,
+        max: usize,
+    },
+}
+
+/// A count or index sent as a single byte. Values that do not fit are refused rather than
+/// wrapped, which would describe a different configuration to the device.
+pub(crate) fn byte_count(field: &str, value: usize) -> Result<u8, ProtocolError> {
+    u8::try_from(value).map_err(|_| ProtocolError::TooLarge {
+        field: field.to_string(),
+        value,
+        max: u8::MAX as usize,
+    })
+}
+
+/// Commands sent by the host have the high bit clear, responses from the device have it set.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[repr(u8)]
+pub(crate) enum Command {
+    GetConfiguration = 0x01,
+    SetConfiguration = 0x02,
+    SetMacro = 0x03,
+    /// Remove every macro stored by the device.
+    ClearMacros = 0x04,
+    Configuration = 0x81,
+    Ack = 0x82,
+    Nack = 0x83,
+}
+
+/// The single byte payload of a [`Command::Nack`] response.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[repr(u8)]
+pub(crate) enum NackReason {
+    MalformedReport = 0x01,
+    UnsupportedCommand = 0x02,
+    InvalidConfiguration = 0x03,
+    DeviceError = 0x04,
+}
+
+impl TryFrom<u8> for NackReason {
+    type Error = ProtocolError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x01 => Ok(Self::MalformedReport),
+            0x02 => Ok(Self::UnsupportedCommand),
+            0x03 => Ok(Self::InvalidConfiguration),
+            0x04 => Ok(Self::DeviceError),
+            _ => Err(ProtocolError::InvalidPayload(format!(
+                "Unknown nack reason {value:#04x}"
+            ))),
+        }
+    }
+}
+
+impl TryFrom<u8> for Command {
+    type Error = ProtocolError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x01 => Ok(Self::GetConfiguration),
+            0x02 => Ok(Self::SetConfiguration),
+            0x03 => Ok(Self::SetMacro),
+            0x04 => Ok(Self::ClearMacros),
+            0x81 => Ok(Self::Configuration),
+            0x82 => Ok(Self::Ack),
+            0x83 => Ok(Self::Nack),
+            _ => Err(ProtocolError::UnknownCommand(value)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Report {
+    command: Command,
+    payload: Bytes,
+}
+
+impl Report {
+    pub(crate) fn new(command: Command, payload: impl Into<Bytes>) -> Self {
+        Self {
+            command,
+            payload: payload.into(),
+        }
+    }
+
+    pub(crate) fn empty(command: Command) -> Self {
+        Self::new(command, Bytes::new())
+    }
+
+    pub(crate) fn nack(reason: NackReason) -> Self {
+        Self::new(Command::Nack, vec![reason as u8])
+    }
+
+    pub(crate) fn get_command(&self) -> Command {
+        self.command
+    }
+
+    pub(crate) fn get_payload(&self) -> &Bytes {
+        &self.payload
+    }
+
+    /// Serialize the report into a single frame.
+    pub(crate) fn encode(&self) -> Result<Bytes, ProtocolError> {
+        if self.payload.len() > MAX_PAYLOAD_LEN {
+            return Err(ProtocolError::Oversized {
+                size: self.payload.len(),
+                max: MAX_PAYLOAD_LEN,
+            });
+        }
+
+        let mut frame = BytesMut::with_capacity(HEADER_LEN + self.payload.len() + CHECKSUM_LEN);
+        frame.put_u8(PROTOCOL_VERSION);
+        frame.put_u8(self.command as u8);
+        frame.put_u16_le(self.payload.len() as u16);
+        frame.put_slice(&self.payload);
+        frame.put_u32_le(crc32fast::hash(&frame));
+        Ok(frame.freeze())
+    }
+
+    /// Parse a single frame, validating its version, length and checksum.
+    pub(crate) fn decode(frame: &[u8]) -> Result<Self, ProtocolError> {
+        if frame.len() > MAX_FRAME_LEN {
+            return Err(ProtocolError::Oversized {
+                size: frame.len(),
+                max: MAX_FRAME_LEN,
+            });
+        }
+
+        if frame.len() < HEADER_LEN + CHECKSUM_LEN {
+            return Err(ProtocolError::Truncated {
+                expected: HEADER_LEN + CHECKSUM_LEN,
+                actual: frame.len(),
+            });
+        }
+
+        if frame[0] != PROTOCOL_VERSION {
+            return Err(ProtocolError::UnsupportedVersion(frame[0]));
+        }
+
+        let payload_len = u16::from_le_bytes([frame[2], frame[3]]) as usize;
+        if payload_len > MAX_PAYLOAD_LEN {
+            return Err(ProtocolError::Oversized {
+                size: payload_len,
+                max: MAX_PAYLOAD_LEN,
+            });
+        }
+
+        let body_len = HEADER_LEN + payload_len;
+        if frame.len() < body_len + CHECKSUM_LEN {
+            return Err(ProtocolError::Truncated {
+                expected: body_len + CHECKSUM_LEN,
+                actual: frame.len(),
+            });
+        }
+
+        let checksum = &frame[body_len..body_len + CHECKSUM_LEN];
+        let expected = u32::from_le_bytes([checksum[0], checksum[1], checksum[2], checksum[3]]);
+        let actual = crc32fast::hash(&frame[..body_len]);
+        if expected != actual {
+            return Err(ProtocolError::ChecksumMismatch { expected, actual });
+        }
+
+        Ok(Self {
+            command: Command::try_from(frame[1])?,
+            payload: Bytes::copy_from_slice(&frame[HEADER_LEN..body_len]),
+        })
+    }
+}
+
+/// A bounds checked cursor over a report payload.
+pub(crate) struct PayloadReader<'a> {
+    payload: &'a [u8],
+    position: usize,
+}
+
+impl<'a> PayloadReader<'a> {
+    pub(crate) fn new(payload: &'a [u8]) -> Self {
+        Self {
+            payload,
+            position: 0,
+        }
+    }
+
+    pub(crate) fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], ProtocolError> {
+        let end = self.position + len;
+        if end > self.payload.len() {
+            return Err(ProtocolError::Truncated {
+                expected: end,
+                actual: self.payload.len(),
+            });
+        }
+        let bytes = &self.payload[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    pub(crate) fn read_u8(&mut self) -> Result<u8, ProtocolError> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    pub(crate) fn read_u16(&mut self) -> Result<u16, ProtocolError> {
+        let bytes = self.read_bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub(crate) fn read_bool(&mut self) -> Result<bool, ProtocolError> {
+        match self.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            v => Err(ProtocolError::InvalidPayload(format!(
+                "Expected a boolean but found {v}"
+            ))),
+        }
+    }
+
+    /// Fail if any bytes have not been consumed.
+    pub(crate) fn finish(self) -> Result<(), ProtocolError> {
+        if self.position != self.payload.len() {
+            return Err(ProtocolError::InvalidPayload(format!(
+                "{} trailing bytes",
+                self.payload.len() - self.position
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::{Command, ProtocolError, Report, MAX_FRAME_LEN, MAX_PAYLOAD_LEN, PROTOCOL_VERSION};
+
+    /// A small deterministic xorshift generator so fuzz-style tests are reproducible.
+    pub(crate) struct Rng(u64);
+
+    impl Rng {
+        pub(crate) fn new(seed: u64) -> Self {
+            Self(seed)
+        }
+
+        pub(crate) fn next_u64(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        pub(crate) fn below(&mut self, bound: usize) -> usize {
+            (self.next_u64() % bound as u64) as usize
+        }
+
+        pub(crate) fn bytes(&mut self, len: usize) -> Vec<u8> {
+            (0..len).map(|_| self.next_u64() as u8).collect()
+        }
+    }
+
+    const COMMANDS: [Command; 7] = [
+        Command::GetConfiguration,
+        Command::SetConfiguration,
+        Command::SetMacro,
+        Command::ClearMacros,
+        Command::Configuration,
+        Command::Ack,
+        Command::Nack,
+    ];
+
+    #[test]
+    fn can_round_trip_reports() {
+        let mut rng = Rng::new(0x5eed);
+        for command in COMMANDS {
+            for len in [0, 1, 2, 63, 64, 1000, MAX_PAYLOAD_LEN] {
+                let report = Report::new(command, rng.bytes(len));
+                let frame = report.encode().unwrap();
+                assert_eq!(Report::decode(&frame).unwrap(), report);
+            }
+        }
+    }
+
+    #[test]
+    fn ignores_trailing_padding() {
+        let report = Report::new(Command::SetConfiguration, vec![1, 2, 3]);
+        let mut frame = report.encode().unwrap().to_vec();
+        frame.resize(64, 0);
+        assert_eq!(Report::decode(&frame).unwrap(), report);
+    }
+
+    #[test]
+    fn rejects_truncated_frames() {
+        let frame = Report::new(Command::SetConfiguration, vec![7; 32])
+            .encode()
+            .unwrap();
+        for len in 0..frame.len() {
+            assert!(matches!(
+                Report::decode(&frame[..len]),
+                Err(ProtocolError::Truncated { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn rejects_oversized_frames() {
+        let report = Report::new(Command::SetConfiguration, vec![0; MAX_PAYLOAD_LEN + 1]);
+        assert!(matches!(
+            report.encode(),
+            Err(ProtocolError::Oversized { .. })
+        ));
+
+        let frame = vec![0; MAX_FRAME_LEN + 1];
+        assert!(matches!(
+            Report::decode(&frame),
+            Err(ProtocolError::Oversized { .. })
+        ));
+
+        let mut frame = Report::empty(Command::Ack).encode().unwrap().to_vec();
+        frame[2..4].copy_from_slice(&u16::MAX.to_le_bytes());
+        assert!(matches!(
+            Report::decode(&frame),
+            Err(ProtocolError::Oversized { .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_bad_checksums() {
+        let frame = Report::new(Command::Configuration, vec![1, 2, 3, 4])
+            .encode()
+            .unwrap();
+
+        // Flipping any bit after the version byte must be detected.
+        for byte in 1..frame.len() {
+            for bit in 0..8 {
+                let mut corrupted = frame.to_vec();
+                corrupted[byte] ^= 1 << bit;
+                assert!(Report::decode(&corrupted).is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_versions_and_commands() {
+        let mut frame = Report::empty(Command::Ack).encode().unwrap().to_vec();
+        frame[0] = PROTOCOL_VERSION + 1;
+        assert_eq!(
+            Report::decode(&frame),
+            Err(ProtocolError::UnsupportedVersion(PROTOCOL_VERSION + 1))
+        );
+
+        let mut frame = vec![PROTOCOL_VERSION, 0x7f, 0, 0];
+        let checksum = crc32fast::hash(&frame);
+        frame.extend_from_slice(&checksum.to_le_bytes());
+        assert_eq!(
+            Report::decode(&frame),
+            Err(ProtocolError::UnknownCommand(0x7f))
+        );
+    }
+
+    #[test]
+    fn decoding_random_bytes_never_panics() {
+        let mut rng = Rng::new(0xc0ffee);
+        for _ in 0..10_000 {
+            let len = rng.below(128);
+            let mut frame = rng.bytes(len);
+            // Bias towards frames that get past the version check.
+            if !frame.is_empty() && rng.below(2) == 0 {
+                frame[0] = PROTOCOL_VERSION;
+            }
+            let _ = Report::decode(&frame);
+        }
+    }
+}
@@ -0,0 +1,699 @@
+use bytes::{BufMut, Bytes, BytesMut};
+
+use super::{
+    byte_count,
+    lighting::{decode_lighting, encode_lighting},
+    Command, PayloadReader, ProtocolError, Report,
+};
+use crate::profile::{
+    colour::Rgb,
+    keyboard_profile::{KeyAction, KeyBinding, KeyboardProfile, Layer},
+    keycode::{Keycode, MediaKey, Modifiers},
+    macros::MacroId,
+    mouse_profile::{
+        ButtonAction, ButtonBinding, DpiStage, MouseButton, MouseProfile, PollingRate,
+        ScrollDirection,
+    },
+    ProfileConfiguration,
+};
+
+const MOUSE_CLASS: u8 = 0x01;
+const KEYBOARD_CLASS: u8 = 0x02;
+
+/// Encode a [`ProfileConfiguration`] into a configuration payload. The first byte identifies
+/// the device class and the remainder is class specific.
+///
+/// A mouse payload is laid out as follows, multi-byte fields being little endian:
+///
+/// | Field                | Size            |
+/// |----------------------|-----------------|
+/// | stage count `n`      | u8              |
+/// | active stage         | u8              |
+/// | stages               | `n` × 7 bytes   |
+/// | polling rate (Hz)    | u16             |
+/// | lift-off (mm)        | u8              |
+/// | angle snapping       | u8              |
+/// | motion sync          | u8              |
+/// | debounce (ms)        | u8              |
+/// | button count `m`     | u8              |
+/// | buttons              | `m` bindings    |
+/// | flags                | u8              |
+/// | lighting             | variable        |
+///
+/// where each stage is the X DPI (u16), Y DPI (u16) and colour (3 × u8) and each button
+/// binding is the button id (u8) followed by a button action, see [`encode_button_action`].
+/// Bit 0 of the flags allows an unbound primary click.
+///
+/// A keyboard payload is the layer count (u8) followed by each layer: its binding count (u8)
+/// and every binding as row (u8), column (u8) and a key action. A key action is a tag byte
+/// followed by its data, see [`encode_key_action`]. The layers are followed by the lighting.
+///
+/// Lighting is laid out as described in [`encode_lighting`].
+///
+/// Counts and indices that do not fit their field are refused, configurations should be
+/// validated against the model before they are encoded.
+pub(crate) fn encode_configuration(
+    configuration: &ProfileConfiguration,
+) -> Result<Bytes, ProtocolError> {
+    let mut payload = BytesMut::new();
+    match configuration {
+        ProfileConfiguration::Mouse(mouse) => {
+            payload.put_u8(MOUSE_CLASS);
+            encode_mouse(mouse, &mut payload)?;
+        }
+        ProfileConfiguration::Keyboard(keyboard) => {
+            payload.put_u8(KEYBOARD_CLASS);
+            encode_keyboard(keyboard, &mut payload)?;
+        }
+    }
+    Ok(payload.freeze())
+}
+
+pub(crate) fn decode_configuration(payload: &[u8]) -> Result<ProfileConfiguration, ProtocolError> {
+    let mut reader = PayloadReader::new(payload);
+    let configuration = match reader.read_u8()? {
+        MOUSE_CLASS => ProfileConfiguration::Mouse(decode_mouse(&mut reader)?),
+        KEYBOARD_CLASS => ProfileConfiguration::Keyboard(decode_keyboard(&mut reader)?),
+        class => {
+            return Err(ProtocolError::InvalidPayload(format!(
+                "Unknown device class {class:#04x}"
+            )))
+        }
+    };
+    reader.finish()?;
+    Ok(configuration)
+}
+
+fn encode_mouse(mouse: &MouseProfile, payload: &mut BytesMut) -> Result<(), ProtocolError> {
+    let stages = mouse.get_dpi_stages();
+    payload.put_u8(byte_count("dpi_stages", stages.len())?);
+    payload.put_u8(byte_count(
+        "active_dpi_stage",
+        mouse.get_active_dpi_stage(),
+    )?);
+    for stage in stages {
+        payload.put_u16_le(stage.x);
+        payload.put_u16_le(stage.y);
+        payload.put_slice(&[stage.colour.r, stage.colour.g, stage.colour.b]);
+    }
+    payload.put_u16_le(mouse.get_polling_rate().hz());
+    payload.put_u8(mouse.get_lift_off_distance_mm());
+    payload.put_u8(mouse.get_angle_snapping() as u8);
+    payload.put_u8(mouse.get_motion_sync() as u8);
+    payload.put_u8(mouse.get_debounce_ms());
+
+    let buttons = mouse.get_buttons();
+    payload.put_u8(byte_count("buttons", buttons.len())?);
+    for binding in buttons {
+        payload.put_u8(mouse_button_id(binding.button));
+        encode_button_action(&binding.action, payload)?;
+    }
+    payload.put_u8(mouse.get_allow_unbound_primary() as u8);
+    encode_lighting(mouse.get_lighting(), payload)
+}
+
+fn decode_mouse(reader: &mut PayloadReader) -> Result<MouseProfile, ProtocolError> {
+    let count = reader.read_u8()? as usize;
+    let active = reader.read_u8()? as usize;
+    if count > 0 && active >= count {
+        return Err(ProtocolError::InvalidPayload(format!(
+            "Active DPI stage {active} out of {count}"
+        )));
+    }
+
+    let stages = (0..count)
+        .map(|_| {
+            Ok(DpiStage {
+                x: reader.read_u16()?,
+                y: reader.read_u16()?,
+                colour: Rgb::new(reader.read_u8()?, reader.read_u8()?, reader.read_u8()?),
+            })
+        })
+        .collect::<Result<Vec<_>, ProtocolError>>()?;
+
+    let hz = reader.read_u16()?;
+    let polling_rate = PollingRate::from_hz(hz)
+        .ok_or_else(|| ProtocolError::InvalidPayload(format!("Unknown polling rate {hz}")))?;
+
+    let mouse = MouseProfile::default()
+        .with_dpi_stages(stages, active)
+        .with_polling_rate(polling_rate)
+        .with_lift_off_distance_mm(reader.read_u8()?)
+        .with_angle_snapping(reader.read_bool()?)
+        .with_motion_sync(reader.read_bool()?)
+        .with_debounce_ms(reader.read_u8()?);
+
+    let buttons = (0..reader.read_u8()?)
+        .map(|_| {
+            Ok(ButtonBinding {
+                button: decode_mouse_button(reader.read_u8()?)?,
+                action: decode_button_action(reader)?,
+            })
+        })
+        .collect::<Result<Vec<_>, ProtocolError>>()?;
+
+    let flags = reader.read_u8()?;
+    Ok(mouse
+        .with_buttons(buttons)
+        .with_allow_unbound_primary(flags & 0x01 != 0)
+        .with_lighting(decode_lighting(reader)?))
+}
+
+const MOUSE_BUTTONS: [MouseButton; 6] = [
+    MouseButton::Left,
+    MouseButton::Right,
+    MouseButton::Middle,
+    MouseButton::Back,
+    MouseButton::Forward,
+    MouseButton::Dpi,
+];
+
+pub(crate) fn mouse_button_id(button: MouseButton) -> u8 {
+    MOUSE_BUTTONS
+        .iter()
+        .position(|candidate| *candidate == button)
+        .unwrap() as u8
+}
+
+pub(crate) fn decode_mouse_button(id: u8) -> Result<MouseButton, ProtocolError> {
+    MOUSE_BUTTONS
+        .get(id as usize)
+        .copied()
+        .ok_or_else(|| ProtocolError::InvalidPayload(format!("Unknown mouse button {id:#04x}")))
+}
+
+const SCROLL_DIRECTIONS: [ScrollDirection; 4] = [
+    ScrollDirection::Up,
+    ScrollDirection::Down,
+    ScrollDirection::Left,
+    ScrollDirection::Right,
+];
+
+const BUTTON_DISABLED: u8 = 0x00;
+const BUTTON_CLICK: u8 = 0x01;
+const BUTTON_KEYSTROKE: u8 = 0x02;
+const BUTTON_DPI_UP: u8 = 0x03;
+const BUTTON_DPI_DOWN: u8 = 0x04;
+const BUTTON_DPI_CYCLE: u8 = 0x05;
+const BUTTON_PROFILE_SLOT: u8 = 0x06;
+const BUTTON_SCROLL: u8 = 0x07;
+const BUTTON_MACRO: u8 = 0x08;
+
+/// Encode a [`ButtonAction`] as a tag byte followed by:
+///
+/// | Action        | Data                                                 |
+/// |---------------|------------------------------------------------------|
+/// | `Disabled`    | -                                                    |
+/// | `Click`       | button id (u8)                                       |
+/// | `Keystroke`   | modifier mask (u8), HID usage or 0 (u8)              |
+/// | `DpiUp`       | -                                                    |
+/// | `DpiDown`     | -                                                    |
+/// | `DpiCycle`    | -                                                    |
+/// | `ProfileSlot` | slot (u8)                                            |
+/// | `Scroll`      | direction (u8): up, down, left, right                |
+/// | `Macro`       | macro id, see [`encode_macro_id`]                    |
+pub(crate) fn encode_button_action(
+    action: &ButtonAction,
+    payload: &mut BytesMut,
+) -> Result<(), ProtocolError> {
+    match action {
+        ButtonAction::Disabled => payload.put_u8(BUTTON_DISABLED),
+        ButtonAction::Click(button) => {
+            payload.put_u8(BUTTON_CLICK);
+            payload.put_u8(mouse_button_id(*button));
+        }
+        ButtonAction::Keystroke { modifiers, key } => {
+            payload.put_u8(BUTTON_KEYSTROKE);
+            payload.put_u8(modifiers.to_mask());
+            payload.put_u8(key.map_or(0, |key| key.usage()));
+        }
+        ButtonAction::DpiUp => payload.put_u8(BUTTON_DPI_UP),
+        ButtonAction::DpiDown => payload.put_u8(BUTTON_DPI_DOWN),
+        ButtonAction::DpiCycle => payload.put_u8(BUTTON_DPI_CYCLE),
+        ButtonAction::ProfileSlot(slot) => {
+            payload.put_u8(BUTTON_PROFILE_SLOT);
+            payload.put_u8(*slot);
+        }
+        ButtonAction::Scroll(direction) => {
+            payload.put_u8(BUTTON_SCROLL);
+            payload.put_u8(
+                SCROLL_DIRECTIONS
+                    .iter()
+                    .position(|candidate| candidate == direction)
+                    .unwrap() as u8,
+            );
+        }
+        ButtonAction::Macro(macro_id) => {
+            payload.put_u8(BUTTON_MACRO);
+            encode_macro_id(macro_id, payload)?;
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn decode_button_action(
+    reader: &mut PayloadReader,
+) -> Result<ButtonAction, ProtocolError> {
+    let action = match reader.read_u8()? {
+        BUTTON_DISABLED => ButtonAction::Disabled,
+        BUTTON_CLICK => ButtonAction::Click(decode_mouse_button(reader.read_u8()?)?),
+        BUTTON_KEYSTROKE => {
+            let modifiers = Modifiers::from_mask(reader.read_u8()?);
+            let key = match reader.read_u8()? {
+                0 => None,
+                usage => Some(decode_keycode(usage)?),
+            };
+            ButtonAction::Keystroke { modifiers, key }
+        }
+        BUTTON_DPI_UP => ButtonAction::DpiUp,
+        BUTTON_DPI_DOWN => ButtonAction::DpiDown,
+        BUTTON_DPI_CYCLE => ButtonAction::DpiCycle,
+        BUTTON_PROFILE_SLOT => ButtonAction::ProfileSlot(reader.read_u8()?),
+        BUTTON_SCROLL => {
+            let direction = reader.read_u8()?;
+            ButtonAction::Scroll(SCROLL_DIRECTIONS.get(direction as usize).copied().ok_or_else(
+                || ProtocolError::InvalidPayload(format!("Unknown scroll direction {direction}")),
+            )?)
+        }
+        BUTTON_MACRO => ButtonAction::Macro(decode_macro_id(reader)?),
+        tag => {
+            return Err(ProtocolError::InvalidPayload(format!(
+                "Unknown button action {tag:#04x}"
+            )))
+        }
+    };
+    Ok(action)
+}
+
+fn encode_keyboard(
+    keyboard: &KeyboardProfile,
+    payload: &mut BytesMut,
+) -> Result<(), ProtocolError> {
+    let layers = keyboard.get_layers();
+    payload.put_u8(byte_count("layers", layers.len())?);
+    for layer in layers {
+        payload.put_u8(byte_count("layer bindings", layer.bindings.len())?);
+        for binding in &layer.bindings {
+            payload.put_u8(binding.row);
+            payload.put_u8(binding.col);
+            encode_key_action(&binding.action, payload)?;
+        }
+    }
+    encode_lighting(keyboard.get_lighting(), payload)
+}
+
+fn decode_keyboard(reader: &mut PayloadReader) -> Result<KeyboardProfile, ProtocolError> {
+    let layers = (0..reader.read_u8()?)
+        .map(|_| {
+            let bindings = (0..reader.read_u8()?)
+                .map(|_| {
+                    Ok(KeyBinding {
+                        row: reader.read_u8()?,
+                        col: reader.read_u8()?,
+                        action: decode_key_action(reader)?,
+                    })
+                })
+                .collect::<Result<Vec<_>, ProtocolError>>()?;
+            Ok(Layer { bindings })
+        })
+        .collect::<Result<Vec<_>, ProtocolError>>()?;
+
+    Ok(KeyboardProfile::new(layers).with_lighting(decode_lighting(reader)?))
+}
+
+const ACTION_NONE: u8 = 0x00;
+const ACTION_TRANSPARENT: u8 = 0x01;
+const ACTION_KEY: u8 = 0x02;
+const ACTION_COMBO: u8 = 0x03;
+const ACTION_LAYER_MOMENTARY: u8 = 0x04;
+const ACTION_LAYER_TOGGLE: u8 = 0x05;
+const ACTION_MEDIA: u8 = 0x06;
+const ACTION_MACRO: u8 = 0x07;
+
+/// Encode a [`KeyAction`] as a tag byte followed by:
+///
+/// | Action           | Data                                          |
+/// |------------------|-----------------------------------------------|
+/// | `None`           | -                                             |
+/// | `Transparent`    | -                                             |
+/// | `Key`            | HID usage (u8)                                |
+/// | `Combo`          | modifier mask (u8), HID usage or 0 (u8)       |
+/// | `LayerMomentary` | layer (u8)                                    |
+/// | `LayerToggle`    | layer (u8)                                    |
+/// | `Media`          | consumer usage (u16)                          |
+/// | `Macro`          | macro id, see [`encode_macro_id`]             |
+pub(crate) fn encode_key_action(
+    action: &KeyAction,
+    payload: &mut BytesMut,
+) -> Result<(), ProtocolError> {
+    match action {
+        KeyAction::None => payload.put_u8(ACTION_NONE),
+        KeyAction::Transparent => payload.put_u8(ACTION_TRANSPARENT),
+        KeyAction::Key(keycode) => {
+            payload.put_u8(ACTION_KEY);
+            payload.put_u8(keycode.usage());
+        }
+        KeyAction::Combo { modifiers, key } => {
+            payload.put_u8(ACTION_COMBO);
+            payload.put_u8(modifiers.to_mask());
+            payload.put_u8(key.map_or(0, |key| key.usage()));
+        }
+        KeyAction::LayerMomentary(layer) => {
+            payload.put_u8(ACTION_LAYER_MOMENTARY);
+            payload.put_u8(*layer);
+        }
+        KeyAction::LayerToggle(layer) => {
+            payload.put_u8(ACTION_LAYER_TOGGLE);
+            payload.put_u8(*layer);
+        }
+        KeyAction::Media(key) => {
+            payload.put_u8(ACTION_MEDIA);
+            payload.put_u16_le(key.usage());
+        }
+        KeyAction::Macro(macro_id) => {
+            payload.put_u8(ACTION_MACRO);
+            encode_macro_id(macro_id, payload)?;
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn decode_key_action(reader: &mut PayloadReader) -> Result<KeyAction, ProtocolError> {
+    let action = match reader.read_u8()? {
+        ACTION_NONE => KeyAction::None,
+        ACTION_TRANSPARENT => KeyAction::Transparent,
+        ACTION_KEY => KeyAction::Key(decode_keycode(reader.read_u8()?)?),
+        ACTION_COMBO => {
+            let modifiers = Modifiers::from_mask(reader.read_u8()?);
+            let key = match reader.read_u8()? {
+                0 => None,
+                usage => Some(decode_keycode(usage)?),
+            };
+            KeyAction::Combo { modifiers, key }
+        }
+        ACTION_LAYER_MOMENTARY => KeyAction::LayerMomentary(reader.read_u8()?),
+        ACTION_LAYER_TOGGLE => KeyAction::LayerToggle(reader.read_u8()?),
+        ACTION_MEDIA => {
+            let usage = reader.read_u16()?;
+            KeyAction::Media(MediaKey::from_usage(usage).ok_or_else(|| {
+                ProtocolError::InvalidPayload(format!("Unknown media usage {usage:#06x}"))
+            })?)
+        }
+        ACTION_MACRO => KeyAction::Macro(decode_macro_id(reader)?),
+        tag => {
+            return Err(ProtocolError::InvalidPayload(format!(
+                "Unknown key action {tag:#04x}"
+            )))
+        }
+    };
+    Ok(action)
+}
+
+/// Encode a [`MacroId`] as its length (u8) followed by its UTF-8 bytes. Ids longer than
+/// [`MAX_MACRO_ID_LEN`][crate::profile::macros::MAX_MACRO_ID_LEN] bytes are refused rather than truncated, which would reference a
+/// different macro.
+pub(crate) fn encode_macro_id(
+    macro_id: &MacroId,
+    payload: &mut BytesMut,
+) -> Result<(), ProtocolError> {
+    payload.put_u8(byte_count("macro id length", macro_id.len())?);
+    payload.put_slice(macro_id.as_bytes());
+    Ok(())
+}
+
+pub(crate) fn decode_macro_id(reader: &mut PayloadReader) -> Result<MacroId, ProtocolError> {
+    let len = reader.read_u8()? as usize;
+    String::from_utf8(reader.read_bytes(len)?.to_vec())
+        .map_err(|_| ProtocolError::InvalidPayload("Macro id is not UTF-8".to_string()))
+}
+
+pub(crate) fn decode_keycode(usage: u8) -> Result<Keycode, ProtocolError> {
+    Keycode::from_usage(usage)
+        .ok_or_else(|| ProtocolError::InvalidPayload(format!("Unknown key usage {usage:#04x}")))
+}
+
+/// Build the report that writes a configuration to a device.
+pub(crate) fn set_configuration_report(
+    configuration: &ProfileConfiguration,
+) -> Result<Report, ProtocolError> {
+    Ok(Report::new(
+        Command::SetConfiguration,
+        encode_configuration(configuration)?,
+    ))
+}
+
+/// Extract the configuration from a device's `Configuration` response.
+pub(crate) fn configuration_from_report(
+    report: &Report,
+) -> Result<ProfileConfiguration, ProtocolError> {
+    match report.get_command() {
+        Command::Configuration | Command::SetConfiguration => {
+            decode_configuration(report.get_payload())
+        }
+        command => Err(ProtocolError::UnexpectedCommand(command)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        configuration_from_report, decode_configuration, encode_configuration,
+        set_configuration_report,
+    };
+    use crate::{
+        device::protocol::{tests::Rng, Command, ProtocolError, Report},
+        profile::{
+            colour::Rgb,
+            keyboard_profile::{KeyAction, KeyBinding, KeyboardProfile, Layer},
+            keycode::{Keycode, MediaKey, Modifiers},
+            lighting::{Led, LedColour, Lighting, LightingDirection, LightingEffect, LightingZone},
+            mouse_profile::{
+                ButtonAction, ButtonBinding, DpiStage, MouseButton, MouseProfile, PollingRate,
+                ScrollDirection,
+            },
+            ProfileConfiguration,
+        },
+    };
+
+    fn every_key_action() -> KeyboardProfile {
+        let actions = [
+            KeyAction::None,
+            KeyAction::Transparent,
+            KeyAction::Key(Keycode::RightGui),
+            KeyAction::Combo {
+                modifiers: Modifiers::from_mask(0b1010_0101),
+                key: Some(Keycode::Tab),
+            },
+            KeyAction::Combo {
+                modifiers: Modifiers::from_mask(0b0000_0001),
+                key: None,
+            },
+            KeyAction::LayerMomentary(3),
+            KeyAction::LayerToggle(2),
+            KeyAction::Media(MediaKey::VolumeDown),
+            KeyAction::Macro("e0c1a3b2-0000-4000-8000-000000000000".to_string()),
+        ];
+        let bindings = actions
+            .into_iter()
+            .enumerate()
+            .map(|(col, action)| KeyBinding {
+                row: 1,
+                col: col as u8,
+                action,
+            })
+            .collect();
+
+        let lighting = Lighting {
+            effect: LightingEffect::Static,
+            direction: LightingDirection::BottomToTop,
+            colours: vec![LedColour {
+                led: Led::Key { row: 4, col: 3 },
+                colour: Rgb::new(0x12, 0x34, 0x56),
+            }],
+            ..Lighting::default()
+        };
+
+        KeyboardProfile::new(vec![Layer::default(), Layer { bindings }]).with_lighting(lighting)
+    }
+
+    fn random_mouse(rng: &mut Rng) -> MouseProfile {
+        let count = rng.below(6) + 1;
+        let stages = (0..count)
+            .map(|_| DpiStage {
+                x: rng.next_u64() as u16,
+                y: rng.next_u64() as u16,
+                colour: Rgb::new(
+                    rng.next_u64() as u8,
+                    rng.next_u64() as u8,
+                    rng.next_u64() as u8,
+                ),
+            })
+            .collect();
+        let rates = [PollingRate::Hz125, PollingRate::Hz1000, PollingRate::Hz8000];
+        let actions = [
+            ButtonAction::Disabled,
+            ButtonAction::Click(MouseButton::Forward),
+            ButtonAction::Keystroke {
+                modifiers: Modifiers::from_mask(rng.next_u64() as u8),
+                key: Some(Keycode::F5),
+            },
+            ButtonAction::DpiUp,
+            ButtonAction::DpiDown,
+            ButtonAction::DpiCycle,
+            ButtonAction::ProfileSlot(rng.next_u64() as u8),
+            ButtonAction::Scroll(ScrollDirection::Left),
+            ButtonAction::Macro("macro".to_string()),
+        ];
+        let buttons = [MouseButton::Left, MouseButton::Dpi, MouseButton::Back]
+            .into_iter()
+            .map(|button| ButtonBinding {
+                button,
+                action: actions[rng.below(actions.len())].clone(),
+            })
+            .collect();
+
+        let effects = [
+            LightingEffect::Off,
+            LightingEffect::Breathing,
+            LightingEffect::Reactive,
+        ];
+        let lighting = Lighting {
+            effect: effects[rng.below(effects.len())],
+            speed: rng.next_u64() as u8,
+            brightness: rng.next_u64() as u8,
+            direction: LightingDirection::RightToLeft,
+            palette: (0..rng.below(4))
+                .map(|_| Rgb::new(rng.next_u64() as u8, 0, rng.next_u64() as u8))
+                .collect(),
+            colours: [LightingZone::Logo, LightingZone::Underglow]
+                .into_iter()
+                .take(rng.below(3))
+                .map(|zone| LedColour {
+                    led: Led::Zone(zone),
+                    colour: Rgb::new(0, rng.next_u64() as u8, 0),
+                })
+                .collect(),
+        };
+
+        MouseProfile::default()
+            .with_dpi_stages(stages, rng.below(count))
+            .with_polling_rate(rates[rng.below(rates.len())])
+            .with_lift_off_distance_mm(rng.next_u64() as u8)
+            .with_angle_snapping(rng.below(2) == 1)
+            .with_motion_sync(rng.below(2) == 1)
+            .with_debounce_ms(rng.next_u64() as u8)
+            .with_buttons(buttons)
+            .with_allow_unbound_primary(rng.below(2) == 1)
+            .with_lighting(lighting)
+    }
+
+    #[test]
+    fn can_round_trip_configurations() {
+        let mut rng = Rng::new(42);
+        let mut configurations = vec![
+            ProfileConfiguration::Keyboard(KeyboardProfile::default()),
+            ProfileConfiguration::Keyboard(every_key_action()),
+            ProfileConfiguration::Mouse(MouseProfile::default()),
+        ];
+        configurations
+            .extend((0..100).map(|_| ProfileConfiguration::Mouse(random_mouse(&mut rng))));
+
+        for configuration in configurations {
+            let frame = set_configuration_report(&configuration)
+                .unwrap()
+                .encode()
+                .unwrap();
+            let report = Report::decode(&frame).unwrap();
+            assert_eq!(configuration_from_report(&report).unwrap(), configuration);
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_payloads() {
+        let payload =
+            encode_configuration(&ProfileConfiguration::Mouse(MouseProfile::new(800))).unwrap();
+
+        for len in 0..payload.len() {
+            assert!(matches!(
+                decode_configuration(&payload[..len]),
+                Err(ProtocolError::Truncated { .. })
+            ));
+        }
+
+        let mut trailing = payload.to_vec();
+        trailing.push(0);
+        assert!(matches!(
+            decode_configuration(&trailing),
+            Err(ProtocolError::InvalidPayload(_))
+        ));
+
+        assert!(matches!(
+            decode_configuration(&[0xff]),
+            Err(ProtocolError::InvalidPayload(_))
+        ));
+    }
+
+    #[test]
+    fn refuses_counts_that_do_not_fit() {
+        let stages = vec![DpiStage::new(800, Rgb::new(0, 0, 0)); 256];
+        let mouse = MouseProfile::default().with_dpi_stages(stages, 0);
+        assert!(matches!(
+            encode_configuration(&ProfileConfiguration::Mouse(mouse)),
+            Err(ProtocolError::TooLarge { value: 256, .. })
+        ));
+
+        let bindings = vec![
+            KeyBinding {
+                row: 0,
+                col: 0,
+                action: KeyAction::None,
+            };
+            256
+        ];
+        let keyboard = KeyboardProfile::new(vec![Layer { bindings }]);
+        assert!(matches!(
+            encode_configuration(&ProfileConfiguration::Keyboard(keyboard)),
+            Err(ProtocolError::TooLarge { value: 256, .. })
+        ));
+
+        let buttons = vec![
+            ButtonBinding {
+                button: MouseButton::Left,
+                action: ButtonAction::Disabled,
+            };
+            300
+        ];
+        let mouse = MouseProfile::default().with_buttons(buttons);
+        assert!(matches!(
+            encode_configuration(&ProfileConfiguration::Mouse(mouse)),
+            Err(ProtocolError::TooLarge { value: 300, .. })
+        ));
+
+        let lighting = Lighting {
+            palette: vec![Rgb::new(0, 0, 0); 256],
+            ..Lighting::default()
+        };
+        let mouse = MouseProfile::default().with_lighting(lighting);
+        assert!(matches!(
+            encode_configuration(&ProfileConfiguration::Mouse(mouse)),
+            Err(ProtocolError::TooLarge { value: 256, .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_unexpected_commands() {
+        assert_eq!(
+            configuration_from_report(&Report::empty(Command::Ack)),
+            Err(ProtocolError::UnexpectedCommand(Command::Ack))
+        );
+    }
+
+    #[test]
+    fn decoding_random_payloads_never_panics() {
+        let mut rng = Rng::new(7);
+        for _ in 0..10_000 {
+            let len = rng.below(16);
+            let _ = decode_configuration(&rng.bytes(len));
+        }
+    }
+}
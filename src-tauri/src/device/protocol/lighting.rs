@@ -0,0 +1,149 @@
+use bytes::{BufMut, BytesMut};
+
+use super::{byte_count, PayloadReader, ProtocolError};
+use crate::profile::{
+    colour::Rgb,
+    lighting::{Led, LedColour, Lighting, LightingDirection, LightingEffect, LightingZone},
+};
+
+const EFFECTS: [LightingEffect; 6] = [
+    LightingEffect::Off,
+    LightingEffect::Static,
+    LightingEffect::Breathing,
+    LightingEffect::Wave,
+    LightingEffect::Reactive,
+    LightingEffect::SpectrumCycle,
+];
+
+const DIRECTIONS: [LightingDirection; 4] = [
+    LightingDirection::LeftToRight,
+    LightingDirection::RightToLeft,
+    LightingDirection::TopToBottom,
+    LightingDirection::BottomToTop,
+];
+
+const ZONES: [LightingZone; 3] = [
+    LightingZone::ScrollWheel,
+    LightingZone::Logo,
+    LightingZone::Underglow,
+];
+
+const LED_ZONE: u8 = 0x00;
+const LED_KEY: u8 = 0x01;
+
+fn index_of<T: PartialEq>(values: &[T], value: &T) -> u8 {
+    values
+        .iter()
+        .position(|candidate| candidate == value)
+        .unwrap() as u8
+}
+
+fn decode_indexed<T: Copy>(values: &[T], index: u8, name: &str) -> Result<T, ProtocolError> {
+    values
+        .get(index as usize)
+        .copied()
+        .ok_or_else(|| ProtocolError::InvalidPayload(format!("Unknown {name} {index}")))
+}
+
+fn encode_rgb(colour: &Rgb, payload: &mut BytesMut) {
+    payload.put_slice(&[colour.r, colour.g, colour.b]);
+}
+
+fn decode_rgb(reader: &mut PayloadReader) -> Result<Rgb, ProtocolError> {
+    Ok(Rgb::new(
+        reader.read_u8()?,
+        reader.read_u8()?,
+        reader.read_u8()?,
+    ))
+}
+
+/// Encode the [`Lighting`] of a profile. The device renders the effect itself, so only its
+/// parameters are sent:
+///
+/// | Field              | Size               |
+/// |--------------------|--------------------|
+/// | effect             | u8                 |
+/// | speed              | u8                 |
+/// | brightness         | u8                 |
+/// | direction          | u8                 |
+/// | palette size `n`   | u8                 |
+/// | palette            | `n` × 3 bytes      |
+/// | colour count `m`   | u8                 |
+/// | colours            | `m` LED colours    |
+///
+/// Effects are numbered off, static, breathing, wave, reactive and spectrum cycle, and
+/// directions left to right, right to left, top to bottom and bottom to top. An LED colour
+/// is either `0x00` and a zone (u8: scroll wheel, logo, underglow) or `0x01`, a row (u8) and
+/// a column (u8), followed by the colour (3 × u8).
+pub(crate) fn encode_lighting(
+    lighting: &Lighting,
+    payload: &mut BytesMut,
+) -> Result<(), ProtocolError> {
+    payload.put_u8(index_of(&EFFECTS, &lighting.effect));
+    payload.put_u8(lighting.speed);
+    payload.put_u8(lighting.brightness);
+    payload.put_u8(index_of(&DIRECTIONS, &lighting.direction));
+
+    payload.put_u8(byte_count("palette", lighting.palette.len())?);
+    for colour in &lighting.palette {
+        encode_rgb(colour, payload);
+    }
+
+    payload.put_u8(byte_count("lighting colours", lighting.colours.len())?);
+    for led_colour in &lighting.colours {
+        match led_colour.led {
+            Led::Zone(zone) => {
+                payload.put_u8(LED_ZONE);
+                payload.put_u8(index_of(&ZONES, &zone));
+            }
+            Led::Key { row, col } => {
+                payload.put_u8(LED_KEY);
+                payload.put_u8(row);
+                payload.put_u8(col);
+            }
+        }
+        encode_rgb(&led_colour.colour, payload);
+    }
+    Ok(())
+}
+
+pub(crate) fn decode_lighting(reader: &mut PayloadReader) -> Result<Lighting, ProtocolError> {
+    let effect = decode_indexed(&EFFECTS, reader.read_u8()?, "lighting effect")?;
+    let speed = reader.read_u8()?;
+    let brightness = reader.read_u8()?;
+    let direction = decode_indexed(&DIRECTIONS, reader.read_u8()?, "lighting direction")?;
+
+    let palette = (0..reader.read_u8()?)
+        .map(|_| decode_rgb(reader))
+        .collect::<Result<Vec<_>, ProtocolError>>()?;
+
+    let colours = (0..reader.read_u8()?)
+        .map(|_| {
+            let led = match reader.read_u8()? {
+                LED_ZONE => Led::Zone(decode_indexed(&ZONES, reader.read_u8()?, "lighting zone")?),
+                LED_KEY => Led::Key {
+                    row: reader.read_u8()?,
+                    col: reader.read_u8()?,
+                },
+                tag => {
+                    return Err(ProtocolError::InvalidPayload(format!(
+                        "Unknown LED {tag:#04x}"
+                    )))
+                }
+            };
+            Ok(LedColour {
+                led,
+                colour: decode_rgb(reader)?,
+            })
+        })
+        .collect::<Result<Vec<_>, ProtocolError>>()?;
+
+    Ok(Lighting {
+        effect,
+        speed,
+        brightness,
+        direction,
+        palette,
+        colours,
+    })
+}
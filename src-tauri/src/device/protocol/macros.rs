@@ -0,0 +1,147 @@
+use bytes::{BufMut, BytesMut};
+
+use super::{
+    configuration::{
+        decode_keycode, decode_macro_id, decode_mouse_button, encode_macro_id, mouse_button_id,
+    },
+    Command, PayloadReader, ProtocolError, Report,
+};
+use crate::profile::macros::{Macro, MacroEvent, MacroId, MacroStep, RepeatMode};
+
+const KEY_PRESS: u8 = 0x00;
+const KEY_RELEASE: u8 = 0x01;
+const BUTTON_PRESS: u8 = 0x02;
+const BUTTON_RELEASE: u8 = 0x03;
+
+const REPEAT_MODES: [RepeatMode; 3] = [RepeatMode::Once, RepeatMode::WhileHeld, RepeatMode::Toggle];
+
+/// A macro as stored by a device. Names are only known to the application.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct DeviceMacro {
+    pub id: MacroId,
+    pub repeat: RepeatMode,
+    pub steps: Vec<MacroStep>,
+}
+
+/// Build the report that uploads a macro to a device. The payload is the macro id, the repeat
+/// mode (u8: once, while held, toggle), the step count (u16 LE) and every step as an event
+/// tag (u8), a HID usage or button id (u8) and the delay in milliseconds (u16 LE).
+pub(crate) fn set_macro_report(recorded: &Macro) -> Result<Report, ProtocolError> {
+    let mut payload = BytesMut::new();
+    encode_macro_id(recorded.get_id(), &mut payload)?;
+    payload.put_u8(
+        REPEAT_MODES
+            .iter()
+            .position(|mode| *mode == recorded.get_repeat())
+            .unwrap() as u8,
+    );
+
+    let steps = recorded.get_steps();
+    payload.put_u16_le(
+        u16::try_from(steps.len()).map_err(|_| ProtocolError::TooLarge {
+            field: "macro steps".to_string(),
+            value: steps.len(),
+            max: u16::MAX as usize,
+        })?,
+    );
+    for step in steps {
+        let (tag, code) = match step.event {
+            MacroEvent::KeyPress(key) => (KEY_PRESS, key.usage()),
+            MacroEvent::KeyRelease(key) => (KEY_RELEASE, key.usage()),
+            MacroEvent::ButtonPress(button) => (BUTTON_PRESS, mouse_button_id(button)),
+            MacroEvent::ButtonRelease(button) => (BUTTON_RELEASE, mouse_button_id(button)),
+        };
+        payload.put_u8(tag);
+        payload.put_u8(code);
+        payload.put_u16_le(step.delay_ms);
+    }
+
+    Ok(Report::new(Command::SetMacro, payload.freeze()))
+}
+
+pub(crate) fn decode_macro(payload: &[u8]) -> Result<DeviceMacro, ProtocolError> {
+    let mut reader = PayloadReader::new(payload);
+    let id = decode_macro_id(&mut reader)?;
+
+    let repeat = reader.read_u8()?;
+    let repeat = REPEAT_MODES
+        .get(repeat as usize)
+        .copied()
+        .ok_or_else(|| ProtocolError::InvalidPayload(format!("Unknown repeat mode {repeat}")))?;
+
+    let steps = (0..reader.read_u16()?)
+        .map(|_| {
+            let tag = reader.read_u8()?;
+            let code = reader.read_u8()?;
+            let event = match tag {
+                KEY_PRESS => MacroEvent::KeyPress(decode_keycode(code)?),
+                KEY_RELEASE => MacroEvent::KeyRelease(decode_keycode(code)?),
+                BUTTON_PRESS => MacroEvent::ButtonPress(decode_mouse_button(code)?),
+                BUTTON_RELEASE => MacroEvent::ButtonRelease(decode_mouse_button(code)?),
+                tag => {
+                    return Err(ProtocolError::InvalidPayload(format!(
+                        "Unknown macro event {tag:#04x}"
+                    )))
+                }
+            };
+            Ok(MacroStep {
+                event,
+                delay_ms: reader.read_u16()?,
+            })
+        })
+        .collect::<Result<Vec<_>, ProtocolError>>()?;
+
+    reader.finish()?;
+    Ok(DeviceMacro { id, repeat, steps })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_macro, set_macro_report};
+    use crate::{
+        device::protocol::{tests::Rng, Report},
+        profile::{
+            keycode::Keycode,
+            macros::{Macro, MacroEvent, MacroStep, RepeatMode},
+            mouse_profile::MouseButton,
+        },
+    };
+
+    #[test]
+    fn can_round_trip_macros() {
+        let steps = vec![
+            MacroStep {
+                event: MacroEvent::KeyPress(Keycode::LeftCtrl),
+                delay_ms: 10,
+            },
+            MacroStep {
+                event: MacroEvent::ButtonPress(MouseButton::Forward),
+                delay_ms: 65535,
+            },
+            MacroStep {
+                event: MacroEvent::ButtonRelease(MouseButton::Forward),
+                delay_ms: 0,
+            },
+            MacroStep {
+                event: MacroEvent::KeyRelease(Keycode::LeftCtrl),
+                delay_ms: 0,
+            },
+        ];
+        let recorded = Macro::new("macro", steps, RepeatMode::WhileHeld);
+
+        let frame = set_macro_report(&recorded).unwrap().encode().unwrap();
+        let decoded = decode_macro(Report::decode(&frame).unwrap().get_payload()).unwrap();
+        assert_eq!(&decoded.id, recorded.get_id());
+        assert_eq!(decoded.repeat, recorded.get_repeat());
+        assert_eq!(decoded.steps, recorded.get_steps());
+    }
+
+    #[test]
+    fn decoding_random_payloads_never_panics() {
+        let mut rng = Rng::new(11);
+        for _ in 0..10_000 {
+            let len = rng.below(32);
+            let _ = decode_macro(&rng.bytes(len));
+        }
+    }
+}
@@ -0,0 +1,388 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use bytes::Bytes;
+use tracing::{instrument, warn};
+
+use super::{
+    capabilities::DeviceClass,
+    discovery::{DeviceEnumerator, DiscoveredDevice},
+    identity::HardwareIdentity,
+    protocol::{
+        configuration::{decode_configuration, encode_configuration},
+        macros::{decode_macro, DeviceMacro},
+        Command, NackReason, Report,
+    },
+    transport::{DeviceTransport, TransportError},
+    DeviceModel, VENDOR_ID,
+};
+use crate::profile::{macros::MacroId, Profile, ProfileConfiguration};
+
+#[derive(Debug)]
+struct SimulatedDeviceState {
+    model: DeviceModel,
+    serial: String,
+    connected: bool,
+    configuration: ProfileConfiguration,
+    macros: HashMap<MacroId, DeviceMacro>,
+    outbox: VecDeque<Bytes>,
+    failing_writes: usize,
+    pending_errors: VecDeque<NackReason>,
+}
+
+/// A virtual device that speaks the same protocol as real hardware and keeps its own onboard
+/// configuration. Clones share state, so a test (or the debug start up path) can keep a handle
+/// to script the device after its transport has been handed to the
+/// [`DeviceManager`][DeviceManager].
+///
+/// [DeviceManager]: crate::device::device_manager::DeviceManager
+#[derive(Clone, Debug)]
+pub(crate) struct SimulatedDevice {
+    state: Arc<Mutex<SimulatedDeviceState>>,
+}
+
+impl SimulatedDevice {
+    /// Create a connected device holding the default configuration of its model.
+    pub(crate) fn new(model: DeviceModel, serial: impl Into<String>) -> Self {
+        let configuration = Profile::get_default_provide_configuration(model.clone())
+            .expect("Simulated devices need a model with a definition");
+        Self {
+            state: Arc::new(Mutex::new(SimulatedDeviceState {
+                model,
+                serial: serial.into(),
+                connected: true,
+                configuration,
+                macros: HashMap::new(),
+                outbox: VecDeque::new(),
+                failing_writes: 0,
+                pending_errors: VecDeque::new(),
+            })),
+        }
+    }
+
+    pub(crate) fn get_model(&self) -> DeviceModel {
+        self.state.lock().unwrap().model.clone()
+    }
+
+    pub(crate) fn get_serial(&self) -> String {
+        self.state.lock().unwrap().serial.clone()
+    }
+
+    pub(crate) fn get_identity(&self) -> HardwareIdentity {
+        let state = self.state.lock().unwrap();
+        HardwareIdentity::new(
+            VENDOR_ID,
+            state.model.product_id().unwrap(),
+            Some(state.serial.clone()),
+            &state.serial,
+        )
+    }
+
+    pub(crate) fn get_configuration(&self) -> ProfileConfiguration {
+        self.state.lock().unwrap().configuration.clone()
+    }
+
+    pub(crate) fn get_macro(&self, macro_id: &MacroId) -> Option<DeviceMacro> {
+        self.state.lock().unwrap().macros.get(macro_id).cloned()
+    }
+
+    pub(crate) fn is_connected(&self) -> bool {
+        self.state.lock().unwrap().connected
+    }
+
+    pub(crate) fn connect(&self) {
+        self.state.lock().unwrap().connected = true;
+    }
+
+    /// Unplug the device. Any unread responses are lost.
+    pub(crate) fn disconnect(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.connected = false;
+        state.outbox.clear();
+    }
+
+    /// Make the next `count` report writes fail at the transport level.
+    pub(crate) fn fail_next_writes(&self, count: usize) {
+        self.state.lock().unwrap().failing_writes = count;
+    }
+
+    /// Answer the next command with a [`Command::Nack`] carrying the given reason.
+    pub(crate) fn respond_with_error(&self, reason: NackReason) {
+        self.state.lock().unwrap().pending_errors.push_back(reason);
+    }
+
+    pub(crate) fn transport(&self) -> SimulatedTransport {
+        SimulatedTransport {
+            device: self.clone(),
+            open: false,
+        }
+    }
+}
+
+impl SimulatedDeviceState {
+    fn handle_report(&mut self, frame: &[u8]) -> Report {
+        let report = match Report::decode(frame) {
+            Ok(report) => report,
+            Err(e) => {
+                warn!(e=%e, "Simulated device received a malformed report.");
+                return Report::nack(NackReason::MalformedReport);
+            }
+        };
+
+        if let Some(reason) = self.pending_errors.pop_front() {
+            return Report::nack(reason);
+        }
+
+        match report.get_command() {
+            Command::GetConfiguration => match encode_configuration(&self.configuration) {
+                Ok(payload) => Report::new(Command::Configuration, payload),
+                Err(_) => Report::nack(NackReason::DeviceError),
+            },
+            Command::SetConfiguration => match decode_configuration(report.get_payload()) {
+                Ok(configuration) if self.accepts(&configuration) => {
+                    self.configuration = configuration;
+                    Report::empty(Command::Ack)
+                }
+                _ => Report::nack(NackReason::InvalidConfiguration),
+            },
+            Command::SetMacro => match decode_macro(report.get_payload()) {
+                Ok(device_macro) => {
+                    self.macros.insert(device_macro.id.clone(), device_macro);
+                    Report::empty(Command::Ack)
+                }
+                Err(_) => Report::nack(NackReason::InvalidConfiguration),
+            },
+            Command::ClearMacros => {
+                self.macros.clear();
+                Report::empty(Command::Ack)
+            }
+            _ => Report::nack(NackReason::UnsupportedCommand),
+        }
+    }
+
+    fn accepts(&self, configuration: &ProfileConfiguration) -> bool {
+        matches!(
+            (
+                self.model.get_capabilities().map(|capabilities| capabilities.class),
+                configuration
+            ),
+            (Some(DeviceClass::Mouse), ProfileConfiguration::Mouse(_))
+                | (Some(DeviceClass::Keyboard), ProfileConfiguration::Keyboard(_))
+        )
+    }
+}
+
+/// The [`DeviceTransport`] end of a [`SimulatedDevice`]. Responses are produced synchronously
+/// when a report is written, so reads never block.
+#[derive(Debug)]
+pub(crate) struct SimulatedTransport {
+    device: SimulatedDevice,
+    open: bool,
+}
+
+impl DeviceTransport for SimulatedTransport {
+    fn open(&mut self) -> Result<(), TransportError> {
+        if !self.device.is_connected() {
+            return Err(TransportError::Disconnected);
+        }
+        self.open = true;
+        Ok(())
+    }
+
+    fn read_report(&mut self, timeout: Duration) -> Result<Bytes, TransportError> {
+        if !self.open {
+            return Err(TransportError::NotOpen);
+        }
+
+        let mut state = self.device.state.lock().unwrap();
+        if !state.connected {
+            return Err(TransportError::Disconnected);
+        }
+        state
+            .outbox
+            .pop_front()
+            .ok_or(TransportError::Timeout(timeout.as_millis() as u64))
+    }
+
+    #[instrument(skip_all)]
+    fn write_report(&mut self, report: &[u8]) -> Result<(), TransportError> {
+        if !self.open {
+            return Err(TransportError::NotOpen);
+        }
+
+        let mut state = self.device.state.lock().unwrap();
+        if !state.connected {
+            return Err(TransportError::Disconnected);
+        }
+
+        if state.failing_writes > 0 {
+            state.failing_writes -= 1;
+            return Err(TransportError::IOError {
+                message: "Simulated write failure".to_string(),
+            });
+        }
+
+        let response = state.handle_report(report);
+        match response.encode() {
+            Ok(frame) => state.outbox.push_back(frame),
+            Err(e) => warn!(e=%e, "Simulated device could not encode its response."),
+        }
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<(), TransportError> {
+        self.open = false;
+        Ok(())
+    }
+
+    fn is_open(&self) -> bool {
+        self.open && self.device.is_connected()
+    }
+}
+
+/// A collection of [`SimulatedDevice`]s standing in for the hardware attached to the machine.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct SimulatedBackend {
+    devices: Vec<SimulatedDevice>,
+}
+
+impl SimulatedBackend {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// A backend with one connected M1 and one connected K1.
+    pub(crate) fn with_default_devices() -> Self {
+        let mut backend = Self::new();
+        backend.add_device(SimulatedDevice::new(DeviceModel::new("M1"), "SIM-M1-0001"));
+        backend.add_device(SimulatedDevice::new(DeviceModel::new("K1"), "SIM-K1-0001"));
+        backend
+    }
+
+    pub(crate) fn add_device(&mut self, device: SimulatedDevice) -> SimulatedDevice {
+        self.devices.push(device.clone());
+        device
+    }
+
+    pub(crate) fn get_devices(&self) -> &[SimulatedDevice] {
+        &self.devices
+    }
+
+    pub(crate) fn get_connected_devices(&self) -> Vec<&SimulatedDevice> {
+        self.devices.iter().filter(|d| d.is_connected()).collect()
+    }
+}
+
+impl DeviceEnumerator for SimulatedBackend {
+    fn enumerate(&self) -> Vec<DiscoveredDevice> {
+        self.get_connected_devices()
+            .into_iter()
+            .map(|device| {
+                DiscoveredDevice::new(
+                    device.get_model(),
+                    device.get_identity(),
+                    format!("simulated:{}", device.get_serial()),
+                )
+            })
+            .collect()
+    }
+
+    fn open(&self, device: &DiscoveredDevice) -> Result<Box<dyn DeviceTransport>, TransportError> {
+        self.devices
+            .iter()
+            .find(|simulated| simulated.get_identity() == *device.get_identity())
+            .map(|simulated| Box::new(simulated.transport()) as Box<dyn DeviceTransport>)
+            .ok_or(TransportError::Disconnected)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::SimulatedDevice;
+    use crate::{
+        device::{
+            protocol::{
+                configuration::{configuration_from_report, set_configuration_report},
+                Command, NackReason, Report,
+            },
+            transport::{DeviceTransport, TransportError},
+            DeviceModel,
+        },
+        profile::{
+            keyboard_profile::KeyboardProfile, mouse_profile::MouseProfile, ProfileConfiguration,
+        },
+    };
+
+    const TIMEOUT: Duration = Duration::from_millis(10);
+
+    fn exchange(transport: &mut impl DeviceTransport, report: Report) -> Report {
+        transport.write_report(&report.encode().unwrap()).unwrap();
+        Report::decode(&transport.read_report(TIMEOUT).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn can_set_and_get_configuration() {
+        let device = SimulatedDevice::new(DeviceModel::new("M1"), "serial");
+        let mut transport = device.transport();
+        transport.open().unwrap();
+
+        let configuration = ProfileConfiguration::Mouse(MouseProfile::new(1600));
+        let response = exchange(
+            &mut transport,
+            set_configuration_report(&configuration).unwrap(),
+        );
+        assert_eq!(response.get_command(), Command::Ack);
+        assert_eq!(device.get_configuration(), configuration);
+
+        let response = exchange(&mut transport, Report::empty(Command::GetConfiguration));
+        assert_eq!(configuration_from_report(&response).unwrap(), configuration);
+    }
+
+    #[test]
+    fn rejects_configuration_for_other_device_class() {
+        let device = SimulatedDevice::new(DeviceModel::new("M1"), "serial");
+        let mut transport = device.transport();
+        transport.open().unwrap();
+
+        let configuration = ProfileConfiguration::Keyboard(KeyboardProfile::default());
+        let response = exchange(
+            &mut transport,
+            set_configuration_report(&configuration).unwrap(),
+        );
+        assert_eq!(response, Report::nack(NackReason::InvalidConfiguration));
+    }
+
+    #[test]
+    fn can_script_failures() {
+        let device = SimulatedDevice::new(DeviceModel::new("K1"), "serial");
+        let mut transport = device.transport();
+        transport.open().unwrap();
+
+        device.fail_next_writes(1);
+        let frame = Report::empty(Command::GetConfiguration).encode().unwrap();
+        assert!(matches!(
+            transport.write_report(&frame),
+            Err(TransportError::IOError { .. })
+        ));
+
+        device.respond_with_error(NackReason::DeviceError);
+        let response = exchange(&mut transport, Report::empty(Command::GetConfiguration));
+        assert_eq!(response, Report::nack(NackReason::DeviceError));
+
+        device.disconnect();
+        assert!(matches!(
+            transport.write_report(&frame),
+            Err(TransportError::Disconnected)
+        ));
+        assert!(!transport.is_open());
+
+        device.connect();
+        let response = exchange(&mut transport, Report::empty(Command::GetConfiguration));
+        assert_eq!(response.get_command(), Command::Configuration);
+    }
+}
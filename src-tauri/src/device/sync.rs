@@ -0,0 +1,320 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use thiserror::Error;
+use tracing::{debug, instrument};
+
+use super::{
+    capabilities::DeviceCapabilities,
+    protocol::{
+        configuration::{configuration_from_report, set_configuration_report},
+        macros::set_macro_report,
+        Command, NackReason, ProtocolError, Report, PROTOCOL_VERSION,
+    },
+    transport::{DeviceTransport, TransportError},
+};
+use crate::profile::{macros::Macro, ConfigurationError, ProfileConfiguration};
+
+const RESPONSE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// At most this many stale reports are discarded before an exchange, so that a device that
+/// keeps sending cannot hold up a sync.
+const MAX_STALE_REPORTS: usize = 64;
+
+/// Whether the configuration stored on a device matches its active profile.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize, Type)]
+pub enum SyncStatus {
+    InSync,
+    #[default]
+    Pending,
+    Failed(String),
+}
+
+#[derive(Debug, Error, Serialize, Deserialize, Type)]
+pub(crate) enum SyncError {
+    #[error(transparent)]
+    TransportError(#[from] TransportError),
+
+    #[error(transparent)]
+    ProtocolError(#[from] ProtocolError),
+
+    #[error("Rejected: The device rejected the report ({0:?})")]
+    Rejected(NackReason),
+
+    #[error("VerificationFailed: The configuration read back does not match the one written")]
+    VerificationFailed,
+
+    #[error("UnsupportedProtocol: The device does not support protocol version {0}")]
+    UnsupportedProtocol(u8),
+
+    #[error("InsufficientMemory: {required} bytes required, {available} bytes available")]
+    InsufficientMemory { required: usize, available: usize },
+
+    #[error("UnknownModel: There is no definition of {0}")]
+    UnknownModel(String),
+
+    #[error(transparent)]
+    InvalidConfiguration(#[from] ConfigurationError),
+}
+
+/// Discard the reports left unread by an earlier exchange, such as a response that arrived
+/// after its timeout, so that they are not taken for the response to the next report.
+fn drain_stale_reports(transport: &mut dyn DeviceTransport) -> Result<(), SyncError> {
+    for _ in 0..MAX_STALE_REPORTS {
+        match transport.read_report(Duration::ZERO) {
+            Ok(stale) => debug!("Discarding a stale report of {} bytes.", stale.len()),
+            Err(TransportError::Timeout(_)) => return Ok(()),
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(())
+}
+
+/// Write a report and wait for the device's response.
+fn exchange(transport: &mut dyn DeviceTransport, report: &Report) -> Result<Report, SyncError> {
+    drain_stale_reports(transport)?;
+    transport.write_report(&report.encode()?)?;
+    let response = Report::decode(&transport.read_report(RESPONSE_TIMEOUT)?)?;
+
+    if response.get_command() == Command::Nack {
+        let reason = response
+            .get_payload()
+            .first()
+            .copied()
+            .ok_or_else(|| ProtocolError::InvalidPayload("Empty nack".to_string()))?;
+        return Err(SyncError::Rejected(NackReason::try_from(reason)?));
+    }
+    Ok(response)
+}
+
+fn expect_ack(transport: &mut dyn DeviceTransport, report: &Report) -> Result<(), SyncError> {
+    let response = exchange(transport, report)?;
+    if response.get_command() != Command::Ack {
+        return Err(ProtocolError::UnexpectedCommand(response.get_command()).into());
+    }
+    Ok(())
+}
+
+/// Check that a device can store a configuration and its macros before sending them. The
+/// configuration and macros are validated against the limits of the model, so that nothing
+/// the encoder would have to refuse or the device would misread is sent.
+pub(crate) fn check_capabilities(
+    capabilities: &DeviceCapabilities,
+    configuration: &ProfileConfiguration,
+    macros: &[Macro],
+) -> Result<(), SyncError> {
+    if !capabilities.supports_protocol(PROTOCOL_VERSION) {
+        return Err(SyncError::UnsupportedProtocol(PROTOCOL_VERSION));
+    }
+
+    configuration.validate_for(capabilities)?;
+    if macros.len() > capabilities.macros.max_macros {
+        return Err(ConfigurationError::out_of_range(
+            "macros",
+            macros.len(),
+            &(0..=capabilities.macros.max_macros),
+        )
+        .into());
+    }
+    for recorded in macros {
+        recorded.validate(&capabilities.macros)?;
+    }
+
+    let required = set_configuration_report(configuration)?.get_payload().len()
+        + macros
+            .iter()
+            .map(|recorded| Ok(set_macro_report(recorded)?.get_payload().len()))
+            .sum::<Result<usize, ProtocolError>>()?;
+    if required > capabilities.onboard_memory_bytes {
+        return Err(SyncError::InsufficientMemory {
+            required,
+            available: capabilities.onboard_memory_bytes,
+        });
+    }
+
+    Ok(())
+}
+
+/// Replace the macros of a device with the ones referenced by a configuration, push the
+/// configuration to the device and verify it by reading it back. Clearing the macros first
+/// keeps the device from filling up with macros that no profile references any more.
+#[instrument(skip_all)]
+pub(crate) fn apply_configuration(
+    transport: &mut dyn DeviceTransport,
+    configuration: &ProfileConfiguration,
+    macros: &[Macro],
+) -> Result<(), SyncError> {
+    expect_ack(transport, &Report::empty(Command::ClearMacros))?;
+    for recorded in macros {
+        expect_ack(transport, &set_macro_report(recorded)?)?;
+    }
+    expect_ack(transport, &set_configuration_report(configuration)?)?;
+
+    let response = exchange(transport, &Report::empty(Command::GetConfiguration))?;
+    if configuration_from_report(&response)? != *configuration {
+        return Err(SyncError::VerificationFailed);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply_configuration, check_capabilities, SyncError};
+    use crate::{
+        device::{
+            capabilities::DeviceCapabilities,
+            protocol::{configuration::encode_configuration, Command, NackReason, Report},
+            simulated::SimulatedDevice,
+            transport::{loopback::LoopbackTransport, DeviceTransport, TransportError},
+            DeviceModel,
+        },
+        profile::{
+            colour::Rgb,
+            keycode::Keycode,
+            macros::{Macro, MacroEvent, MacroStep, RepeatMode},
+            mouse_profile::{DpiStage, MouseProfile},
+            ProfileConfiguration,
+        },
+    };
+
+    #[test]
+    fn applies_and_verifies_configuration() {
+        let device = SimulatedDevice::new(DeviceModel::new("M1"), "serial");
+        let mut transport = device.transport();
+        transport.open().unwrap();
+
+        let configuration = ProfileConfiguration::Mouse(MouseProfile::new(3200));
+        apply_configuration(&mut transport, &configuration, &[]).unwrap();
+        assert_eq!(device.get_configuration(), configuration);
+    }
+
+    #[test]
+    fn uploads_macros_before_configuration() {
+        let device = SimulatedDevice::new(DeviceModel::new("M1"), "serial");
+        let mut transport = device.transport();
+        transport.open().unwrap();
+
+        let steps = vec![MacroStep {
+            event: MacroEvent::KeyPress(Keycode::Enter),
+            delay_ms: 5,
+        }];
+        let recorded = Macro::new("enter", steps, RepeatMode::Toggle);
+        let configuration = ProfileConfiguration::Mouse(MouseProfile::new(3200));
+        apply_configuration(
+            &mut transport,
+            &configuration,
+            std::slice::from_ref(&recorded),
+        )
+        .unwrap();
+
+        let stored = device.get_macro(recorded.get_id()).unwrap();
+        assert_eq!(stored.steps, recorded.get_steps());
+        assert_eq!(stored.repeat, RepeatMode::Toggle);
+
+        // Macros no longer referenced are removed from the device.
+        apply_configuration(&mut transport, &configuration, &[]).unwrap();
+        assert!(device.get_macro(recorded.get_id()).is_none());
+    }
+
+    #[test]
+    fn surfaces_device_failures() {
+        let device = SimulatedDevice::new(DeviceModel::new("M1"), "serial");
+        let mut transport = device.transport();
+        transport.open().unwrap();
+        let configuration = ProfileConfiguration::Mouse(MouseProfile::new(3200));
+
+        device.fail_next_writes(1);
+        assert!(matches!(
+            apply_configuration(&mut transport, &configuration, &[]),
+            Err(SyncError::TransportError(TransportError::IOError { .. }))
+        ));
+
+        device.respond_with_error(NackReason::DeviceError);
+        assert!(matches!(
+            apply_configuration(&mut transport, &configuration, &[]),
+            Err(SyncError::Rejected(NackReason::DeviceError))
+        ));
+    }
+
+    #[test]
+    fn detects_mismatched_read_back() {
+        let mut transport = LoopbackTransport::without_echo();
+        transport.open().unwrap();
+
+        let written = ProfileConfiguration::Mouse(MouseProfile::new(3200));
+        let stored = ProfileConfiguration::Mouse(MouseProfile::new(800));
+        for _ in 0..2 {
+            transport.queue_response(Report::empty(Command::Ack).encode().unwrap());
+        }
+        transport.queue_response(
+            Report::new(
+                Command::Configuration,
+                encode_configuration(&stored).unwrap(),
+            )
+            .encode()
+            .unwrap(),
+        );
+
+        assert!(matches!(
+            apply_configuration(&mut transport, &written, &[]),
+            Err(SyncError::VerificationFailed)
+        ));
+    }
+
+    #[test]
+    fn ignores_stale_responses() {
+        let mut transport = LoopbackTransport::without_echo();
+        transport.open().unwrap();
+
+        // A rejection that arrived after an earlier exchange had timed out.
+        transport.queue_report(
+            Report::new(Command::Nack, vec![NackReason::DeviceError as u8])
+                .encode()
+                .unwrap(),
+        );
+        let configuration = ProfileConfiguration::Mouse(MouseProfile::new(3200));
+        for _ in 0..2 {
+            transport.queue_response(Report::empty(Command::Ack).encode().unwrap());
+        }
+        transport.queue_response(
+            Report::new(
+                Command::Configuration,
+                encode_configuration(&configuration).unwrap(),
+            )
+            .encode()
+            .unwrap(),
+        );
+
+        apply_configuration(&mut transport, &configuration, &[]).unwrap();
+    }
+
+    #[test]
+    fn checks_device_capabilities() {
+        let configuration = ProfileConfiguration::Mouse(MouseProfile::new(3200));
+        let mut capabilities = DeviceCapabilities::for_model(&DeviceModel::new("M1"))
+            .unwrap()
+            .clone();
+        check_capabilities(&capabilities, &configuration, &[]).unwrap();
+
+        let stages = vec![DpiStage::new(800, Rgb::new(0, 0, 0)); 64];
+        let oversized =
+            ProfileConfiguration::Mouse(MouseProfile::default().with_dpi_stages(stages, 0));
+        assert!(matches!(
+            check_capabilities(&capabilities, &oversized, &[]),
+            Err(SyncError::InvalidConfiguration(_))
+        ));
+
+        capabilities.onboard_memory_bytes = 8;
+        assert!(matches!(
+            check_capabilities(&capabilities, &configuration, &[]),
+            Err(SyncError::InsufficientMemory { available: 8, .. })
+        ));
+
+        capabilities.protocol_versions = vec![];
+        assert!(matches!(
+            check_capabilities(&capabilities, &configuration, &[]),
+            Err(SyncError::UnsupportedProtocol(_))
+        ));
+    }
+}
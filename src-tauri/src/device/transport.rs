@@ -0,0 +1,60 @@
+use std::{fmt::Debug, io, time::Duration};
+
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use thiserror::Error;
+
+#[cfg(target_os = "linux")]
+pub mod hidraw;
+pub mod loopback;
+
+#[derive(Debug, Error, Serialize, Deserialize, Type)]
+pub(crate) enum TransportError {
+    #[error("NotOpen: The transport has not been opened")]
+    NotOpen,
+
+    #[error("Disconnected: The device is no longer attached")]
+    Disconnected,
+
+    #[error("Timeout: No report received within {0} ms")]
+    Timeout(u64),
+
+    #[error("ReportTooLarge: {size} bytes exceeds the maximum report size of {max} bytes")]
+    ReportTooLarge { size: usize, max: usize },
+
+    #[error("IOError: {message}")]
+    IOError { message: String },
+}
+
+impl From<io::Error> for TransportError {
+    fn from(e: io::Error) -> Self {
+        Self::IOError {
+            message: e.to_string(),
+        }
+    }
+}
+
+/// A `DeviceTransport` is the channel through which raw reports are exchanged with a device.
+/// The transport knows nothing about the contents of a report; encoding and decoding is left
+/// to the caller.
+///
+/// Implementations must be `Send + Sync` as they are owned by the [`DeviceManager`][DeviceManager]
+/// which lives inside the shared application state.
+///
+/// [DeviceManager]: crate::device::device_manager::DeviceManager
+pub(crate) trait DeviceTransport: Debug + Send + Sync {
+    /// Open the underlying channel. Opening an already open transport is a no-op.
+    fn open(&mut self) -> Result<(), TransportError>;
+
+    /// Block until a report is received or the `timeout` elapses.
+    fn read_report(&mut self, timeout: Duration) -> Result<Bytes, TransportError>;
+
+    /// Send a single report to the device.
+    fn write_report(&mut self, report: &[u8]) -> Result<(), TransportError>;
+
+    /// Close the underlying channel. Closing an already closed transport is a no-op.
+    fn close(&mut self) -> Result<(), TransportError>;
+
+    fn is_open(&self) -> bool;
+}
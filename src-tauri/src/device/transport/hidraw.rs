@@ -0,0 +1,119 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Write},
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use tracing::{instrument, warn};
+
+use super::{DeviceTransport, TransportError};
+
+/// The largest report the hidraw driver will hand to user space.
+pub(crate) const MAX_REPORT_SIZE: usize = 4096;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(2);
+
+/// A [`DeviceTransport`] backed by a Linux hidraw node (e.g. `/dev/hidraw3`).
+#[derive(Debug)]
+pub(crate) struct HidrawTransport {
+    path: PathBuf,
+    file: Option<File>,
+}
+
+impl HidrawTransport {
+    pub(crate) fn new(path: impl AsRef<Path>) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            file: None,
+        }
+    }
+
+    pub(crate) fn get_path(&self) -> &Path {
+        &self.path
+    }
+
+    fn file_mut(&mut self) -> Result<&mut File, TransportError> {
+        self.file.as_mut().ok_or(TransportError::NotOpen)
+    }
+}
+
+fn map_io_error(e: io::Error) -> TransportError {
+    match e.raw_os_error() {
+        Some(libc::ENODEV) | Some(libc::ENOENT) => TransportError::Disconnected,
+        _ => e.into(),
+    }
+}
+
+impl DeviceTransport for HidrawTransport {
+    #[instrument(skip(self), fields(path = ?self.path))]
+    fn open(&mut self) -> Result<(), TransportError> {
+        if self.file.is_some() {
+            return Ok(());
+        }
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(&self.path)
+            .map_err(|e| {
+                warn!(e=%e, "Could not open hidraw node.");
+                map_io_error(e)
+            })?;
+
+        self.file = Some(file);
+        Ok(())
+    }
+
+    fn read_report(&mut self, timeout: Duration) -> Result<Bytes, TransportError> {
+        let deadline = Instant::now() + timeout;
+        let file = self.file_mut()?;
+        let mut buffer = [0u8; MAX_REPORT_SIZE];
+
+        loop {
+            match file.read(&mut buffer) {
+                Ok(0) => return Err(TransportError::Disconnected),
+                Ok(n) => return Ok(Bytes::copy_from_slice(&buffer[..n])),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    if Instant::now() >= deadline {
+                        return Err(TransportError::Timeout(timeout.as_millis() as u64));
+                    }
+                    thread::sleep(POLL_INTERVAL);
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(map_io_error(e)),
+            }
+        }
+    }
+
+    fn write_report(&mut self, report: &[u8]) -> Result<(), TransportError> {
+        if report.len() > MAX_REPORT_SIZE {
+            return Err(TransportError::ReportTooLarge {
+                size: report.len(),
+                max: MAX_REPORT_SIZE,
+            });
+        }
+
+        // hidraw expects each report in a single write call.
+        let written = self.file_mut()?.write(report).map_err(map_io_error)?;
+        if written != report.len() {
+            return Err(TransportError::IOError {
+                message: format!("Short write: {written} of {} bytes", report.len()),
+            });
+        }
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<(), TransportError> {
+        self.file = None;
+        Ok(())
+    }
+
+    fn is_open(&self) -> bool {
+        self.file.is_some()
+    }
+}
@@ -0,0 +1,114 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use bytes::Bytes;
+
+use super::{DeviceTransport, TransportError};
+
+#[derive(Debug, Default)]
+struct LoopbackState {
+    open: bool,
+    inbound: VecDeque<Bytes>,
+    /// Reports queued to be read back once the next report is written, like a device's
+    /// response.
+    responses: VecDeque<Bytes>,
+    written: Vec<Bytes>,
+}
+
+/// An in-memory [`DeviceTransport`]. Every written report is recorded and, when echo is
+/// enabled, queued to be read back. Clones share the same state so a test can keep a handle
+/// to a transport after handing it over to the [`DeviceManager`][DeviceManager].
+///
+/// [DeviceManager]: crate::device::device_manager::DeviceManager
+#[derive(Clone, Debug)]
+pub(crate) struct LoopbackTransport {
+    state: Arc<Mutex<LoopbackState>>,
+    echo: bool,
+}
+
+impl LoopbackTransport {
+    pub(crate) fn new() -> Self {
+        Self {
+            state: Arc::default(),
+            echo: true,
+        }
+    }
+
+    pub(crate) fn without_echo() -> Self {
+        Self {
+            echo: false,
+            ..Self::new()
+        }
+    }
+
+    /// Queue a report to be returned by the next call to `read_report`.
+    pub(crate) fn queue_report(&self, report: impl Into<Bytes>) {
+        self.state.lock().unwrap().inbound.push_back(report.into());
+    }
+
+    /// Queue a report to be returned by `read_report` once the next report is written.
+    pub(crate) fn queue_response(&self, report: impl Into<Bytes>) {
+        self.state
+            .lock()
+            .unwrap()
+            .responses
+            .push_back(report.into());
+    }
+
+    pub(crate) fn get_written_reports(&self) -> Vec<Bytes> {
+        self.state.lock().unwrap().written.clone()
+    }
+}
+
+impl Default for LoopbackTransport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DeviceTransport for LoopbackTransport {
+    fn open(&mut self) -> Result<(), TransportError> {
+        self.state.lock().unwrap().open = true;
+        Ok(())
+    }
+
+    fn read_report(&mut self, timeout: Duration) -> Result<Bytes, TransportError> {
+        let mut state = self.state.lock().unwrap();
+        if !state.open {
+            return Err(TransportError::NotOpen);
+        }
+        state
+            .inbound
+            .pop_front()
+            .ok_or(TransportError::Timeout(timeout.as_millis() as u64))
+    }
+
+    fn write_report(&mut self, report: &[u8]) -> Result<(), TransportError> {
+        let mut state = self.state.lock().unwrap();
+        if !state.open {
+            return Err(TransportError::NotOpen);
+        }
+
+        let report = Bytes::copy_from_slice(report);
+        if self.echo {
+            state.inbound.push_back(report.clone());
+        }
+        if let Some(response) = state.responses.pop_front() {
+            state.inbound.push_back(response);
+        }
+        state.written.push(report);
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<(), TransportError> {
+        self.state.lock().unwrap().open = false;
+        Ok(())
+    }
+
+    fn is_open(&self) -> bool {
+        self.state.lock().unwrap().open
+    }
+}
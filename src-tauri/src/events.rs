@@ -0,0 +1,19 @@
+//! Events emitted to the frontend.
+
+use tauri::{AppHandle, Manager};
+use tracing::error;
+
+use crate::device::Device;
+
+pub(crate) const DEVICE_CONNECTED: &str = "device-connected";
+pub(crate) const DEVICE_DISCONNECTED: &str = "device-disconnected";
+pub(crate) const DEVICE_DISCOVERED: &str = "device-discovered";
+pub(crate) const DEVICE_SYNC_STATUS_CHANGED: &str = "device-sync-status-changed";
+pub(crate) const PROFILE_ACTIVATED: &str = "profile-activated";
+
+/// Emit an event carrying a [`Device`] to every window, logging failures.
+pub(crate) fn emit_device_event(app_handle: &AppHandle, event: &str, device: &Device) {
+    if let Err(e) = app_handle.emit_all(event, device) {
+        error!(e=%e, "Could not emit {event} event.");
+    }
+}
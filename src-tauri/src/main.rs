@@ -1,12 +1,15 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+use specta::ts::{BigIntExportBehavior, ExportConfiguration};
 use tauri::async_runtime::RwLock;
 use tauri_specta::ts;
 use tracing::error;
 
+pub mod archive;
 pub mod commands;
 pub mod device;
+pub mod events;
 pub mod profile;
 pub mod state;
 pub mod storage_manager;
@@ -14,21 +17,54 @@ pub mod storage_manager;
 mod start_up;
 
 fn main() { 
-    ts::export(commands::export_commands(), "../src/bindings.ts").unwrap();
+    // Timestamps and sizes are u64 but stay well below 2^53, so they are safe as numbers.
+    ts::export_with_cfg(
+        commands::export_commands().unwrap(),
+        ExportConfiguration::new().bigint(BigIntExportBehavior::Number),
+        "../src/bindings.ts",
+    )
+    .unwrap();
 
     let _ = tracing_subscriber::fmt().pretty().init();
-    let state = RwLock::new(start_up::run());
+    let (state, discovery_service) = start_up::run();
 
     tauri::Builder::default()
-        .manage(state)
+        .manage(RwLock::new(state))
+        .setup(|app| {
+            device::discovery::spawn(app.handle(), discovery_service);
+            Ok(())
+        })
         .invoke_handler(tauri::generate_handler![
             commands::device_commands::delete_device,
             commands::device_commands::get_connected_devices,
+            commands::device_commands::get_unregistered_devices,
+            commands::device_commands::register_device,
+            commands::lighting_commands::preview_lighting,
+            commands::macro_commands::get_macros,
+            commands::macro_commands::insert_macro,
+            commands::macro_commands::record_macro,
+            commands::macro_commands::delete_macro,
             commands::profile_commands::get_profile,
+            commands::profile_commands::get_active_profile,
             commands::profile_commands::get_device_profile_ids,
+            commands::profile_commands::set_active_profile,
             commands::profile_commands::insert_profile,
             commands::profile_commands::overwrite_profile,
             commands::profile_commands::delete_profile,
+            commands::profile_commands::get_library_profiles,
+            commands::profile_commands::add_library_profile,
+            commands::profile_commands::assign_profile,
+            commands::profile_commands::delete_library_profile,
+            commands::profile_commands::export_profile,
+            commands::profile_commands::import_profile,
+            commands::archive_commands::export_configuration_archive,
+            commands::archive_commands::preview_configuration_archive,
+            commands::archive_commands::restore_configuration_archive,
+            commands::storage_commands::take_recovery_notices,
+            commands::storage_commands::list_backups,
+            commands::storage_commands::restore_backup,
+            commands::storage_commands::get_backup_policy,
+            commands::storage_commands::set_backup_policy,
         ])
         .run(tauri::generate_context!())
         .map_err(|e| error!(e=%e, "FATAL ERROR: Application crashed. {e}"))
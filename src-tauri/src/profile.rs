@@ -1,45 +1,158 @@
+use std::{collections::HashMap, fmt::Display, ops::RangeInclusive};
+
 use serde::{Deserialize, Serialize};
 use specta::Type;
+use thiserror::Error;
 use uuid::Uuid;
 
-use crate::device::{DeviceId, DeviceModel};
+use crate::device::{capabilities::DeviceCapabilities, DeviceModel};
 
+pub mod colour;
 mod default_profile_provider;
 pub mod keyboard_profile;
+pub mod keycode;
+pub mod lighting;
+pub mod macros;
+pub mod metadata;
 pub mod mouse_profile;
+pub mod portable;
 pub mod profile_manager;
 
 use default_profile_provider::DefaultProfileProvider;
-use keyboard_profile::KeyboardProfile;
-use mouse_profile::MouseProfile;
+use keyboard_profile::{KeyAction, KeyboardProfile};
+use macros::MacroId;
+use metadata::ProfileMetadata;
+use mouse_profile::{ButtonAction, MouseProfile};
 
 pub(crate) type ProfileId = String;
 
-#[derive(Clone, Debug, Serialize, Deserialize, Type)]
+/// A configuration that cannot be applied to a device.
+#[derive(Debug, Error, Serialize, Deserialize, Type)]
+pub(crate) enum ConfigurationError {
+    #[error("ModelMismatch: The configuration cannot be applied to a {0}")]
+    ModelMismatch(String),
+
+    #[error("UnknownModel: There is no definition of {0}")]
+    UnknownModel(String),
+
+    #[error("OutOfRange: {field} is {value}, expected {min} to {max}")]
+    OutOfRange {
+        field: String,
+        value: String,
+        min: String,
+        max: String,
+    },
+
+    #[error("Unsupported: {field} does not support {value}")]
+    Unsupported { field: String, value: String },
+
+    #[error("Invalid: {0}")]
+    Invalid(String),
+}
+
+impl ConfigurationError {
+    pub(crate) fn out_of_range<T: Display>(field: &str, value: T, range: &RangeInclusive<T>) -> Self {
+        Self::OutOfRange {
+            field: field.to_string(),
+            value: value.to_string(),
+            min: range.start().to_string(),
+            max: range.end().to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Type)]
 pub(crate) enum ProfileConfiguration {
     Mouse(MouseProfile),
     Keyboard(KeyboardProfile),
 }
 
+impl ProfileConfiguration {
+    /// Check that the configuration can be applied to a device of the given model.
+    pub(crate) fn validate(&self, model: &DeviceModel) -> Result<(), ConfigurationError> {
+        let capabilities = DeviceCapabilities::for_model(model)
+            .ok_or_else(|| ConfigurationError::UnknownModel(model.to_string()))?;
+        self.validate_for(capabilities)
+    }
+
+    /// Check that the configuration fits a model described by `capabilities`.
+    pub(crate) fn validate_for(
+        &self,
+        capabilities: &DeviceCapabilities,
+    ) -> Result<(), ConfigurationError> {
+        match (self, &capabilities.mouse, &capabilities.keyboard) {
+            (Self::Mouse(mouse), Some(limits), _) => {
+                mouse.validate(limits, capabilities.profile_slots)
+            }
+            (Self::Keyboard(keyboard), _, Some(matrix)) => keyboard.validate(matrix),
+            _ => Err(ConfigurationError::ModelMismatch(
+                capabilities.model.to_string(),
+            )),
+        }
+    }
+
+    /// Replace the macros bound to keys or buttons according to `macro_ids`, for a
+    /// configuration whose macros were given new ids.
+    pub(crate) fn remap_macros(&mut self, macro_ids: &HashMap<MacroId, MacroId>) {
+        match self {
+            Self::Mouse(mouse) => mouse.remap_macros(macro_ids),
+            Self::Keyboard(keyboard) => keyboard.remap_macros(macro_ids),
+        }
+    }
+
+    /// The ids of the macros bound to a key or button, without duplicates.
+    pub(crate) fn get_macro_ids(&self) -> Vec<&MacroId> {
+        let mut macro_ids: Vec<&MacroId> = match self {
+            Self::Mouse(mouse) => mouse
+                .get_buttons()
+                .iter()
+                .filter_map(|binding| match &binding.action {
+                    ButtonAction::Macro(macro_id) => Some(macro_id),
+                    _ => None,
+                })
+                .collect(),
+            Self::Keyboard(keyboard) => keyboard
+                .get_layers()
+                .iter()
+                .flat_map(|layer| &layer.bindings)
+                .filter_map(|binding| match &binding.action {
+                    KeyAction::Macro(macro_id) => Some(macro_id),
+                    _ => None,
+                })
+                .collect(),
+        };
+        macro_ids.sort();
+        macro_ids.dedup();
+        macro_ids
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, Type)]
 pub(crate) struct Profile {
     id: ProfileId,
-    device_id: DeviceId,
+    #[serde(default)]
+    metadata: ProfileMetadata,
     configuration: ProfileConfiguration,
 }
 
 impl Profile {
-    pub(crate) fn new(device_id: &DeviceId, configuration: ProfileConfiguration) -> Self {
+    pub(crate) fn new(configuration: ProfileConfiguration) -> Self {
         Self {
             id: Uuid::new_v4().to_string(),
-            device_id: device_id.to_string(),
+            metadata: ProfileMetadata::default(),
             configuration,
         }
     }
 
+    pub(crate) fn with_metadata(mut self, metadata: ProfileMetadata) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    /// `None` for models without a definition.
     pub(crate) fn get_default_provide_configuration(
         device_model: DeviceModel,
-    ) -> ProfileConfiguration {
+    ) -> Option<ProfileConfiguration> {
         DefaultProfileProvider::profile(device_model)
     }
 
@@ -47,8 +160,8 @@ impl Profile {
         &self.id
     }
 
-    pub(crate) fn get_device_id(&self) -> &DeviceId {
-        &self.device_id
+    pub(crate) fn get_metadata(&self) -> &ProfileMetadata {
+        &self.metadata
     }
 
     pub(crate) fn get_configuration(&self) -> &ProfileConfiguration {
@@ -0,0 +1,67 @@
+use serde::{Deserialize, Serialize};
+use specta::Type;
+
+/// A 24-bit RGB colour.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Deserialize, Serialize, Type)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Rgb {
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+
+    /// The fully saturated colour of a hue, given as a fraction of a turn of the colour wheel
+    /// starting at red.
+    pub fn from_hue(hue: f32) -> Self {
+        let sector = hue.rem_euclid(1.0) * 6.0;
+        let rising = (sector.fract() * 255.0).round() as u8;
+        let falling = 255 - rising;
+
+        match sector as u8 {
+            0 => Self::new(255, rising, 0),
+            1 => Self::new(falling, 255, 0),
+            2 => Self::new(0, 255, rising),
+            3 => Self::new(0, falling, 255),
+            4 => Self::new(rising, 0, 255),
+            _ => Self::new(255, 0, falling),
+        }
+    }
+
+    /// Blend towards `other`, `t` being 0 for this colour and 1 for `other`.
+    pub fn lerp(self, other: Self, t: f32) -> Self {
+        let t = t.clamp(0.0, 1.0);
+        let mix = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * t).round() as u8;
+        Self::new(
+            mix(self.r, other.r),
+            mix(self.g, other.g),
+            mix(self.b, other.b),
+        )
+    }
+
+    /// Dim the colour, `factor` being 0 for black and 1 for the colour itself.
+    pub fn scale(self, factor: f32) -> Self {
+        Self::new(0, 0, 0).lerp(self, factor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Rgb;
+
+    #[test]
+    fn blends_colours() {
+        assert_eq!(Rgb::from_hue(0.0), Rgb::new(255, 0, 0));
+        assert_eq!(Rgb::from_hue(1.0 / 3.0), Rgb::new(0, 255, 0));
+        assert_eq!(Rgb::from_hue(2.0 / 3.0), Rgb::new(0, 0, 255));
+        assert_eq!(Rgb::from_hue(1.5), Rgb::new(0, 255, 255));
+
+        let white = Rgb::new(255, 255, 255);
+        assert_eq!(Rgb::default().lerp(white, 0.5), Rgb::new(128, 128, 128));
+        assert_eq!(white.scale(0.0), Rgb::default());
+        assert_eq!(white.scale(2.0), white);
+    }
+}
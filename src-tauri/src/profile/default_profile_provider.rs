@@ -1,13 +1,21 @@
-use super::{keyboard_profile::KeyboardProfile, mouse_profile::MouseProfile, ProfileConfiguration};
+use super::{
+    keyboard_profile::{KeyboardMatrix, KeyboardProfile},
+    mouse_profile::{MouseLimits, MouseProfile},
+    ProfileConfiguration,
+};
 use crate::device::DeviceModel;
 
 pub(super) struct DefaultProfileProvider;
 
 impl DefaultProfileProvider {
-    pub(super) fn profile(model: DeviceModel) -> ProfileConfiguration {
-        match model {
-            DeviceModel::K1 => ProfileConfiguration::Keyboard(KeyboardProfile {}),
-            DeviceModel::M1 => ProfileConfiguration::Mouse(MouseProfile { dpi: 800 }),
+    /// The factory profile described by the model's definition. `None` for models without a
+    /// definition.
+    pub(super) fn profile(model: DeviceModel) -> Option<ProfileConfiguration> {
+        if let Some(limits) = MouseLimits::for_model(&model) {
+            Some(ProfileConfiguration::Mouse(MouseProfile::factory(&limits)))
+        } else {
+            KeyboardMatrix::for_model(&model)
+                .map(|matrix| ProfileConfiguration::Keyboard(KeyboardProfile::factory(&matrix)))
         }
     }
 }
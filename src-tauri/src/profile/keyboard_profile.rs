@@ -1,5 +1,334 @@
-use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Deserializer, Serialize};
 use specta::Type;
 
-#[derive(Clone, Debug, Deserialize, Serialize, Type)]
-pub(crate) struct KeyboardProfile {}
+use super::{
+    colour::Rgb,
+    keycode::{Keycode, MediaKey, Modifiers},
+    lighting::{Lighting, LightingEffect, LightingLayout},
+    macros::MacroId,
+    ConfigurationError,
+};
+use crate::device::{capabilities::DeviceCapabilities, DeviceModel};
+
+/// What a key does when pressed.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize, Type)]
+pub enum KeyAction {
+    /// The key does nothing.
+    None,
+    /// Fall through to the next active layer below.
+    Transparent,
+    Key(Keycode),
+    /// A key pressed with modifiers held down, or modifiers alone.
+    Combo {
+        modifiers: Modifiers,
+        key: Option<Keycode>,
+    },
+    /// Activate a layer while the key is held.
+    LayerMomentary(u8),
+    /// Turn a layer on or off.
+    LayerToggle(u8),
+    Media(MediaKey),
+    /// Play back a macro of the device.
+    Macro(MacroId),
+}
+
+/// The action of the key at a position of the keyboard matrix.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize, Type)]
+pub struct KeyBinding {
+    pub row: u8,
+    pub col: u8,
+    pub action: KeyAction,
+}
+
+/// A set of key bindings. Keys without a binding do nothing on the base layer and are
+/// transparent on every other layer.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize, Type)]
+pub struct Layer {
+    pub bindings: Vec<KeyBinding>,
+}
+
+/// The physical key matrix of a keyboard model, read from the model's [`DeviceCapabilities`].
+/// `default_layout` holds the factory action of every position, `None` marking positions
+/// without a key, and `factory_layers` the factory layers stacked above it.
+#[derive(Clone, Debug, Deserialize)]
+pub(crate) struct KeyboardMatrix {
+    pub max_layers: usize,
+    pub default_layout: Vec<Vec<Option<KeyAction>>>,
+    #[serde(default)]
+    pub factory_layers: Vec<Layer>,
+}
+
+impl KeyboardMatrix {
+    /// `None` for models that are not keyboards.
+    pub(crate) fn for_model(model: &DeviceModel) -> Option<Self> {
+        DeviceCapabilities::for_model(model)?.keyboard.clone()
+    }
+
+    pub(crate) fn has_key(&self, row: u8, col: u8) -> bool {
+        matches!(
+            self.default_layout
+                .get(row as usize)
+                .and_then(|keys| keys.get(col as usize)),
+            Some(Some(_))
+        )
+    }
+
+    pub(crate) fn key_count(&self) -> usize {
+        self.default_layout
+            .iter()
+            .map(|keys| keys.iter().flatten().count())
+            .sum()
+    }
+
+    /// The factory base layer.
+    pub(crate) fn default_layer(&self) -> Layer {
+        let bindings = self
+            .default_layout
+            .iter()
+            .enumerate()
+            .flat_map(|(row, keys)| {
+                keys.iter().enumerate().filter_map(move |(col, action)| {
+                    action.clone().map(|action| KeyBinding {
+                        row: row as u8,
+                        col: col as u8,
+                        action,
+                    })
+                })
+            })
+            .collect();
+
+        Layer { bindings }
+    }
+
+    /// The factory keymap: the base layer and the factory layers above it.
+    pub(crate) fn factory_keymap(&self) -> Vec<Layer> {
+        [self.default_layer()]
+            .into_iter()
+            .chain(self.factory_layers.iter().cloned())
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Type)]
+pub(crate) struct KeyboardProfile {
+    layers: Vec<Layer>,
+    lighting: Lighting,
+}
+
+impl Default for KeyboardProfile {
+    /// The K1 factory keymap and lighting, from the bundled definition since a user
+    /// definition may replace the K1.
+    fn default() -> Self {
+        let matrix = DeviceCapabilities::bundled(&DeviceModel::new("K1"))
+            .and_then(|capabilities| capabilities.keyboard.as_ref())
+            .unwrap();
+        Self::factory(matrix)
+    }
+}
+
+impl KeyboardProfile {
+    /// The factory profile of a keyboard model.
+    pub(crate) fn factory(matrix: &KeyboardMatrix) -> Self {
+        Self::new(matrix.factory_keymap())
+    }
+
+    /// A profile with the given keymap and the factory lighting.
+    pub(crate) fn new(layers: Vec<Layer>) -> Self {
+        Self {
+            layers,
+            lighting: Lighting {
+                effect: LightingEffect::Wave,
+                palette: vec![
+                    Rgb::new(0xff, 0x00, 0x00),
+                    Rgb::new(0x00, 0xff, 0x00),
+                    Rgb::new(0x00, 0x00, 0xff),
+                ],
+                ..Lighting::default()
+            },
+        }
+    }
+
+    pub(crate) fn with_lighting(mut self, lighting: Lighting) -> Self {
+        self.lighting = lighting;
+        self
+    }
+
+    pub(crate) fn get_layers(&self) -> &[Layer] {
+        &self.layers
+    }
+
+    /// Replace the macros bound to keys according to `macro_ids`.
+    pub(crate) fn remap_macros(&mut self, macro_ids: &HashMap<MacroId, MacroId>) {
+        for binding in self.layers.iter_mut().flat_map(|layer| &mut layer.bindings) {
+            if let KeyAction::Macro(macro_id) = &mut binding.action {
+                if let Some(new_id) = macro_ids.get(macro_id) {
+                    *macro_id = new_id.to_string();
+                }
+            }
+        }
+    }
+
+    pub(crate) fn get_lighting(&self) -> &Lighting {
+        &self.lighting
+    }
+
+    /// Check the keymap against the matrix of a keyboard model.
+    pub(crate) fn validate(&self, matrix: &KeyboardMatrix) -> Result<(), ConfigurationError> {
+        if self.layers.is_empty() || self.layers.len() > matrix.max_layers {
+            return Err(ConfigurationError::out_of_range(
+                "layers",
+                self.layers.len(),
+                &(1..=matrix.max_layers),
+            ));
+        }
+
+        for (index, layer) in self.layers.iter().enumerate() {
+            let mut positions = HashSet::new();
+
+            for binding in &layer.bindings {
+                let position = format!("row {} column {}", binding.row, binding.col);
+                if !matrix.has_key(binding.row, binding.col) {
+                    return Err(ConfigurationError::Unsupported {
+                        field: "key".to_string(),
+                        value: position,
+                    });
+                }
+                if !positions.insert((binding.row, binding.col)) {
+                    return Err(ConfigurationError::Invalid(format!(
+                        "Layer {index} binds {position} more than once"
+                    )));
+                }
+
+                match binding.action {
+                    KeyAction::Transparent if index == 0 => {
+                        return Err(ConfigurationError::Invalid(format!(
+                            "The base layer cannot be transparent at {position}"
+                        )))
+                    }
+                    KeyAction::LayerMomentary(target) | KeyAction::LayerToggle(target)
+                        if target as usize >= self.layers.len() =>
+                    {
+                        return Err(ConfigurationError::out_of_range(
+                            "layer",
+                            target as usize,
+                            &(0..=self.layers.len() - 1),
+                        ))
+                    }
+                    KeyAction::Combo {
+                        modifiers,
+                        key: None,
+                    } if modifiers.is_empty() => {
+                        return Err(ConfigurationError::Invalid(format!(
+                            "Empty key combination at {position}"
+                        )))
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        self.lighting.validate(&LightingLayout::per_key(matrix))
+    }
+}
+
+/// Mirror of [`KeyboardProfile`] used for deserialization. Profiles stored before keymaps
+/// or lighting existed get the factory keymap and lighting.
+#[derive(Deserialize)]
+struct StoredKeyboardProfile {
+    layers: Option<Vec<Layer>>,
+    lighting: Option<Lighting>,
+}
+
+impl<'de> Deserialize<'de> for KeyboardProfile {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let stored = StoredKeyboardProfile::deserialize(deserializer)?;
+        let default = KeyboardProfile::default();
+
+        Ok(Self {
+            layers: stored.layers.unwrap_or(default.layers),
+            lighting: stored.lighting.unwrap_or(default.lighting),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{KeyAction, KeyBinding, KeyboardMatrix, KeyboardProfile, Layer};
+    use crate::{
+        device::DeviceModel,
+        profile::{
+            keycode::{Keycode, Modifiers},
+            ProfileConfiguration,
+        },
+    };
+
+    #[test]
+    fn default_keymap_is_valid() {
+        let matrix = KeyboardMatrix::for_model(&DeviceModel::new("K1")).unwrap();
+        assert_eq!(matrix.key_count(), 61);
+        KeyboardProfile::default().validate(&matrix).unwrap();
+    }
+
+    #[test]
+    fn rejects_invalid_keymaps() {
+        let matrix = KeyboardMatrix::for_model(&DeviceModel::new("K1")).unwrap();
+        let layer = |action, row, col| Layer {
+            bindings: vec![KeyBinding { row, col, action }],
+        };
+        let key = KeyAction::Key(Keycode::A);
+
+        let invalid = [
+            vec![],
+            vec![Layer::default(); matrix.max_layers + 1],
+            vec![layer(key.clone(), 4, 13)],
+            vec![layer(key.clone(), 9, 0)],
+            vec![layer(KeyAction::Transparent, 0, 0)],
+            vec![layer(KeyAction::LayerToggle(1), 0, 0)],
+            vec![layer(
+                KeyAction::Combo {
+                    modifiers: Modifiers::default(),
+                    key: None,
+                },
+                0,
+                0,
+            )],
+            vec![Layer {
+                bindings: vec![
+                    KeyBinding {
+                        row: 0,
+                        col: 0,
+                        action: key,
+                    };
+                    2
+                ],
+            }],
+        ];
+
+        for layers in invalid {
+            assert!(
+                KeyboardProfile::new(layers.clone())
+                    .validate(&matrix)
+                    .is_err(),
+                "{layers:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn round_trips_through_json() {
+        let configuration = ProfileConfiguration::Keyboard(KeyboardProfile::default());
+        let json = serde_json::to_string(&configuration).unwrap();
+        assert_eq!(
+            serde_json::from_str::<ProfileConfiguration>(&json).unwrap(),
+            configuration
+        );
+
+        let legacy: KeyboardProfile = serde_json::from_str("{}").unwrap();
+        assert_eq!(legacy, KeyboardProfile::default());
+    }
+}
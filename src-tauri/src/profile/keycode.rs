@@ -0,0 +1,167 @@
+//! Keys and modifiers shared by keyboard keymaps and mouse button bindings. Keycodes map to
+//! usages of the USB HID keyboard (0x07) and consumer (0x0c) usage pages.
+
+use serde::{Deserialize, Serialize};
+use specta::Type;
+
+/// Defines [`Keycode`] along with its conversion to and from HID usage ids.
+macro_rules! keycodes {
+    ($($key:ident = $usage:literal),* $(,)?) => {
+        #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize, Type)]
+        pub enum Keycode {
+            $($key),*
+        }
+
+        impl Keycode {
+            /// The usage id on the HID keyboard page.
+            pub fn usage(&self) -> u8 {
+                match self {
+                    $(Self::$key => $usage),*
+                }
+            }
+
+            pub fn from_usage(usage: u8) -> Option<Self> {
+                match usage {
+                    $($usage => Some(Self::$key),)*
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+keycodes! {
+    A = 0x04, B = 0x05, C = 0x06, D = 0x07, E = 0x08, F = 0x09, G = 0x0a, H = 0x0b,
+    I = 0x0c, J = 0x0d, K = 0x0e, L = 0x0f, M = 0x10, N = 0x11, O = 0x12, P = 0x13,
+    Q = 0x14, R = 0x15, S = 0x16, T = 0x17, U = 0x18, V = 0x19, W = 0x1a, X = 0x1b,
+    Y = 0x1c, Z = 0x1d,
+    Digit1 = 0x1e, Digit2 = 0x1f, Digit3 = 0x20, Digit4 = 0x21, Digit5 = 0x22,
+    Digit6 = 0x23, Digit7 = 0x24, Digit8 = 0x25, Digit9 = 0x26, Digit0 = 0x27,
+    Enter = 0x28, Escape = 0x29, Backspace = 0x2a, Tab = 0x2b, Space = 0x2c,
+    Minus = 0x2d, Equal = 0x2e, LeftBracket = 0x2f, RightBracket = 0x30, Backslash = 0x31,
+    Semicolon = 0x33, Quote = 0x34, Grave = 0x35, Comma = 0x36, Period = 0x37, Slash = 0x38,
+    CapsLock = 0x39,
+    F1 = 0x3a, F2 = 0x3b, F3 = 0x3c, F4 = 0x3d, F5 = 0x3e, F6 = 0x3f,
+    F7 = 0x40, F8 = 0x41, F9 = 0x42, F10 = 0x43, F11 = 0x44, F12 = 0x45,
+    PrintScreen = 0x46, ScrollLock = 0x47, Pause = 0x48, Insert = 0x49, Home = 0x4a,
+    PageUp = 0x4b, Delete = 0x4c, End = 0x4d, PageDown = 0x4e,
+    Right = 0x4f, Left = 0x50, Down = 0x51, Up = 0x52,
+    Menu = 0x65,
+    LeftCtrl = 0xe0, LeftShift = 0xe1, LeftAlt = 0xe2, LeftGui = 0xe3,
+    RightCtrl = 0xe4, RightShift = 0xe5, RightAlt = 0xe6, RightGui = 0xe7,
+}
+
+/// Modifiers held down together with a key. Encoded as the HID modifier byte.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Deserialize, Serialize, Type)]
+pub struct Modifiers {
+    pub left_ctrl: bool,
+    pub left_shift: bool,
+    pub left_alt: bool,
+    pub left_gui: bool,
+    pub right_ctrl: bool,
+    pub right_shift: bool,
+    pub right_alt: bool,
+    pub right_gui: bool,
+}
+
+impl Modifiers {
+    pub fn to_mask(&self) -> u8 {
+        [
+            self.left_ctrl,
+            self.left_shift,
+            self.left_alt,
+            self.left_gui,
+            self.right_ctrl,
+            self.right_shift,
+            self.right_alt,
+            self.right_gui,
+        ]
+        .iter()
+        .enumerate()
+        .fold(0, |mask, (bit, set)| mask | ((*set as u8) << bit))
+    }
+
+    pub fn from_mask(mask: u8) -> Self {
+        let bit = |n: u8| mask & (1 << n) != 0;
+        Self {
+            left_ctrl: bit(0),
+            left_shift: bit(1),
+            left_alt: bit(2),
+            left_gui: bit(3),
+            right_ctrl: bit(4),
+            right_shift: bit(5),
+            right_alt: bit(6),
+            right_gui: bit(7),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.to_mask() == 0
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize, Type)]
+pub enum MediaKey {
+    PlayPause,
+    Stop,
+    NextTrack,
+    PreviousTrack,
+    Mute,
+    VolumeUp,
+    VolumeDown,
+}
+
+impl MediaKey {
+    /// The usage id on the HID consumer page.
+    pub fn usage(&self) -> u16 {
+        match self {
+            Self::PlayPause => 0xcd,
+            Self::Stop => 0xb7,
+            Self::NextTrack => 0xb5,
+            Self::PreviousTrack => 0xb6,
+            Self::Mute => 0xe2,
+            Self::VolumeUp => 0xe9,
+            Self::VolumeDown => 0xea,
+        }
+    }
+
+    pub fn from_usage(usage: u16) -> Option<Self> {
+        [
+            Self::PlayPause,
+            Self::Stop,
+            Self::NextTrack,
+            Self::PreviousTrack,
+            Self::Mute,
+            Self::VolumeUp,
+            Self::VolumeDown,
+        ]
+        .into_iter()
+        .find(|key| key.usage() == usage)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Keycode, MediaKey, Modifiers};
+
+    #[test]
+    fn usages_round_trip() {
+        for usage in 0..=u8::MAX {
+            if let Some(keycode) = Keycode::from_usage(usage) {
+                assert_eq!(keycode.usage(), usage);
+            }
+        }
+        assert_eq!(
+            Keycode::from_usage(Keycode::Space.usage()),
+            Some(Keycode::Space)
+        );
+        assert_eq!(
+            MediaKey::from_usage(MediaKey::VolumeUp.usage()),
+            Some(MediaKey::VolumeUp)
+        );
+
+        for mask in 0..=u8::MAX {
+            assert_eq!(Modifiers::from_mask(mask).to_mask(), mask);
+        }
+    }
+}
@@ -0,0 +1,521 @@
+use std::{collections::HashSet, f32::consts::PI, ops::RangeInclusive};
+
+use serde::{Deserialize, Serialize};
+use specta::Type;
+
+use super::{
+    colour::Rgb, keyboard_profile::KeyboardMatrix, mouse_profile::MouseLimits, ConfigurationError,
+};
+use crate::device::DeviceModel;
+
+pub(crate) const SPEED: RangeInclusive<u8> = 1..=10;
+pub(crate) const BRIGHTNESS: RangeInclusive<u8> = 0..=100;
+pub(crate) const MAX_PALETTE_COLOURS: usize = 8;
+
+/// A lighting zone of a mouse.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize, Type)]
+pub enum LightingZone {
+    ScrollWheel,
+    Logo,
+    Underglow,
+}
+
+impl LightingZone {
+    /// Where the zone sits on the top view of the mouse, see [`LightingLayout`].
+    fn position(&self) -> (f32, f32) {
+        match self {
+            Self::ScrollWheel => (0.5, 0.15),
+            Self::Logo => (0.5, 0.7),
+            Self::Underglow => (0.5, 0.95),
+        }
+    }
+}
+
+/// A light that can be coloured on its own: a zone of a mouse or a key of a keyboard.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize, Type)]
+pub enum Led {
+    Zone(LightingZone),
+    Key { row: u8, col: u8 },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, Type)]
+pub enum LightingEffect {
+    Off,
+    /// Every light shows the first palette colour, unless given its own colour.
+    Static,
+    /// Fade in and out, moving to the next palette colour on every breath.
+    Breathing,
+    /// The palette scrolls across the device as a gradient.
+    Wave,
+    /// Pressed keys light up in the first palette colour and fade back to the second, or to
+    /// black if the palette has a single colour.
+    Reactive,
+    /// Every light cycles through the colour wheel in unison.
+    SpectrumCycle,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, Type)]
+pub enum LightingDirection {
+    LeftToRight,
+    RightToLeft,
+    TopToBottom,
+    BottomToTop,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize, Type)]
+pub struct LedColour {
+    pub led: Led,
+    pub colour: Rgb,
+}
+
+/// The lighting of a profile. `speed` ranges from 1 (one cycle every ten seconds) to 10 (one
+/// cycle per second) and `brightness` is a percentage.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize, Type)]
+pub struct Lighting {
+    pub effect: LightingEffect,
+    pub speed: u8,
+    pub brightness: u8,
+    pub direction: LightingDirection,
+    pub palette: Vec<Rgb>,
+    /// Colours of individual lights for the static effect.
+    pub colours: Vec<LedColour>,
+}
+
+impl Default for Lighting {
+    fn default() -> Self {
+        Self {
+            effect: LightingEffect::SpectrumCycle,
+            speed: 5,
+            brightness: 80,
+            direction: LightingDirection::LeftToRight,
+            palette: vec![Rgb::new(0xff, 0xff, 0xff)],
+            colours: vec![],
+        }
+    }
+}
+
+impl Lighting {
+    /// The duration of one cycle of the effect.
+    pub(crate) fn period_ms(&self) -> u64 {
+        (11 - self.speed.clamp(*SPEED.start(), *SPEED.end()) as u64) * 1000
+    }
+
+    pub(crate) fn validate(&self, layout: &LightingLayout) -> Result<(), ConfigurationError> {
+        if !SPEED.contains(&self.speed) {
+            return Err(ConfigurationError::out_of_range(
+                "speed", self.speed, &SPEED,
+            ));
+        }
+
+        if !BRIGHTNESS.contains(&self.brightness) {
+            return Err(ConfigurationError::out_of_range(
+                "brightness",
+                self.brightness,
+                &BRIGHTNESS,
+            ));
+        }
+
+        if self.palette.is_empty() || self.palette.len() > MAX_PALETTE_COLOURS {
+            return Err(ConfigurationError::out_of_range(
+                "palette",
+                self.palette.len(),
+                &(1..=MAX_PALETTE_COLOURS),
+            ));
+        }
+
+        let mut leds = HashSet::new();
+        for led_colour in &self.colours {
+            if !layout.has_led(&led_colour.led) {
+                return Err(ConfigurationError::Unsupported {
+                    field: "led".to_string(),
+                    value: format!("{:?}", led_colour.led),
+                });
+            }
+            if !leds.insert(led_colour.led) {
+                return Err(ConfigurationError::Invalid(format!(
+                    "{:?} is coloured more than once",
+                    led_colour.led
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The lights of a device model and their positions, as fractions of the width and height of
+/// the device seen from the top with the origin at the top left.
+#[derive(Clone, Debug)]
+pub(crate) struct LightingLayout {
+    leds: Vec<(Led, (f32, f32))>,
+}
+
+impl LightingLayout {
+    pub(crate) fn for_model(model: &DeviceModel) -> Self {
+        if let Some(limits) = MouseLimits::for_model(model) {
+            Self::zones(&limits.lighting_zones)
+        } else if let Some(matrix) = KeyboardMatrix::for_model(model) {
+            Self::per_key(&matrix)
+        } else {
+            Self { leds: vec![] }
+        }
+    }
+
+    pub(crate) fn zones(zones: &[LightingZone]) -> Self {
+        let leds = zones
+            .iter()
+            .map(|zone| (Led::Zone(*zone), zone.position()))
+            .collect();
+        Self { leds }
+    }
+
+    pub(crate) fn per_key(matrix: &KeyboardMatrix) -> Self {
+        let rows = matrix.default_layout.len() as f32;
+        let cols = matrix
+            .default_layout
+            .iter()
+            .map(|keys| keys.len())
+            .max()
+            .unwrap_or_default() as f32;
+
+        let leds = matrix
+            .default_layout
+            .iter()
+            .enumerate()
+            .flat_map(|(row, keys)| {
+                keys.iter()
+                    .enumerate()
+                    .filter(|(_, action)| action.is_some())
+                    .map(move |(col, _)| {
+                        let led = Led::Key {
+                            row: row as u8,
+                            col: col as u8,
+                        };
+                        (led, ((col as f32 + 0.5) / cols, (row as f32 + 0.5) / rows))
+                    })
+            })
+            .collect();
+        Self { leds }
+    }
+
+    pub(crate) fn has_led(&self, led: &Led) -> bool {
+        self.leds.iter().any(|(candidate, _)| candidate == led)
+    }
+
+    pub(crate) fn get_leds(&self) -> impl Iterator<Item = &Led> {
+        self.leds.iter().map(|(led, _)| led)
+    }
+}
+
+/// A press of a key, used to preview the reactive effect.
+#[derive(Clone, Debug, Deserialize, Serialize, Type)]
+pub struct LedPress {
+    pub led: Led,
+    pub timestamp_ms: u64,
+}
+
+/// The colour of every light at a point in time.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize, Type)]
+pub struct LightingFrame {
+    pub timestamp_ms: u64,
+    pub colours: Vec<LedColour>,
+}
+
+/// The most frames a single preview may render.
+pub(crate) const MAX_PREVIEW_FRAMES: usize = 600;
+
+/// Computes the frames of a [`Lighting`] effect in software, the same way a device does, so
+/// the effect can be previewed.
+#[derive(Debug)]
+pub(crate) struct LightingRenderer<'a> {
+    lighting: &'a Lighting,
+    layout: &'a LightingLayout,
+    presses: Vec<LedPress>,
+}
+
+impl<'a> LightingRenderer<'a> {
+    pub(crate) fn new(lighting: &'a Lighting, layout: &'a LightingLayout) -> Self {
+        Self {
+            lighting,
+            layout,
+            presses: vec![],
+        }
+    }
+
+    pub(crate) fn press(&mut self, press: LedPress) {
+        self.presses.push(press);
+    }
+
+    /// `count` frames starting at `start_ms`, `interval_ms` apart. At most
+    /// [`MAX_PREVIEW_FRAMES`] frames are rendered at once, and every timestamp must fit a
+    /// `u64`.
+    pub(crate) fn frames(
+        &self,
+        start_ms: u64,
+        interval_ms: u64,
+        count: usize,
+    ) -> Result<Vec<LightingFrame>, ConfigurationError> {
+        if count > MAX_PREVIEW_FRAMES {
+            return Err(ConfigurationError::out_of_range(
+                "frame_count",
+                count,
+                &(0..=MAX_PREVIEW_FRAMES),
+            ));
+        }
+
+        (0..count as u64)
+            .map(|index| {
+                let timestamp_ms = index
+                    .checked_mul(interval_ms)
+                    .and_then(|offset| start_ms.checked_add(offset))
+                    .ok_or_else(|| {
+                        ConfigurationError::Invalid(format!(
+                            "Frame {index} starting at {start_ms} ms, {interval_ms} ms apart, \
+                             is out of range"
+                        ))
+                    })?;
+                Ok(self.frame(timestamp_ms))
+            })
+            .collect()
+    }
+
+    pub(crate) fn frame(&self, timestamp_ms: u64) -> LightingFrame {
+        let brightness = self.lighting.brightness.min(*BRIGHTNESS.end()) as f32 / 100.0;
+        let colours = self
+            .layout
+            .leds
+            .iter()
+            .map(|(led, position)| LedColour {
+                led: *led,
+                colour: self.colour(led, *position, timestamp_ms).scale(brightness),
+            })
+            .collect();
+
+        LightingFrame {
+            timestamp_ms,
+            colours,
+        }
+    }
+
+    fn colour(&self, led: &Led, (x, y): (f32, f32), timestamp_ms: u64) -> Rgb {
+        let palette = &self.lighting.palette;
+        let first = palette.first().copied().unwrap_or_default();
+        let period_ms = self.lighting.period_ms();
+        let cycle = timestamp_ms / period_ms;
+        let phase = (timestamp_ms % period_ms) as f32 / period_ms as f32;
+
+        match self.lighting.effect {
+            LightingEffect::Off => Rgb::default(),
+            LightingEffect::Static => self
+                .lighting
+                .colours
+                .iter()
+                .find(|led_colour| led_colour.led == *led)
+                .map_or(first, |led_colour| led_colour.colour),
+            LightingEffect::Breathing => {
+                let colour = palette
+                    .get(cycle as usize % palette.len().max(1))
+                    .copied()
+                    .unwrap_or_default();
+                colour.scale((1.0 - (2.0 * PI * phase).cos()) / 2.0)
+            }
+            LightingEffect::Wave => {
+                let offset = match self.lighting.direction {
+                    LightingDirection::LeftToRight => x,
+                    LightingDirection::RightToLeft => 1.0 - x,
+                    LightingDirection::TopToBottom => y,
+                    LightingDirection::BottomToTop => 1.0 - y,
+                };
+                gradient(palette, (phase - offset).rem_euclid(1.0))
+            }
+            LightingEffect::Reactive => {
+                let background = palette.get(1).copied().unwrap_or_default();
+                let fade_ms = period_ms / 2;
+                self.presses
+                    .iter()
+                    .filter(|press| press.led == *led && press.timestamp_ms <= timestamp_ms)
+                    .map(|press| timestamp_ms - press.timestamp_ms)
+                    .min()
+                    .filter(|age| *age < fade_ms)
+                    .map_or(background, |age| {
+                        first.lerp(background, age as f32 / fade_ms as f32)
+                    })
+            }
+            LightingEffect::SpectrumCycle => Rgb::from_hue(phase),
+        }
+    }
+}
+
+/// The colour at `position` of a gradient that runs through every palette colour and back to
+/// the first, so that it can repeat seamlessly.
+fn gradient(palette: &[Rgb], position: f32) -> Rgb {
+    if palette.is_empty() {
+        return Rgb::default();
+    }
+
+    let scaled = position * palette.len() as f32;
+    let index = scaled as usize % palette.len();
+    let next = (index + 1) % palette.len();
+    palette[index].lerp(palette[next], scaled.fract())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Led, LedColour, LedPress, Lighting, LightingEffect, LightingFrame, LightingLayout,
+        LightingRenderer, LightingZone, MAX_PREVIEW_FRAMES,
+    };
+    use crate::{
+        device::DeviceModel,
+        profile::{colour::Rgb, keyboard_profile::KeyboardMatrix},
+    };
+
+    const RED: Rgb = Rgb::new(0xff, 0, 0);
+    const BLUE: Rgb = Rgb::new(0, 0, 0xff);
+
+    fn lighting(effect: LightingEffect) -> Lighting {
+        Lighting {
+            effect,
+            speed: 10,
+            brightness: 100,
+            palette: vec![RED, BLUE],
+            ..Lighting::default()
+        }
+    }
+
+    fn colour_of(frame: &LightingFrame, led: Led) -> Rgb {
+        frame
+            .colours
+            .iter()
+            .find(|led_colour| led_colour.led == led)
+            .unwrap()
+            .colour
+    }
+
+    #[test]
+    fn layouts_cover_every_light() {
+        let keyboard = LightingLayout::for_model(&DeviceModel::new("K1"));
+        let matrix = KeyboardMatrix::for_model(&DeviceModel::new("K1")).unwrap();
+        assert_eq!(keyboard.get_leds().count(), matrix.key_count());
+        assert!(keyboard.has_led(&Led::Key { row: 0, col: 0 }));
+        assert!(!keyboard.has_led(&Led::Zone(LightingZone::Logo)));
+
+        let mouse = LightingLayout::for_model(&DeviceModel::new("M1"));
+        assert!(mouse.has_led(&Led::Zone(LightingZone::Logo)));
+        assert!(!mouse.has_led(&Led::Key { row: 0, col: 0 }));
+    }
+
+    #[test]
+    fn validates_lighting() {
+        let layout = LightingLayout::for_model(&DeviceModel::new("M1"));
+        Lighting::default().validate(&layout).unwrap();
+
+        let logo = LedColour {
+            led: Led::Zone(LightingZone::Logo),
+            colour: RED,
+        };
+        let invalid = [
+            Lighting {
+                speed: 0,
+                ..Lighting::default()
+            },
+            Lighting {
+                brightness: 101,
+                ..Lighting::default()
+            },
+            Lighting {
+                palette: vec![],
+                ..Lighting::default()
+            },
+            Lighting {
+                palette: vec![RED; 9],
+                ..Lighting::default()
+            },
+            Lighting {
+                colours: vec![LedColour {
+                    led: Led::Key { row: 0, col: 0 },
+                    colour: RED,
+                }],
+                ..Lighting::default()
+            },
+            Lighting {
+                colours: vec![logo.clone(), logo],
+                ..Lighting::default()
+            },
+        ];
+        for lighting in invalid {
+            assert!(lighting.validate(&layout).is_err(), "{lighting:?}");
+        }
+    }
+
+    #[test]
+    fn renders_effects() {
+        let layout = LightingLayout::for_model(&DeviceModel::new("M1"));
+        let logo = Led::Zone(LightingZone::Logo);
+
+        let mut static_lighting = lighting(LightingEffect::Static);
+        static_lighting.brightness = 50;
+        static_lighting.colours = vec![LedColour {
+            led: logo,
+            colour: BLUE,
+        }];
+        let frame = LightingRenderer::new(&static_lighting, &layout).frame(0);
+        assert_eq!(colour_of(&frame, logo), Rgb::new(0, 0, 0x80));
+        assert_eq!(
+            colour_of(&frame, Led::Zone(LightingZone::Underglow)),
+            Rgb::new(0x80, 0, 0)
+        );
+
+        let breathing = lighting(LightingEffect::Breathing);
+        let renderer = LightingRenderer::new(&breathing, &layout);
+        assert_eq!(colour_of(&renderer.frame(0), logo), Rgb::default());
+        assert_eq!(colour_of(&renderer.frame(500), logo), RED);
+        assert_eq!(colour_of(&renderer.frame(1500), logo), BLUE);
+
+        let spectrum = lighting(LightingEffect::SpectrumCycle);
+        let renderer = LightingRenderer::new(&spectrum, &layout);
+        let frames = renderer.frames(0, 250, 4).unwrap();
+        let colours = frames
+            .iter()
+            .map(|frame| colour_of(frame, logo))
+            .collect::<Vec<_>>();
+        assert_eq!(colours[0], RED);
+        assert!(colours.windows(2).all(|pair| pair[0] != pair[1]));
+
+        assert!(renderer.frames(u64::MAX - 100, 250, 2).is_err());
+        assert!(renderer.frames(0, u64::MAX, 3).is_err());
+        assert!(renderer.frames(0, 250, MAX_PREVIEW_FRAMES + 1).is_err());
+    }
+
+    #[test]
+    fn renders_waves_and_reactions() {
+        let layout = LightingLayout::for_model(&DeviceModel::new("K1"));
+        let left = Led::Key { row: 2, col: 0 };
+        let right = Led::Key { row: 2, col: 12 };
+
+        let wave = lighting(LightingEffect::Wave);
+        let renderer = LightingRenderer::new(&wave, &layout);
+        let first = renderer.frame(0);
+        let later = renderer.frame(300);
+        assert_ne!(colour_of(&first, left), colour_of(&first, right));
+        assert_ne!(colour_of(&first, left), colour_of(&later, left));
+        assert_eq!(
+            renderer.frame(1000),
+            LightingFrame {
+                timestamp_ms: 1000,
+                ..first
+            }
+        );
+
+        let reactive = lighting(LightingEffect::Reactive);
+        let mut renderer = LightingRenderer::new(&reactive, &layout);
+        renderer.press(LedPress {
+            led: left,
+            timestamp_ms: 100,
+        });
+        assert_eq!(colour_of(&renderer.frame(50), left), BLUE);
+        assert_eq!(colour_of(&renderer.frame(100), left), RED);
+        assert_eq!(colour_of(&renderer.frame(100), right), BLUE);
+        assert_eq!(colour_of(&renderer.frame(600), left), BLUE);
+    }
+}
@@ -0,0 +1,185 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use uuid::Uuid;
+
+use super::{keycode::Keycode, mouse_profile::MouseButton, ConfigurationError};
+use crate::device::{capabilities::DeviceCapabilities, DeviceModel};
+
+pub mod recorder;
+
+pub(crate) type MacroId = String;
+
+/// The longest [`MacroId`] in bytes, as its length is sent to devices in a single byte.
+pub(crate) const MAX_MACRO_ID_LEN: usize = u8::MAX as usize;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, Type)]
+pub enum MacroEvent {
+    KeyPress(Keycode),
+    KeyRelease(Keycode),
+    ButtonPress(MouseButton),
+    ButtonRelease(MouseButton),
+}
+
+/// An event followed by a pause before the next step is played.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize, Type)]
+pub struct MacroStep {
+    pub event: MacroEvent,
+    pub delay_ms: u16,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize, Type)]
+pub enum RepeatMode {
+    /// Play the macro once per press.
+    #[default]
+    Once,
+    /// Repeat the macro for as long as the key is held.
+    WhileHeld,
+    /// Start repeating the macro on one press and stop on the next.
+    Toggle,
+}
+
+/// How many macros a device model stores and how long each one may be.
+#[derive(Clone, Debug, Deserialize)]
+pub(crate) struct MacroLimits {
+    pub max_macros: usize,
+    pub max_steps: usize,
+}
+
+impl MacroLimits {
+    pub(crate) fn for_model(model: &DeviceModel) -> Option<Self> {
+        DeviceCapabilities::for_model(model).map(|capabilities| capabilities.macros.clone())
+    }
+}
+
+/// A sequence of key and mouse events that a key or button plays back when pressed. Macros
+/// are kept in the library alongside profiles and referenced from them by [`MacroId`], so a
+/// profile plays the same macros on every device it is assigned to. A device stores the
+/// macros of its active profile.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, Type)]
+pub(crate) struct Macro {
+    id: MacroId,
+    name: String,
+    steps: Vec<MacroStep>,
+    repeat: RepeatMode,
+}
+
+impl Macro {
+    pub(crate) fn new(name: impl Into<String>, steps: Vec<MacroStep>, repeat: RepeatMode) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            name: name.into(),
+            steps,
+            repeat,
+        }
+    }
+
+    /// The same macro under a new id, so that a copy does not collide with the original.
+    pub(crate) fn with_new_id(mut self) -> Self {
+        self.id = Uuid::new_v4().to_string();
+        self
+    }
+
+    pub(crate) fn get_id(&self) -> &MacroId {
+        &self.id
+    }
+
+    pub(crate) fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub(crate) fn get_steps(&self) -> &[MacroStep] {
+        &self.steps
+    }
+
+    pub(crate) fn get_repeat(&self) -> RepeatMode {
+        self.repeat
+    }
+
+    /// The time to play the macro once.
+    pub(crate) fn duration(&self) -> Duration {
+        Duration::from_millis(
+            self.steps
+                .iter()
+                .map(|step| step.delay_ms as u64)
+                .sum::<u64>(),
+        )
+    }
+
+    /// Every event of a single playback along with its offset from the start of the macro.
+    pub(crate) fn playback(&self) -> impl Iterator<Item = (Duration, MacroEvent)> + '_ {
+        self.steps.iter().scan(Duration::ZERO, |offset, step| {
+            let at = *offset;
+            *offset += Duration::from_millis(step.delay_ms as u64);
+            Some((at, step.event))
+        })
+    }
+
+    pub(crate) fn validate(&self, limits: &MacroLimits) -> Result<(), ConfigurationError> {
+        if self.id.is_empty() || self.id.len() > MAX_MACRO_ID_LEN {
+            return Err(ConfigurationError::out_of_range(
+                "macro id length",
+                self.id.len(),
+                &(1..=MAX_MACRO_ID_LEN),
+            ));
+        }
+        if self.steps.is_empty() || self.steps.len() > limits.max_steps {
+            return Err(ConfigurationError::out_of_range(
+                "macro steps",
+                self.steps.len(),
+                &(1..=limits.max_steps),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{Macro, MacroEvent, MacroLimits, MacroStep, RepeatMode, MAX_MACRO_ID_LEN};
+    use crate::{device::DeviceModel, profile::keycode::Keycode};
+
+    #[test]
+    fn plays_back_steps_in_order() {
+        let steps = vec![
+            MacroStep {
+                event: MacroEvent::KeyPress(Keycode::A),
+                delay_ms: 20,
+            },
+            MacroStep {
+                event: MacroEvent::KeyRelease(Keycode::A),
+                delay_ms: 30,
+            },
+        ];
+        let recorded = Macro::new("a", steps, RepeatMode::Once);
+
+        let playback = recorded.playback().collect::<Vec<_>>();
+        assert_eq!(
+            playback,
+            vec![
+                (Duration::ZERO, MacroEvent::KeyPress(Keycode::A)),
+                (
+                    Duration::from_millis(20),
+                    MacroEvent::KeyRelease(Keycode::A)
+                ),
+            ]
+        );
+        assert_eq!(recorded.duration(), Duration::from_millis(50));
+
+        let limits = MacroLimits::for_model(&DeviceModel::new("M1")).unwrap();
+        assert!(recorded.validate(&limits).is_ok());
+        let too_long = Macro::new(
+            "long",
+            vec![recorded.get_steps()[0].clone(); limits.max_steps + 1],
+            RepeatMode::Toggle,
+        );
+        assert!(too_long.validate(&limits).is_err());
+
+        let mut long_id = recorded.clone();
+        long_id.id = "x".repeat(MAX_MACRO_ID_LEN + 1);
+        assert!(long_id.validate(&limits).is_err());
+    }
+}
@@ -0,0 +1,169 @@
+use serde::{Deserialize, Serialize};
+use specta::Type;
+
+use super::{Macro, MacroEvent, MacroStep, RepeatMode};
+
+/// An input event captured while recording. Timestamps are in milliseconds from any fixed
+/// origin and must not decrease.
+#[derive(Clone, Debug, Deserialize, Serialize, Type)]
+pub struct RecordedEvent {
+    pub event: MacroEvent,
+    pub timestamp_ms: u64,
+}
+
+/// How the pauses between the steps of a recorded macro are chosen.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Type)]
+pub enum DelayMode {
+    /// Keep the time between the recorded events.
+    Recorded,
+    /// Use the same pause after every step.
+    Fixed(u16),
+}
+
+/// Builds a [`Macro`] from a stream of input events. Auto-repeated presses of a held key and
+/// releases of keys pressed before recording started are dropped, and keys still held when
+/// recording stops are released at the end of the macro.
+#[derive(Debug)]
+pub(crate) struct MacroRecorder {
+    delay_mode: DelayMode,
+    steps: Vec<MacroStep>,
+    held: Vec<MacroEvent>,
+    last_timestamp_ms: Option<u64>,
+}
+
+impl MacroRecorder {
+    pub(crate) fn new(delay_mode: DelayMode) -> Self {
+        Self {
+            delay_mode,
+            steps: vec![],
+            held: vec![],
+            last_timestamp_ms: None,
+        }
+    }
+
+    pub(crate) fn push(&mut self, recorded: RecordedEvent) {
+        let pressed = press_of(&recorded.event);
+        match recorded.event {
+            MacroEvent::KeyPress(_) | MacroEvent::ButtonPress(_) => {
+                if self.held.contains(&pressed) {
+                    return;
+                }
+                self.held.push(pressed);
+            }
+            MacroEvent::KeyRelease(_) | MacroEvent::ButtonRelease(_) => {
+                let Some(index) = self.held.iter().position(|held| *held == pressed) else {
+                    return;
+                };
+                self.held.remove(index);
+            }
+        }
+
+        let delay_ms = match (self.delay_mode, self.last_timestamp_ms) {
+            (DelayMode::Fixed(delay_ms), _) => delay_ms,
+            (DelayMode::Recorded, Some(last)) => recorded
+                .timestamp_ms
+                .saturating_sub(last)
+                .min(u16::MAX as u64) as u16,
+            (DelayMode::Recorded, None) => 0,
+        };
+        if let Some(previous) = self.steps.last_mut() {
+            previous.delay_ms = delay_ms;
+        }
+
+        self.last_timestamp_ms = Some(recorded.timestamp_ms);
+        self.steps.push(MacroStep {
+            event: recorded.event,
+            delay_ms: 0,
+        });
+    }
+
+    pub(crate) fn finish(mut self, name: impl Into<String>, repeat: RepeatMode) -> Macro {
+        let fixed_delay = match self.delay_mode {
+            DelayMode::Fixed(delay_ms) => delay_ms,
+            DelayMode::Recorded => 0,
+        };
+
+        while let Some(pressed) = self.held.pop() {
+            if let Some(previous) = self.steps.last_mut() {
+                previous.delay_ms = fixed_delay;
+            }
+            self.steps.push(MacroStep {
+                event: release_of(&pressed),
+                delay_ms: 0,
+            });
+        }
+
+        Macro::new(name, self.steps, repeat)
+    }
+}
+
+fn press_of(event: &MacroEvent) -> MacroEvent {
+    match *event {
+        MacroEvent::KeyRelease(key) => MacroEvent::KeyPress(key),
+        MacroEvent::ButtonRelease(button) => MacroEvent::ButtonPress(button),
+        press => press,
+    }
+}
+
+fn release_of(event: &MacroEvent) -> MacroEvent {
+    match *event {
+        MacroEvent::KeyPress(key) => MacroEvent::KeyRelease(key),
+        MacroEvent::ButtonPress(button) => MacroEvent::ButtonRelease(button),
+        release => release,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DelayMode, MacroRecorder, RecordedEvent};
+    use crate::profile::{
+        keycode::Keycode,
+        macros::{MacroEvent, MacroStep, RepeatMode},
+        mouse_profile::MouseButton,
+    };
+
+    fn at(event: MacroEvent, timestamp_ms: u64) -> RecordedEvent {
+        RecordedEvent {
+            event,
+            timestamp_ms,
+        }
+    }
+
+    #[test]
+    fn records_delays_between_events() {
+        let mut recorder = MacroRecorder::new(DelayMode::Recorded);
+        recorder.push(at(MacroEvent::KeyRelease(Keycode::B), 0));
+        recorder.push(at(MacroEvent::KeyPress(Keycode::A), 100));
+        recorder.push(at(MacroEvent::KeyPress(Keycode::A), 130));
+        recorder.push(at(MacroEvent::KeyRelease(Keycode::A), 150));
+        recorder.push(at(MacroEvent::ButtonPress(MouseButton::Left), 400));
+
+        let recorded = recorder.finish("test", RepeatMode::Once);
+        let step = |event, delay_ms| MacroStep { event, delay_ms };
+        assert_eq!(
+            recorded.get_steps(),
+            &[
+                step(MacroEvent::KeyPress(Keycode::A), 50),
+                step(MacroEvent::KeyRelease(Keycode::A), 250),
+                step(MacroEvent::ButtonPress(MouseButton::Left), 0),
+                step(MacroEvent::ButtonRelease(MouseButton::Left), 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn uses_fixed_delays() {
+        let mut recorder = MacroRecorder::new(DelayMode::Fixed(10));
+        recorder.push(at(MacroEvent::KeyPress(Keycode::LeftShift), 0));
+        recorder.push(at(MacroEvent::KeyPress(Keycode::A), 5000));
+
+        let recorded = recorder.finish("test", RepeatMode::Once);
+        assert!(recorded.get_steps()[..3]
+            .iter()
+            .all(|step| step.delay_ms == 10));
+        assert_eq!(
+            recorded.get_steps()[3].event,
+            MacroEvent::KeyRelease(Keycode::LeftShift)
+        );
+    }
+}
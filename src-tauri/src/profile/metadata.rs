@@ -0,0 +1,157 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use specta::Type;
+
+use super::{colour::Rgb, ConfigurationError};
+
+pub(crate) const MAX_NAME_LENGTH: usize = 64;
+pub(crate) const MAX_DESCRIPTION_LENGTH: usize = 512;
+pub(crate) const MAX_ICON_LENGTH: usize = 32;
+
+/// Milliseconds since the Unix epoch.
+pub(crate) fn unix_time_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// What the user knows a profile by. The timestamps and the revision are maintained by the
+/// [`ProfileManager`][ProfileManager], values sent along with a profile are ignored.
+///
+/// [ProfileManager]: super::profile_manager::ProfileManager
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize, Type)]
+pub(crate) struct ProfileMetadata {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    /// Shown next to the name, for telling profiles apart at a glance.
+    #[serde(default)]
+    pub colour: Option<Rgb>,
+    /// The name of an icon known to the interface.
+    #[serde(default)]
+    pub icon: Option<String>,
+    /// Zero for profiles stored before timestamps were recorded.
+    #[serde(default)]
+    pub created_ms: u64,
+    #[serde(default)]
+    pub modified_ms: u64,
+    /// Counts the times the profile has been written, starting at 1.
+    #[serde(default)]
+    pub revision: u32,
+}
+
+impl Default for ProfileMetadata {
+    fn default() -> Self {
+        Self::new("Profile")
+    }
+}
+
+impl ProfileMetadata {
+    pub(crate) fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            description: String::new(),
+            colour: None,
+            icon: None,
+            created_ms: 0,
+            modified_ms: 0,
+            revision: 0,
+        }
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), ConfigurationError> {
+        let name = self.name.trim();
+        if name.is_empty() {
+            return Err(ConfigurationError::Invalid(
+                "A profile needs a name".to_string(),
+            ));
+        }
+        if name.chars().count() > MAX_NAME_LENGTH {
+            return Err(ConfigurationError::Invalid(format!(
+                "Profile names are limited to {MAX_NAME_LENGTH} characters"
+            )));
+        }
+        if self.description.chars().count() > MAX_DESCRIPTION_LENGTH {
+            return Err(ConfigurationError::Invalid(format!(
+                "Profile descriptions are limited to {MAX_DESCRIPTION_LENGTH} characters"
+            )));
+        }
+        if let Some(icon) = &self.icon {
+            if icon.is_empty()
+                || icon.len() > MAX_ICON_LENGTH
+                || !icon
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            {
+                return Err(ConfigurationError::Unsupported {
+                    field: "icon".to_string(),
+                    value: icon.to_string(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether two profiles would be confused by name, ignoring case and surrounding spaces.
+    pub(crate) fn has_same_name(&self, other: &Self) -> bool {
+        self.name.trim().to_lowercase() == other.name.trim().to_lowercase()
+    }
+
+    /// Record the first write of a profile.
+    pub(crate) fn stamp_created(&mut self, now_ms: u64) {
+        self.created_ms = now_ms;
+        self.modified_ms = now_ms;
+        self.revision = 1;
+    }
+
+    /// Record a write of a profile that replaces `previous`.
+    pub(crate) fn stamp_revision(&mut self, previous: &Self, now_ms: u64) {
+        self.created_ms = previous.created_ms;
+        self.modified_ms = now_ms.max(previous.modified_ms);
+        self.revision = previous.revision.saturating_add(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ProfileMetadata;
+
+    #[test]
+    fn validates_metadata() {
+        assert!(ProfileMetadata::new("Gaming").validate().is_ok());
+        assert!(ProfileMetadata::new("  ").validate().is_err());
+        assert!(ProfileMetadata::new("x".repeat(65)).validate().is_err());
+
+        let mut metadata = ProfileMetadata::new("Gaming");
+        metadata.icon = Some("crosshair".to_string());
+        assert!(metadata.validate().is_ok());
+        metadata.icon = Some("../crosshair".to_string());
+        assert!(metadata.validate().is_err());
+
+        assert!(ProfileMetadata::new("Gaming").has_same_name(&ProfileMetadata::new(" gaming ")));
+    }
+
+    #[test]
+    fn stamps_revisions() {
+        let mut first = ProfileMetadata::new("Gaming");
+        first.stamp_created(1_000);
+        assert_eq!(
+            (first.created_ms, first.modified_ms, first.revision),
+            (1_000, 1_000, 1)
+        );
+
+        let mut second = ProfileMetadata::new("Work");
+        second.stamp_revision(&first, 2_000);
+        assert_eq!(
+            (second.created_ms, second.modified_ms, second.revision),
+            (1_000, 2_000, 2)
+        );
+
+        // A clock set back does not make a revision older than the one it replaces.
+        let mut third = second.clone();
+        third.stamp_revision(&second, 500);
+        assert_eq!((third.modified_ms, third.revision), (2_000, 3));
+    }
+}
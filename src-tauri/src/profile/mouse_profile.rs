@@ -1,13 +1,615 @@
-use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, ops::RangeInclusive};
+
+use serde::{Deserialize, Deserializer, Serialize};
 use specta::Type;
 
-#[derive(Clone, Debug, Deserialize, Serialize, Type)]
+use super::{
+    colour::Rgb,
+    keycode::{Keycode, Modifiers},
+    lighting::{Lighting, LightingLayout, LightingZone},
+    macros::MacroId,
+    ConfigurationError,
+};
+use crate::device::{capabilities::DeviceCapabilities, DeviceModel};
+
+/// A sensitivity level the user can cycle through with the DPI button. X and Y sensitivity are
+/// set separately.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize, Type)]
+pub struct DpiStage {
+    pub x: u16,
+    pub y: u16,
+    pub colour: Rgb,
+}
+
+impl DpiStage {
+    pub fn new(dpi: u16, colour: Rgb) -> Self {
+        Self {
+            x: dpi,
+            y: dpi,
+            colour,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, Type)]
+pub enum PollingRate {
+    Hz125,
+    Hz250,
+    Hz500,
+    Hz1000,
+    Hz2000,
+    Hz4000,
+    Hz8000,
+}
+
+impl PollingRate {
+    pub fn hz(&self) -> u16 {
+        match self {
+            Self::Hz125 => 125,
+            Self::Hz250 => 250,
+            Self::Hz500 => 500,
+            Self::Hz1000 => 1000,
+            Self::Hz2000 => 2000,
+            Self::Hz4000 => 4000,
+            Self::Hz8000 => 8000,
+        }
+    }
+
+    pub fn from_hz(hz: u16) -> Option<Self> {
+        match hz {
+            125 => Some(Self::Hz125),
+            250 => Some(Self::Hz250),
+            500 => Some(Self::Hz500),
+            1000 => Some(Self::Hz1000),
+            2000 => Some(Self::Hz2000),
+            4000 => Some(Self::Hz4000),
+            8000 => Some(Self::Hz8000),
+            _ => None,
+        }
+    }
+}
+
+/// A physical mouse button.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize, Type)]
+pub enum MouseButton {
+    Left,
+    Right,
+    Middle,
+    Back,
+    Forward,
+    /// The button behind the scroll wheel.
+    Dpi,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, Type)]
+pub enum ScrollDirection {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+/// What a mouse button does when pressed.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize, Type)]
+pub enum ButtonAction {
+    Disabled,
+    /// Act as a standard mouse button.
+    Click(MouseButton),
+    /// Press a key, a key combination or modifiers alone.
+    Keystroke {
+        modifiers: Modifiers,
+        key: Option<Keycode>,
+    },
+    DpiUp,
+    DpiDown,
+    DpiCycle,
+    /// Switch to the profile in the given slot.
+    ProfileSlot(u8),
+    Scroll(ScrollDirection),
+    /// Play back a macro of the device.
+    Macro(MacroId),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize, Type)]
+pub struct ButtonBinding {
+    pub button: MouseButton,
+    pub action: ButtonAction,
+}
+
+/// The range of values a mouse model accepts for each setting, along with its factory DPI
+/// stages. Read from the model's [`DeviceCapabilities`].
+#[derive(Clone, Debug, Deserialize)]
+pub(crate) struct MouseLimits {
+    pub max_dpi_stages: usize,
+    pub dpi: RangeInclusive<u16>,
+    pub dpi_step: u16,
+    pub default_dpi_stages: Vec<DpiStage>,
+    pub default_dpi_stage: usize,
+    pub polling_rates: Vec<PollingRate>,
+    pub lift_off_distance_mm: RangeInclusive<u8>,
+    pub debounce_ms: RangeInclusive<u8>,
+    pub buttons: Vec<MouseButton>,
+    pub lighting_zones: Vec<LightingZone>,
+}
+
+impl MouseLimits {
+    /// `None` for models that are not mice.
+    pub(crate) fn for_model(model: &DeviceModel) -> Option<Self> {
+        DeviceCapabilities::for_model(model)?.mouse.clone()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Type)]
 pub struct MouseProfile {
-    pub(super) dpi: u16,
+    dpi_stages: Vec<DpiStage>,
+    active_dpi_stage: usize,
+    polling_rate: PollingRate,
+    lift_off_distance_mm: u8,
+    angle_snapping: bool,
+    motion_sync: bool,
+    debounce_ms: u8,
+    buttons: Vec<ButtonBinding>,
+    /// Allow profiles where no button performs a primary click. Without it such profiles are
+    /// rejected, as they leave the mouse unable to click.
+    allow_unbound_primary: bool,
+    lighting: Lighting,
+}
+
+impl Default for MouseProfile {
+    fn default() -> Self {
+        Self {
+            dpi_stages: vec![
+                DpiStage::new(400, Rgb::new(0xff, 0x00, 0x00)),
+                DpiStage::new(800, Rgb::new(0x00, 0xff, 0x00)),
+                DpiStage::new(1600, Rgb::new(0x00, 0x00, 0xff)),
+                DpiStage::new(3200, Rgb::new(0xff, 0xff, 0x00)),
+            ],
+            active_dpi_stage: 1,
+            polling_rate: PollingRate::Hz1000,
+            lift_off_distance_mm: 2,
+            angle_snapping: false,
+            motion_sync: false,
+            debounce_ms: 4,
+            buttons: default_bindings(&[
+                MouseButton::Left,
+                MouseButton::Right,
+                MouseButton::Middle,
+                MouseButton::Back,
+                MouseButton::Forward,
+                MouseButton::Dpi,
+            ]),
+            allow_unbound_primary: false,
+            lighting: Lighting::default(),
+        }
+    }
+}
+
+/// Every button performs its own click, except the DPI button which cycles through the stages.
+fn default_bindings(buttons: &[MouseButton]) -> Vec<ButtonBinding> {
+    buttons
+        .iter()
+        .map(|button| ButtonBinding {
+            button: *button,
+            action: match button {
+                MouseButton::Dpi => ButtonAction::DpiCycle,
+                button => ButtonAction::Click(*button),
+            },
+        })
+        .collect()
 }
 
 impl MouseProfile {
-    pub(crate) fn dpi(&self) -> u16 {
-        self.dpi
+    /// The factory profile of a mouse model.
+    pub(crate) fn factory(limits: &MouseLimits) -> Self {
+        Self::default()
+            .with_dpi_stages(limits.default_dpi_stages.clone(), limits.default_dpi_stage)
+            .with_buttons(default_bindings(&limits.buttons))
+    }
+
+    /// A default profile with a single DPI stage.
+    pub(crate) fn new(dpi: u16) -> Self {
+        Self::default().with_dpi_stages(vec![DpiStage::new(dpi, Rgb::new(0xff, 0xff, 0xff))], 0)
+    }
+
+    pub(crate) fn with_dpi_stages(mut self, dpi_stages: Vec<DpiStage>, active: usize) -> Self {
+        self.dpi_stages = dpi_stages;
+        self.active_dpi_stage = active;
+        self
+    }
+
+    pub(crate) fn with_polling_rate(mut self, polling_rate: PollingRate) -> Self {
+        self.polling_rate = polling_rate;
+        self
+    }
+
+    pub(crate) fn with_lift_off_distance_mm(mut self, lift_off_distance_mm: u8) -> Self {
+        self.lift_off_distance_mm = lift_off_distance_mm;
+        self
+    }
+
+    pub(crate) fn with_angle_snapping(mut self, angle_snapping: bool) -> Self {
+        self.angle_snapping = angle_snapping;
+        self
+    }
+
+    pub(crate) fn with_motion_sync(mut self, motion_sync: bool) -> Self {
+        self.motion_sync = motion_sync;
+        self
+    }
+
+    pub(crate) fn with_debounce_ms(mut self, debounce_ms: u8) -> Self {
+        self.debounce_ms = debounce_ms;
+        self
+    }
+
+    pub(crate) fn with_buttons(mut self, buttons: Vec<ButtonBinding>) -> Self {
+        self.buttons = buttons;
+        self
+    }
+
+    pub(crate) fn with_allow_unbound_primary(mut self, allow_unbound_primary: bool) -> Self {
+        self.allow_unbound_primary = allow_unbound_primary;
+        self
+    }
+
+    pub(crate) fn with_lighting(mut self, lighting: Lighting) -> Self {
+        self.lighting = lighting;
+        self
+    }
+
+    pub(crate) fn get_dpi_stages(&self) -> &[DpiStage] {
+        &self.dpi_stages
+    }
+
+    pub(crate) fn get_active_dpi_stage(&self) -> usize {
+        self.active_dpi_stage
+    }
+
+    pub(crate) fn get_polling_rate(&self) -> PollingRate {
+        self.polling_rate
+    }
+
+    pub(crate) fn get_lift_off_distance_mm(&self) -> u8 {
+        self.lift_off_distance_mm
+    }
+
+    pub(crate) fn get_angle_snapping(&self) -> bool {
+        self.angle_snapping
+    }
+
+    pub(crate) fn get_motion_sync(&self) -> bool {
+        self.motion_sync
+    }
+
+    pub(crate) fn get_debounce_ms(&self) -> u8 {
+        self.debounce_ms
+    }
+
+    pub(crate) fn get_buttons(&self) -> &[ButtonBinding] {
+        &self.buttons
+    }
+
+    /// Replace the macros bound to buttons according to `macro_ids`.
+    pub(crate) fn remap_macros(&mut self, macro_ids: &HashMap<MacroId, MacroId>) {
+        for binding in &mut self.buttons {
+            if let ButtonAction::Macro(macro_id) = &mut binding.action {
+                if let Some(new_id) = macro_ids.get(macro_id) {
+                    *macro_id = new_id.to_string();
+                }
+            }
+        }
+    }
+
+    pub(crate) fn get_allow_unbound_primary(&self) -> bool {
+        self.allow_unbound_primary
+    }
+
+    pub(crate) fn get_lighting(&self) -> &Lighting {
+        &self.lighting
+    }
+
+    /// Check every setting against the limits of a mouse model with `profile_slots` slots.
+    pub(crate) fn validate(
+        &self,
+        limits: &MouseLimits,
+        profile_slots: u8,
+    ) -> Result<(), ConfigurationError> {
+        if self.dpi_stages.is_empty() || self.dpi_stages.len() > limits.max_dpi_stages {
+            return Err(ConfigurationError::out_of_range(
+                "dpi_stages",
+                self.dpi_stages.len(),
+                &(1..=limits.max_dpi_stages),
+            ));
+        }
+
+        if self.active_dpi_stage >= self.dpi_stages.len() {
+            return Err(ConfigurationError::out_of_range(
+                "active_dpi_stage",
+                self.active_dpi_stage,
+                &(0..=self.dpi_stages.len() - 1),
+            ));
+        }
+
+        for dpi in self.dpi_stages.iter().flat_map(|stage| [stage.x, stage.y]) {
+            if !limits.dpi.contains(&dpi) {
+                return Err(ConfigurationError::out_of_range("dpi", dpi, &limits.dpi));
+            }
+            if dpi % limits.dpi_step != 0 {
+                return Err(ConfigurationError::Unsupported {
+                    field: "dpi".to_string(),
+                    value: format!("{dpi} (not a multiple of {})", limits.dpi_step),
+                });
+            }
+        }
+
+        if !limits.polling_rates.contains(&self.polling_rate) {
+            return Err(ConfigurationError::Unsupported {
+                field: "polling_rate".to_string(),
+                value: format!("{} Hz", self.polling_rate.hz()),
+            });
+        }
+
+        if !limits
+            .lift_off_distance_mm
+            .contains(&self.lift_off_distance_mm)
+        {
+            return Err(ConfigurationError::out_of_range(
+                "lift_off_distance_mm",
+                self.lift_off_distance_mm,
+                &limits.lift_off_distance_mm,
+            ));
+        }
+
+        if !limits.debounce_ms.contains(&self.debounce_ms) {
+            return Err(ConfigurationError::out_of_range(
+                "debounce_ms",
+                self.debounce_ms,
+                &limits.debounce_ms,
+            ));
+        }
+
+        self.validate_buttons(limits, profile_slots)?;
+        self.lighting
+            .validate(&LightingLayout::zones(&limits.lighting_zones))
+    }
+
+    fn validate_buttons(
+        &self,
+        limits: &MouseLimits,
+        profile_slots: u8,
+    ) -> Result<(), ConfigurationError> {
+        for button in &limits.buttons {
+            let count = self
+                .buttons
+                .iter()
+                .filter(|binding| binding.button == *button)
+                .count();
+            if count != 1 {
+                return Err(ConfigurationError::Invalid(format!(
+                    "{button:?} must be bound exactly once, found {count} bindings"
+                )));
+            }
+        }
+
+        for binding in &self.buttons {
+            if !limits.buttons.contains(&binding.button) {
+                return Err(ConfigurationError::Unsupported {
+                    field: "button".to_string(),
+                    value: format!("{:?}", binding.button),
+                });
+            }
+
+            match binding.action {
+                ButtonAction::Click(button) if !limits.buttons.contains(&button) => {
+                    return Err(ConfigurationError::Unsupported {
+                        field: "click".to_string(),
+                        value: format!("{button:?}"),
+                    })
+                }
+                ButtonAction::Keystroke {
+                    modifiers,
+                    key: None,
+                } if modifiers.is_empty() => {
+                    return Err(ConfigurationError::Invalid(format!(
+                        "Empty keystroke bound to {:?}",
+                        binding.button
+                    )))
+                }
+                ButtonAction::ProfileSlot(slot) if slot >= profile_slots => {
+                    return Err(ConfigurationError::out_of_range(
+                        "profile_slot",
+                        slot,
+                        &(0..=profile_slots - 1),
+                    ))
+                }
+                _ => {}
+            }
+        }
+
+        let primary_bound = self
+            .buttons
+            .iter()
+            .any(|binding| binding.action == ButtonAction::Click(MouseButton::Left));
+        if !primary_bound && !self.allow_unbound_primary {
+            return Err(ConfigurationError::Invalid(
+                "No button performs a primary click".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Mirror of [`MouseProfile`] used for deserialization. Missing settings take their default
+/// value and profiles stored before DPI stages existed only have a single `dpi`, which becomes
+/// the only stage.
+#[derive(Deserialize)]
+struct StoredMouseProfile {
+    dpi: Option<u16>,
+    dpi_stages: Option<Vec<DpiStage>>,
+    active_dpi_stage: Option<usize>,
+    polling_rate: Option<PollingRate>,
+    lift_off_distance_mm: Option<u8>,
+    angle_snapping: Option<bool>,
+    motion_sync: Option<bool>,
+    debounce_ms: Option<u8>,
+    buttons: Option<Vec<ButtonBinding>>,
+    allow_unbound_primary: Option<bool>,
+    lighting: Option<Lighting>,
+}
+
+impl<'de> Deserialize<'de> for MouseProfile {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let stored = StoredMouseProfile::deserialize(deserializer)?;
+        let default = match stored.dpi {
+            Some(dpi) => MouseProfile::new(dpi),
+            None => MouseProfile::default(),
+        };
+
+        Ok(Self {
+            dpi_stages: stored.dpi_stages.unwrap_or(default.dpi_stages),
+            active_dpi_stage: stored.active_dpi_stage.unwrap_or(default.active_dpi_stage),
+            polling_rate: stored.polling_rate.unwrap_or(default.polling_rate),
+            lift_off_distance_mm: stored
+                .lift_off_distance_mm
+                .unwrap_or(default.lift_off_distance_mm),
+            angle_snapping: stored.angle_snapping.unwrap_or(default.angle_snapping),
+            motion_sync: stored.motion_sync.unwrap_or(default.motion_sync),
+            debounce_ms: stored.debounce_ms.unwrap_or(default.debounce_ms),
+            buttons: stored.buttons.unwrap_or(default.buttons),
+            allow_unbound_primary: stored
+                .allow_unbound_primary
+                .unwrap_or(default.allow_unbound_primary),
+            lighting: stored.lighting.unwrap_or(default.lighting),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        ButtonAction, ButtonBinding, DpiStage, MouseButton, MouseLimits, MouseProfile, PollingRate,
+    };
+    use crate::{
+        device::{capabilities::DeviceCapabilities, DeviceModel},
+        profile::{colour::Rgb, ConfigurationError},
+    };
+
+    #[test]
+    fn loads_legacy_profiles() {
+        let profile: MouseProfile = serde_json::from_str(r#"{"dpi":1600}"#).unwrap();
+        assert_eq!(profile, MouseProfile::new(1600));
+
+        let profile: MouseProfile =
+            serde_json::from_str(&serde_json::to_string(&MouseProfile::default()).unwrap())
+                .unwrap();
+        assert_eq!(profile, MouseProfile::default());
+    }
+
+    #[test]
+    fn enforces_model_limits() {
+        let limits = MouseLimits::for_model(&DeviceModel::new("M1")).unwrap();
+        let slots = DeviceCapabilities::for_model(&DeviceModel::new("M1"))
+            .unwrap()
+            .profile_slots;
+        assert!(MouseProfile::default().validate(&limits, slots).is_ok());
+
+        let too_many_stages = MouseProfile::default().with_dpi_stages(
+            vec![DpiStage::new(800, Rgb::default()); limits.max_dpi_stages + 1],
+            0,
+        );
+        let inactive_stage = MouseProfile::new(800).with_dpi_stages(vec![], 0);
+        let out_of_range = MouseProfile::new(*limits.dpi.end() + limits.dpi_step);
+        let off_step = MouseProfile::new(825);
+        let debounce = MouseProfile::default().with_debounce_ms(*limits.debounce_ms.end() + 1);
+        let lift_off = MouseProfile::default().with_lift_off_distance_mm(0);
+
+        for profile in [
+            too_many_stages,
+            inactive_stage,
+            out_of_range,
+            off_step,
+            debounce,
+            lift_off,
+        ] {
+            assert!(profile.validate(&limits, slots).is_err(), "{profile:?}");
+        }
+
+        let mut stage = DpiStage::new(800, Rgb::default());
+        stage.y = 50;
+        assert!(matches!(
+            MouseProfile::default()
+                .with_dpi_stages(vec![stage], 0)
+                .validate(&limits, slots),
+            Err(ConfigurationError::OutOfRange { .. })
+        ));
+        assert!(MouseProfile::default()
+            .with_polling_rate(PollingRate::Hz8000)
+            .validate(&limits, slots)
+            .is_ok());
+    }
+
+    #[test]
+    fn validates_button_bindings() {
+        let limits = MouseLimits::for_model(&DeviceModel::new("M1")).unwrap();
+        let slots = DeviceCapabilities::for_model(&DeviceModel::new("M1"))
+            .unwrap()
+            .profile_slots;
+        let rebind = |button, action: ButtonAction| {
+            let buttons = MouseProfile::default()
+                .get_buttons()
+                .iter()
+                .map(|binding| match binding.button == button {
+                    true => ButtonBinding {
+                        button,
+                        action: action.clone(),
+                    },
+                    false => binding.clone(),
+                })
+                .collect();
+            MouseProfile::default().with_buttons(buttons)
+        };
+
+        // Swapping primary and secondary keeps a primary click.
+        let swapped = rebind(MouseButton::Left, ButtonAction::Click(MouseButton::Right));
+        let swapped = MouseProfile::default().with_buttons(
+            swapped
+                .get_buttons()
+                .iter()
+                .map(|binding| match binding.button {
+                    MouseButton::Right => ButtonBinding {
+                        button: MouseButton::Right,
+                        action: ButtonAction::Click(MouseButton::Left),
+                    },
+                    _ => binding.clone(),
+                })
+                .collect(),
+        );
+        assert!(swapped.validate(&limits, slots).is_ok());
+
+        let unbound = rebind(MouseButton::Left, ButtonAction::Disabled);
+        assert!(matches!(
+            unbound.validate(&limits, slots),
+            Err(ConfigurationError::Invalid(_))
+        ));
+        assert!(unbound
+            .with_allow_unbound_primary(true)
+            .validate(&limits, slots)
+            .is_ok());
+
+        let missing = MouseProfile::default().with_buttons(vec![]);
+        assert!(missing.validate(&limits, slots).is_err());
+
+        let slot = rebind(
+            MouseButton::Back,
+            ButtonAction::ProfileSlot(slots),
+        );
+        assert!(matches!(
+            slot.validate(&limits, slots),
+            Err(ConfigurationError::OutOfRange { .. })
+        ));
     }
 }
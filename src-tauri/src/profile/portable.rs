@@ -0,0 +1,275 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use thiserror::Error;
+
+use super::{
+    macros::{Macro, MacroLimits},
+    metadata::ProfileMetadata,
+    Profile, ProfileConfiguration,
+};
+use crate::{
+    device::DeviceModel,
+    storage_manager::file_format::{FileFormat, FileFormatError},
+};
+
+/// The newest version of the file format. Files of newer versions are refused.
+pub(crate) const PROFILE_FILE_VERSION: u32 = 1;
+
+/// Exported profiles, whose header is the file itself.
+const PROFILE_FILE: FileFormat = FileFormat {
+    name: "crabby-profile",
+    description: "profile file",
+    extension: "crabby-profile",
+    version: PROFILE_FILE_VERSION,
+    header: "",
+};
+
+#[derive(Debug, Error, Serialize, Deserialize, Type)]
+pub(crate) enum ProfileFileError {
+    #[error("IOError: {0}")]
+    IOError(String),
+
+    #[error("ParseError: {0}")]
+    ParseError(String),
+
+    #[error("UnsupportedVersion: Version {0} is newer than this application supports")]
+    UnsupportedVersion(u32),
+
+    #[error("Incompatible: {}", .0.join("; "))]
+    Incompatible(Vec<String>),
+}
+
+impl From<FileFormatError> for ProfileFileError {
+    fn from(e: FileFormatError) -> Self {
+        match e {
+            FileFormatError::Malformed(e) => Self::ParseError(e),
+            FileFormatError::UnsupportedVersion(version) => Self::UnsupportedVersion(version),
+        }
+    }
+}
+
+/// A profile along with everything needed to use it on another installation: the model it
+/// was made for, its metadata and the macros it plays back. Stored as JSON.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub(crate) struct ProfileFile {
+    format: String,
+    format_version: u32,
+    model: DeviceModel,
+    metadata: ProfileMetadata,
+    configuration: ProfileConfiguration,
+    #[serde(default)]
+    macros: Vec<Macro>,
+}
+
+/// The outcome of an import, for telling the user what happened to the profile.
+#[derive(Clone, Debug, Serialize, Type)]
+pub(crate) struct ImportReport {
+    pub profile: Profile,
+    /// `None` when the device had no free slot and the profile was only added to the library.
+    pub slot: Option<usize>,
+    /// Adjustments made while importing, such as a new name.
+    pub notes: Vec<String>,
+}
+
+impl ProfileFile {
+    /// Describe a profile of a device of the given model. `macros` are the macros its
+    /// configuration plays back.
+    pub(crate) fn new(profile: &Profile, model: &DeviceModel, macros: &[Macro]) -> Self {
+        Self {
+            format: PROFILE_FILE.name.to_string(),
+            format_version: PROFILE_FILE_VERSION,
+            model: model.clone(),
+            metadata: profile.get_metadata().clone(),
+            configuration: profile.get_configuration().clone(),
+            macros: macros.to_vec(),
+        }
+    }
+
+    pub(crate) fn get_model(&self) -> &DeviceModel {
+        &self.model
+    }
+
+    pub(crate) fn to_json(&self) -> Result<String, ProfileFileError> {
+        serde_json::to_string_pretty(self).map_err(|e| ProfileFileError::ParseError(e.to_string()))
+    }
+
+    pub(crate) fn from_json(json: &str) -> Result<Self, ProfileFileError> {
+        Ok(PROFILE_FILE.parse(json)?)
+    }
+
+    /// Write the file, adding the profile file extension to paths without one.
+    pub(crate) fn write(&self, path: &Path) -> Result<(), ProfileFileError> {
+        PROFILE_FILE
+            .write(path, self.to_json()?.as_bytes())
+            .map_err(|e| ProfileFileError::IOError(e.to_string()))
+    }
+
+    pub(crate) fn read(path: &Path) -> Result<Self, ProfileFileError> {
+        let json =
+            fs::read_to_string(path).map_err(|e| ProfileFileError::IOError(e.to_string()))?;
+        Self::from_json(&json)
+    }
+
+    /// Check that the profile can be used on a device of `model`, whose macros must fit
+    /// `limits`. Every problem found is reported, not only the first.
+    pub(crate) fn check(
+        &self,
+        model: &DeviceModel,
+        limits: &MacroLimits,
+    ) -> Result<(), ProfileFileError> {
+        let mut issues = vec![];
+
+        if let Err(e) = self.metadata.validate() {
+            issues.push(e.to_string());
+        }
+        if let Err(e) = self.configuration.validate(model) {
+            if *model != self.model {
+                issues.push(format!("The profile was made for a {}", self.model));
+            }
+            issues.push(e.to_string());
+        }
+
+        for macro_id in self.configuration.get_macro_ids() {
+            if !self
+                .macros
+                .iter()
+                .any(|recorded| recorded.get_id() == macro_id)
+            {
+                issues.push(format!("The macro {macro_id} is missing from the file"));
+            }
+        }
+        for recorded in &self.macros {
+            if let Err(e) = recorded.validate(limits) {
+                issues.push(format!("Macro {}: {e}", recorded.get_name()));
+            }
+        }
+        if self.macros.len() > limits.max_macros {
+            issues.push(format!(
+                "The profile uses {} macros, the device holds at most {}",
+                self.macros.len(),
+                limits.max_macros
+            ));
+        }
+
+        if issues.is_empty() {
+            Ok(())
+        } else {
+            Err(ProfileFileError::Incompatible(issues))
+        }
+    }
+
+    /// The profile and its macros, with new ids so that importing the same file
+    /// twice, or on the installation it came from, does not collide with existing ones.
+    pub(crate) fn into_profile(self) -> (Profile, Vec<Macro>) {
+        let mut macro_ids = HashMap::new();
+        let macros = self
+            .macros
+            .into_iter()
+            .map(|recorded| {
+                let old_id = recorded.get_id().to_string();
+                let recorded = recorded.with_new_id();
+                macro_ids.insert(old_id, recorded.get_id().to_string());
+                recorded
+            })
+            .collect();
+
+        let mut configuration = self.configuration;
+        configuration.remap_macros(&macro_ids);
+        let profile = Profile::new(configuration).with_metadata(self.metadata);
+        (profile, macros)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ProfileFile, ProfileFileError, PROFILE_FILE_VERSION};
+    use crate::{
+        device::DeviceModel,
+        profile::{
+            keycode::Keycode,
+            macros::{Macro, MacroEvent, MacroLimits, MacroStep, RepeatMode},
+            metadata::ProfileMetadata,
+            mouse_profile::{ButtonAction, MouseButton, MouseProfile},
+            Profile, ProfileConfiguration,
+        },
+    };
+
+    fn exported() -> (ProfileFile, Macro) {
+        let recorded = Macro::new(
+            "copy",
+            vec![MacroStep {
+                event: MacroEvent::KeyPress(Keycode::C),
+                delay_ms: 10,
+            }],
+            RepeatMode::Once,
+        );
+        let mouse = MouseProfile::default();
+        let mut buttons = mouse.get_buttons().to_vec();
+        buttons
+            .iter_mut()
+            .filter(|binding| binding.button == MouseButton::Forward)
+            .for_each(|binding| {
+                binding.action = ButtonAction::Macro(recorded.get_id().to_string())
+            });
+        let profile = Profile::new(ProfileConfiguration::Mouse(mouse.with_buttons(buttons)))
+            .with_metadata(ProfileMetadata::new("Editing"));
+
+        let file = ProfileFile::new(
+            &profile,
+            &DeviceModel::new("M1"),
+            std::slice::from_ref(&recorded),
+        );
+        (file, recorded)
+    }
+
+    #[test]
+    fn round_trips_with_new_ids() {
+        let (file, recorded) = exported();
+        let file = ProfileFile::from_json(&file.to_json().unwrap()).unwrap();
+        let model = DeviceModel::new("M1");
+        let limits = MacroLimits::for_model(&model).unwrap();
+        file.check(&model, &limits).unwrap();
+
+        let (profile, macros) = file.into_profile();
+        assert_eq!(profile.get_metadata().name, "Editing");
+        assert_eq!(macros.len(), 1);
+        assert_eq!(macros[0].get_steps(), recorded.get_steps());
+        assert_ne!(macros[0].get_id(), recorded.get_id());
+        assert_eq!(
+            profile.get_configuration().get_macro_ids(),
+            vec![macros[0].get_id()]
+        );
+    }
+
+    #[test]
+    fn reports_incompatibilities() {
+        let (file, _) = exported();
+        let keyboard = DeviceModel::new("K1");
+        let mut limits = MacroLimits::for_model(&keyboard).unwrap();
+        limits.max_macros = 0;
+
+        match file.check(&keyboard, &limits) {
+            Err(ProfileFileError::Incompatible(issues)) => assert_eq!(issues.len(), 3),
+            result => panic!("Unexpected {result:?}"),
+        }
+    }
+
+    #[test]
+    fn refuses_newer_and_foreign_files() {
+        let (file, _) = exported();
+        let json = file.to_json().unwrap().replace(
+            &format!("\"format_version\": {PROFILE_FILE_VERSION}"),
+            &format!("\"format_version\": {}", PROFILE_FILE_VERSION + 1),
+        );
+        assert!(matches!(
+            ProfileFile::from_json(&json),
+            Err(ProfileFileError::UnsupportedVersion(_))
+        ));
+        assert!(matches!(
+            ProfileFile::from_json(r#"{"format": "other", "format_version": 1}"#),
+            Err(ProfileFileError::ParseError(_))
+        ));
+    }
+}
@@ -1,16 +1,131 @@
-use serde::{Deserialize, Serialize};
+use serde::{de, Deserialize, Deserializer, Serialize};
+use serde_json::{json, Value};
 use specta::Type;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use thiserror::Error;
-use tracing::{error, instrument, warn};
+use tracing::{instrument, warn};
 
-use super::{default_profile_provider::DefaultProfileProvider, Profile, ProfileId};
+use super::{
+    default_profile_provider::DefaultProfileProvider,
+    macros::{Macro, MacroId, MacroLimits},
+    metadata::{unix_time_ms, ProfileMetadata},
+    ConfigurationError, Profile, ProfileConfiguration, ProfileId,
+};
 use crate::{
-    device::{DeviceId, DeviceModel},
-    storage_manager::{StorageManager, StorageManagerError, Store},
+    device::{capabilities::DeviceCapabilities, DeviceId, DeviceModel},
+    storage_manager::{
+        schema::Migration, Recover, Recovered, StorageManager, StorageManagerError, Store,
+    },
 };
 
-pub(crate) type ProfileTetrad = [Option<ProfileId>; 4];
+/// The profile slots of a device. The number of slots comes from the model's
+/// [`DeviceCapabilities`], as does the slot holding the factory profile of a newly registered
+/// device.
+#[derive(Clone, Debug, PartialEq, Serialize, Type)]
+pub(crate) struct ProfileSlots {
+    slots: Vec<Option<ProfileId>>,
+    default_slot: usize,
+}
+
+impl ProfileSlots {
+    pub(crate) fn new(slot_count: usize, default_slot: usize) -> Self {
+        Self {
+            slots: vec![None; slot_count],
+            default_slot,
+        }
+    }
+
+    fn for_capabilities(capabilities: &DeviceCapabilities) -> Self {
+        Self::new(
+            capabilities.profile_slots as usize,
+            capabilities.default_profile_slot as usize,
+        )
+    }
+
+    pub(crate) fn get_slots(&self) -> &[Option<ProfileId>] {
+        &self.slots
+    }
+
+    pub(crate) fn get_default_slot(&self) -> usize {
+        self.default_slot
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &Option<ProfileId>> {
+        self.slots.iter()
+    }
+
+    pub(crate) fn get(&self, index: usize) -> Option<&Option<ProfileId>> {
+        self.slots.get(index)
+    }
+
+    fn set(&mut self, index: usize, profile_id: Option<ProfileId>) {
+        self.slots[index] = profile_id;
+    }
+
+    fn position(&self, profile_id: &ProfileId) -> Option<usize> {
+        self.slots
+            .iter()
+            .position(|slot| slot.as_ref() == Some(profile_id))
+    }
+
+    /// Grow or shrink the slots to the model's layout. Occupied slots are never dropped, so a
+    /// device may keep more slots than its model has until the extra profiles are deleted. The
+    /// profile in the old default slot moves to the new one, trading places with the profile
+    /// found there.
+    fn fit(&mut self, slot_count: usize, default_slot: usize) {
+        if self.slots.len() <= default_slot {
+            self.slots.resize(default_slot + 1, None);
+        }
+        self.slots.swap(self.default_slot, default_slot);
+        self.default_slot = default_slot;
+
+        while self.slots.len() > slot_count.max(default_slot + 1)
+            && self.slots.last() == Some(&None)
+        {
+            self.slots.pop();
+        }
+        if self.slots.len() < slot_count {
+            self.slots.resize(slot_count, None);
+        }
+    }
+}
+
+/// Mirror of [`ProfileSlots`] used for deserialization, so that a default slot outside of the
+/// slots is refused when the store is read rather than when the slot is first used.
+#[derive(Deserialize)]
+struct StoredProfileSlots {
+    slots: Vec<Option<ProfileId>>,
+    default_slot: usize,
+}
+
+impl<'de> Deserialize<'de> for ProfileSlots {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let stored = StoredProfileSlots::deserialize(deserializer)?;
+        if stored.default_slot >= stored.slots.len() {
+            return Err(de::Error::custom(format!(
+                "default slot {} of {} slots",
+                stored.default_slot,
+                stored.slots.len()
+            )));
+        }
+
+        Ok(Self {
+            slots: stored.slots,
+            default_slot: stored.default_slot,
+        })
+    }
+}
+
+/// Refers to one of a device's profiles, either by its [`ProfileId`] or by the index of the
+/// [`ProfileSlots`] slot it occupies.
+#[derive(Clone, Debug, Deserialize, Serialize, Type)]
+pub(crate) enum ProfileSelector {
+    Id(ProfileId),
+    Slot(usize),
+}
 
 #[derive(Debug, Error, Serialize, Deserialize, Type)]
 pub(crate) enum ProfileManagerError {
@@ -31,32 +146,118 @@ pub(crate) enum ProfileManagerError {
 
     #[error("UnknownDevice: DeviceId {0}")]
     UnknownDevice(DeviceId),
+
+    #[error("UnknownModel: There is no definition of {0}")]
+    UnknownModel(String),
+
+    #[error("ProfileNotAssociated: ProfileId {0} does not belong to DeviceId {1}")]
+    ProfileNotAssociated(ProfileId, DeviceId),
+
+    #[error("InvalidProfileSlot: DeviceId {0} has no slot {1}")]
+    InvalidProfileSlot(DeviceId, usize),
+
+    #[error("EmptyProfileSlot: DeviceId {0}, slot {1}")]
+    EmptyProfileSlot(DeviceId, usize),
+
+    #[error("ProfileInUse: ProfileId {0} is assigned to DeviceId {1}")]
+    ProfileInUse(ProfileId, DeviceId),
+
+    #[error("DuplicateProfileName: DeviceId {1} already has a profile named {0}")]
+    DuplicateProfileName(String, DeviceId),
+
+    #[error("ProfileAlreadyAssigned: ProfileId {0} is already assigned to DeviceId {1}")]
+    ProfileAlreadyAssigned(ProfileId, DeviceId),
+
+    #[error("ProfileAlreadyExists: ProfileId {0}")]
+    ProfileAlreadyExists(ProfileId),
+
+    #[error("MacroNotFound: MacroId {0}")]
+    MacroNotFound(MacroId),
+
+    #[error("MacroInUse: MacroId {0} is bound in ProfileId {1}")]
+    MacroInUse(MacroId, ProfileId),
+
+    #[error("InsufficientMacroSlots: DeviceId {0}")]
+    InsufficientMacroSlots(DeviceId),
+
+    #[error(transparent)]
+    ConfigurationError(#[from] ConfigurationError),
 }
 
 /// The `ProfileManager` is responsible for managing the association between [`Device`][Device]s and [`Profile`]s,
-/// as well as, serialization of the profiles to disk.
+/// as well as, serialization of the profiles to disk. Profiles are kept in a library and the
+/// slots of a device only reference them, so a profile can be unassigned or assigned to the
+/// slots of several devices.
 ///
 /// [Device]: crate::device::Device
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub(crate) struct ProfileManager {
-    device_profile_map: HashMap<DeviceId, ProfileTetrad>,
+    device_profile_map: HashMap<DeviceId, ProfileSlots>,
     profiles: HashMap<ProfileId, Profile>,
+    #[serde(default)]
+    macros: HashMap<MacroId, Macro>,
 }
 
 impl ProfileManager {
     pub fn new(
-        device_profile_map: HashMap<DeviceId, ProfileTetrad>,
+        device_profile_map: HashMap<DeviceId, ProfileSlots>,
         profiles: HashMap<ProfileId, Profile>,
+        macros: HashMap<MacroId, Macro>,
     ) -> Self {
         Self {
             device_profile_map,
             profiles,
+            macros,
+        }
+    }
+
+    fn factory_profile(configuration: ProfileConfiguration) -> Profile {
+        let mut metadata = ProfileMetadata::new("Default");
+        metadata.stamp_created(unix_time_ms());
+        Profile::new(configuration).with_metadata(metadata)
+    }
+
+    /// Check that no profile of the library has the id of a profile about to be added, which
+    /// would silently replace it, including in the slots of every device it is assigned to.
+    fn check_new_id(&self, profile_id: &ProfileId) -> Result<(), ProfileManagerError> {
+        if self.profiles.contains_key(profile_id) {
+            Err(ProfileManagerError::ProfileAlreadyExists(
+                profile_id.to_string(),
+            ))
+        } else {
+            Ok(())
         }
     }
 
-    /// Register a new device with the `ProfileManager`. This method automatically
-    /// associates the [`DeviceModel`] default [`Profile`] with the device and allocates
-    /// three additional slots for custom user profiles.The default `Profile` can
+    /// Check that no other profile in the slots of a device has the same name as `metadata`.
+    /// The profile `replaced`, if any, is about to leave the slots and is not compared.
+    fn check_unique_name(
+        &self,
+        device_id: &DeviceId,
+        metadata: &ProfileMetadata,
+        replaced: Option<&ProfileId>,
+    ) -> Result<(), ProfileManagerError> {
+        let duplicate = self
+            .get_device_profile_ids(device_id)?
+            .iter()
+            .flatten()
+            .filter(|profile_id| Some(*profile_id) != replaced)
+            .filter_map(|profile_id| self.profiles.get(profile_id))
+            .any(|profile| profile.metadata.has_same_name(metadata));
+
+        if duplicate {
+            Err(ProfileManagerError::DuplicateProfileName(
+                metadata.name.trim().to_string(),
+                device_id.to_string(),
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Register a new device with the `ProfileManager`. This method allocates the profile
+    /// slots of the [`DeviceModel`] and places its default [`Profile`] in the model's default
+    /// slot. The remaining slots are left for custom user profiles. The default `Profile` can
     /// eventually be replaced with a custom `Profile`.
     pub fn register_device(
         &mut self,
@@ -64,13 +265,18 @@ impl ProfileManager {
         device_model: DeviceModel,
     ) -> Result<ProfileId, ProfileManagerError> {
         if !self.device_profile_map.contains_key(device_id) {
-            let configuration = DefaultProfileProvider::profile(device_model);
-            let profile = Profile::new(device_id, configuration);
+            let capabilities = device_model
+                .get_capabilities()
+                .ok_or_else(|| ProfileManagerError::UnknownModel(device_model.to_string()))?;
+            let configuration = DefaultProfileProvider::profile(device_model.clone())
+                .ok_or_else(|| ProfileManagerError::UnknownModel(device_model.to_string()))?;
+            let profile = Self::factory_profile(configuration);
             let id = profile.id.to_string();
-            self.device_profile_map.insert(
-                device_id.to_string(),
-                [Some(id.to_string()), None, None, None],
-            );
+
+            let mut profile_slots = ProfileSlots::for_capabilities(capabilities);
+            profile_slots.set(profile_slots.get_default_slot(), Some(id.to_string()));
+            self.device_profile_map
+                .insert(device_id.to_string(), profile_slots);
             self.profiles.insert(profile.id.to_string(), profile);
             Ok(id)
         } else {
@@ -86,7 +292,7 @@ impl ProfileManager {
         device_model: &DeviceModel,
     ) -> Result<ProfileId, ProfileManagerError> {
         let profile_ids = if let Some(profile_ids) = self.device_profile_map.get(device_id) {
-            profile_ids
+            profile_ids.clone()
         } else {
             return Err(ProfileManagerError::UnknownDevice(device_id.to_string()));
         };
@@ -97,21 +303,49 @@ impl ProfileManager {
             ));
         }
 
-        let configuration = DefaultProfileProvider::profile(device_model.clone());
-        let profile = Profile::new(device_id, configuration);
+        let configuration = DefaultProfileProvider::profile(device_model.clone())
+            .ok_or_else(|| ProfileManagerError::UnknownModel(device_model.to_string()))?;
+        let profile = Self::factory_profile(configuration);
         let id = profile.id.to_string();
-        let profile_tet = [Some(profile.id.to_string()), None, None, None];
+        let mut profile_slots = profile_ids;
+        profile_slots.set(profile_slots.get_default_slot(), Some(id.to_string()));
 
         self.profiles.insert(profile.id.to_string(), profile);
         self.device_profile_map
-            .insert(device_id.to_string(), profile_tet);
+            .insert(device_id.to_string(), profile_slots);
         Ok(id)
     }
 
+    /// Fit the slots of a device to the layout of its model, for devices registered with a
+    /// different number of slots such as the four slots every device used to have.
+    pub fn fit_device_slots(
+        &mut self,
+        device_id: &DeviceId,
+        capabilities: &DeviceCapabilities,
+    ) -> Result<(), ProfileManagerError> {
+        let profile_slots = self
+            .device_profile_map
+            .get_mut(device_id)
+            .ok_or_else(|| ProfileManagerError::UnknownDevice(device_id.to_string()))?;
+
+        profile_slots.fit(
+            capabilities.profile_slots as usize,
+            capabilities.default_profile_slot as usize,
+        );
+        if profile_slots.get_slots().len() > capabilities.profile_slots as usize {
+            warn!(
+                "{device_id} keeps {} profile slots, its model has {}.",
+                profile_slots.get_slots().len(),
+                capabilities.profile_slots
+            );
+        }
+        Ok(())
+    }
+
     pub fn get_device_profile_ids(
         &self,
         device_id: &DeviceId,
-    ) -> Result<&ProfileTetrad, ProfileManagerError> {
+    ) -> Result<&ProfileSlots, ProfileManagerError> {
         self.device_profile_map
             .get(device_id)
             .ok_or_else(|| ProfileManagerError::UnknownDevice(device_id.to_string()))
@@ -121,13 +355,15 @@ impl ProfileManager {
         &self,
         device_id: &DeviceId,
     ) -> Result<ProfileId, ProfileManagerError> {
-        let mut profile_ids = self
-            .get_device_profile_ids(device_id)?
-            .iter()
-            .skip_while(|v| v.is_none())
-            .map(|v| v.clone().unwrap());
+        let profile_slots = self.get_device_profile_ids(device_id)?;
+        let default_profile = profile_slots
+            .get(profile_slots.get_default_slot())
+            .cloned()
+            .flatten();
 
-        if let Some(profile_id) = profile_ids.next() {
+        if let Some(profile_id) =
+            default_profile.or_else(|| profile_slots.iter().flatten().next().cloned())
+        {
             Ok(profile_id)
         } else {
             Err(ProfileManagerError::EmptyProfileSlots(
@@ -136,22 +372,121 @@ impl ProfileManager {
         }
     }
 
+    /// Resolve a [`ProfileSelector`] to the id of a profile in the slots of the given device.
+    pub fn resolve_device_profile(
+        &self,
+        device_id: &DeviceId,
+        selector: &ProfileSelector,
+    ) -> Result<ProfileId, ProfileManagerError> {
+        let profile_ids = self.get_device_profile_ids(device_id)?;
+
+        match selector {
+            ProfileSelector::Id(profile_id) => profile_ids
+                .iter()
+                .flatten()
+                .find(|id| *id == profile_id)
+                .cloned()
+                .ok_or_else(|| {
+                    ProfileManagerError::ProfileNotAssociated(
+                        profile_id.to_string(),
+                        device_id.to_string(),
+                    )
+                }),
+            ProfileSelector::Slot(index) => profile_ids
+                .get(*index)
+                .ok_or_else(|| {
+                    ProfileManagerError::InvalidProfileSlot(device_id.to_string(), *index)
+                })?
+                .clone()
+                .ok_or_else(|| {
+                    ProfileManagerError::EmptyProfileSlot(device_id.to_string(), *index)
+                }),
+        }
+    }
+
     pub fn get_profile(&self, profile_id: &ProfileId) -> Result<&Profile, ProfileManagerError> {
         self.profiles
             .get(profile_id)
             .ok_or_else(|| ProfileManagerError::ProfileNotFound(profile_id.to_string()))
     }
 
+    /// Every profile in the library, assigned or not.
+    pub fn get_profiles(&self) -> Vec<&Profile> {
+        self.profiles.values().collect()
+    }
+
+    /// The devices and slots a profile is assigned to.
+    pub fn get_profile_assignments(&self, profile_id: &ProfileId) -> Vec<(&DeviceId, usize)> {
+        self.device_profile_map
+            .iter()
+            .filter_map(|(device_id, profile_slots)| {
+                profile_slots
+                    .position(profile_id)
+                    .map(|index| (device_id, index))
+            })
+            .collect()
+    }
+
+    /// Add a [`Profile`] to the library without assigning it to a device. Its id must not be
+    /// taken.
+    pub fn add_profile(&mut self, mut profile: Profile) -> Result<(), ProfileManagerError> {
+        self.check_new_id(&profile.id)?;
+        profile.metadata.validate()?;
+        profile.metadata.stamp_created(unix_time_ms());
+        self.profiles.insert(profile.id.to_string(), profile);
+        Ok(())
+    }
+
+    /// Assign a profile of the library to a slot of a device, replacing the reference in the
+    /// slot. Returns the profile that was in the slot, which stays in the library. The caller
+    /// is responsible for checking that the profile suits the device's model.
+    pub fn assign_profile(
+        &mut self,
+        device_id: &DeviceId,
+        profile_id: &ProfileId,
+        slot: usize,
+    ) -> Result<Option<ProfileId>, ProfileManagerError> {
+        let metadata = self.get_profile(profile_id)?.metadata.clone();
+        let profile_slots = self.get_device_profile_ids(device_id)?;
+        let replaced = profile_slots
+            .get(slot)
+            .ok_or_else(|| ProfileManagerError::InvalidProfileSlot(device_id.to_string(), slot))?
+            .clone();
+        match profile_slots.position(profile_id) {
+            Some(index) if index == slot => return Ok(None),
+            Some(_) => {
+                return Err(ProfileManagerError::ProfileAlreadyAssigned(
+                    profile_id.to_string(),
+                    device_id.to_string(),
+                ))
+            }
+            None => self.check_unique_name(device_id, &metadata, replaced.as_ref())?,
+        }
+
+        let profile_slots = self
+            .device_profile_map
+            .get_mut(device_id)
+            .ok_or_else(|| ProfileManagerError::UnknownDevice(device_id.to_string()))?;
+        profile_slots.set(slot, Some(profile_id.to_string()));
+        Ok(replaced)
+    }
+
     /// Associate a [`Profile`] with a given [`DeviceId`]. If the [`Device`][Device] has not been registered with
     /// the `ProfileManager` yet, an error will be returned. Similarly, if the  has no empty
-    /// `Profile` slots, an error will be returned.
+    /// `Profile` slots, an error will be returned. The profile is added to the library, its id
+    /// must not be taken. Its name must differ from the names of the device's other profiles.
     ///
     /// [Device]: crate::device::Device
     pub fn insert_profile(
         &mut self,
         device_id: &DeviceId,
-        profile: Profile,
+        mut profile: Profile,
     ) -> Result<(), ProfileManagerError> {
+        self.check_new_id(&profile.id)?;
+        profile.metadata.validate()?;
+        self.check_unique_name(device_id, &profile.metadata, None)?;
+        profile.metadata.stamp_created(unix_time_ms());
+
         let profile_ids = if let Some(profile_ids) = self.device_profile_map.get(device_id) {
             profile_ids.clone()
         } else {
@@ -165,11 +500,11 @@ impl ProfileManager {
             .next();
 
         if let Some(index) = next_available {
-            let mut tet_clone = profile_ids.clone();
-            tet_clone[index] = Some(profile.id.to_string());
+            let mut profile_slots = profile_ids.clone();
+            profile_slots.set(index, Some(profile.id.to_string()));
             self.profiles.insert(profile.id.to_string(), profile);
             self.device_profile_map
-                .insert(device_id.to_string(), tet_clone);
+                .insert(device_id.to_string(), profile_slots);
             Ok(())
         } else {
             Err(ProfileManagerError::InsufficientProfileSlots(
@@ -178,48 +513,100 @@ impl ProfileManager {
         }
     }
 
+    /// Add an imported profile and the macros it plays back for a device. A profile named like
+    /// one of the device's profiles is numbered. The profile takes the first free slot of the
+    /// device, or stays unassigned in the library if there is none. Returns the slot.
+    pub fn import_profile(
+        &mut self,
+        device_id: &DeviceId,
+        mut profile: Profile,
+        macros: Vec<Macro>,
+        limits: &MacroLimits,
+    ) -> Result<Option<usize>, ProfileManagerError> {
+        let slot = self
+            .get_device_profile_ids(device_id)?
+            .iter()
+            .position(|profile_id| profile_id.is_none());
+
+        if macros.len() > limits.max_macros {
+            return Err(ProfileManagerError::InsufficientMacroSlots(
+                device_id.to_string(),
+            ));
+        }
+        macros
+            .iter()
+            .try_for_each(|recorded| recorded.validate(limits))?;
+
+        let name = profile.metadata.name.trim().to_string();
+        profile.metadata.name = (1..)
+            .map(|n| match n {
+                1 => name.to_string(),
+                n => format!("{name} ({n})"),
+            })
+            .find(|candidate| {
+                self.check_unique_name(device_id, &ProfileMetadata::new(candidate), None)
+                    .is_ok()
+            })
+            .unwrap();
+
+        match slot {
+            Some(_) => self.insert_profile(device_id, profile)?,
+            None => self.add_profile(profile)?,
+        }
+        for recorded in macros {
+            self.macros.insert(recorded.get_id().to_string(), recorded);
+        }
+        Ok(slot)
+    }
+
     /// Overwrite an existing profile with a new profile. The provided [`ProfileId`]  must reference
     /// an existing profile associated with the given [`DeviceId`]. The existing profile will be
-    /// deleted and its profile id will be disassociated with the [`DeviceId`].
+    /// disassociated with the [`DeviceId`] and deleted, unless other devices still use it. The
+    /// new profile counts as a revision of the existing one, and may keep its id but not take
+    /// the id of another profile.
     pub fn overwrite_profile(
         &mut self,
         device_id: &DeviceId,
         profile_id: &ProfileId,
-        new_profile: Profile,
+        mut new_profile: Profile,
     ) -> Result<(), ProfileManagerError> {
+        if new_profile.id != *profile_id {
+            self.check_new_id(&new_profile.id)?;
+        }
+        new_profile.metadata.validate()?;
+        self.check_unique_name(device_id, &new_profile.metadata, Some(profile_id))?;
+
         let profile_ids = if let Some(profile_ids) = self.device_profile_map.get(device_id) {
             profile_ids.clone()
         } else {
             return Err(ProfileManagerError::UnknownDevice(device_id.to_string()));
         };
 
-        let profile_index = profile_ids
-            .iter()
-            .enumerate()
-            .fold(None, |selected, (i, v)| {
-                v.as_ref().map_or_else(
-                    || None,
-                    |v| if v == profile_id { Some(i) } else { selected },
-                )
-            });
-
-        if let Some(index) = profile_index {
-            let mut tet_clone = profile_ids.clone();
-            tet_clone[index] = Some(new_profile.id.clone());
-
-            self.profiles.remove(profile_id);
+        if let Some(index) = profile_ids.position(profile_id) {
+            let previous = self.get_profile(profile_id)?;
+            new_profile
+                .metadata
+                .stamp_revision(&previous.metadata, unix_time_ms());
+
+            let mut profile_slots = profile_ids.clone();
+            profile_slots.set(index, Some(new_profile.id.clone()));
+
             self.profiles
                 .insert(new_profile.id.to_string(), new_profile);
-
             self.device_profile_map
-                .insert(device_id.to_string(), tet_clone);
+                .insert(device_id.to_string(), profile_slots);
+
+            if self.get_profile_assignments(profile_id).is_empty() {
+                self.profiles.remove(profile_id);
+            }
             Ok(())
         } else {
             Err(ProfileManagerError::ProfileNotFound(profile_id.to_string()))
         }
     }
 
-    pub fn delete_profile(
+    /// Remove a profile from the slots of a device. The profile stays in the library.
+    pub fn unassign_profile(
         &mut self,
         device_id: &DeviceId,
         profile_id: &ProfileId,
@@ -230,37 +617,176 @@ impl ProfileManager {
             return Err(ProfileManagerError::UnknownDevice(device_id.to_string()));
         };
 
-        let profile_index = profile_ids
-            .iter()
-            .enumerate()
-            .fold(None, |selected, (i, v)| {
-                v.as_ref().map_or_else(
-                    || None,
-                    |v| if v == profile_id { Some(i) } else { selected },
-                )
-            });
-
-        if let Some(index) = profile_index {
-            self.profiles.remove(profile_id);
-            let mut tet_clone = profile_ids.clone();
-            tet_clone[index] = None;
+        if let Some(index) = profile_ids.position(profile_id) {
+            let mut profile_slots = profile_ids.clone();
+            profile_slots.set(index, None);
             self.device_profile_map
-                .insert(device_id.to_string(), tet_clone);
+                .insert(device_id.to_string(), profile_slots);
             Ok(())
         } else {
             Err(ProfileManagerError::ProfileNotFound(profile_id.to_string()))
         }
     }
 
-    pub fn delete_device(&mut self, device_id: &DeviceId) -> Result<(), ProfileManagerError> {
-        if let Some(profile_ids) = self.device_profile_map.remove(device_id) {
-            profile_ids.iter().filter(|v| v.is_some()).for_each(|v| {
-                self.profiles.remove(v.as_ref().unwrap());
-            });
-            Ok(())
-        } else {
-            Err(ProfileManagerError::UnknownDevice(device_id.to_string()))
+    /// Delete a profile from the library. Profiles still assigned to a device cannot be
+    /// deleted.
+    pub fn delete_profile(&mut self, profile_id: &ProfileId) -> Result<(), ProfileManagerError> {
+        self.get_profile(profile_id)?;
+        if let Some((device_id, _)) = self.get_profile_assignments(profile_id).first() {
+            return Err(ProfileManagerError::ProfileInUse(
+                profile_id.to_string(),
+                device_id.to_string(),
+            ));
+        }
+
+        self.profiles.remove(profile_id);
+        Ok(())
+    }
+
+    /// Move the profile slots of a device to a new [`DeviceId`].
+    pub fn rekey_device(
+        &mut self,
+        device_id: &DeviceId,
+        new_device_id: &DeviceId,
+    ) -> Result<(), ProfileManagerError> {
+        if self.device_profile_map.contains_key(new_device_id) {
+            return Err(ProfileManagerError::DeviceAlreadyRegistered(
+                new_device_id.to_string(),
+            ));
         }
+
+        let profile_ids = self
+            .device_profile_map
+            .remove(device_id)
+            .ok_or_else(|| ProfileManagerError::UnknownDevice(device_id.to_string()))?;
+
+        self.device_profile_map
+            .insert(new_device_id.to_string(), profile_ids);
+        Ok(())
+    }
+
+    /// Forget the slots of a device of the given model. Its profiles and the macros they play
+    /// stay in the library, except for the factory profiles no other device uses, which
+    /// registering the device again recreates.
+    pub fn delete_device(
+        &mut self,
+        device_id: &DeviceId,
+        device_model: &DeviceModel,
+    ) -> Result<(), ProfileManagerError> {
+        let profile_slots = self
+            .device_profile_map
+            .remove(device_id)
+            .ok_or_else(|| ProfileManagerError::UnknownDevice(device_id.to_string()))?;
+
+        let factory = DefaultProfileProvider::profile(device_model.clone());
+        for profile_id in profile_slots.iter().flatten() {
+            let is_factory = self
+                .profiles
+                .get(profile_id)
+                .is_some_and(|profile| Some(profile.get_configuration()) == factory.as_ref());
+            if is_factory && self.get_profile_assignments(profile_id).is_empty() {
+                self.profiles.remove(profile_id);
+            }
+        }
+        Ok(())
+    }
+
+    /// Forget the slots of the devices `is_registered` does not know, such as those of a
+    /// restored profile store that the device store lacks, and return their ids. Their profiles
+    /// stay in the library.
+    pub fn forget_unregistered_devices(
+        &mut self,
+        is_registered: impl Fn(&DeviceId) -> bool,
+    ) -> Vec<DeviceId> {
+        let mut forgotten = vec![];
+        self.device_profile_map.retain(|device_id, _| {
+            let keep = is_registered(device_id);
+            if !keep {
+                forgotten.push(device_id.to_string());
+            }
+            keep
+        });
+        forgotten
+    }
+
+    pub fn get_macro(&self, macro_id: &MacroId) -> Result<&Macro, ProfileManagerError> {
+        self.macros
+            .get(macro_id)
+            .ok_or_else(|| ProfileManagerError::MacroNotFound(macro_id.to_string()))
+    }
+
+    /// Every macro in the library, bound in a profile or not.
+    pub fn get_macros(&self) -> Vec<&Macro> {
+        self.macros.values().collect()
+    }
+
+    /// Store a [`Macro`] in the library, replacing any macro with the same id. The macro must
+    /// fit within the [`MacroLimits`] of the device it is recorded for.
+    pub fn insert_macro(
+        &mut self,
+        recorded: Macro,
+        limits: &MacroLimits,
+    ) -> Result<(), ProfileManagerError> {
+        recorded.validate(limits)?;
+        self.macros.insert(recorded.get_id().to_string(), recorded);
+        Ok(())
+    }
+
+    /// Delete a macro from the library. Macros still bound in a profile of the library
+    /// cannot be deleted, whether or not the profile is assigned.
+    pub fn delete_macro(&mut self, macro_id: &MacroId) -> Result<(), ProfileManagerError> {
+        self.get_macro(macro_id)?;
+        if let Some(profile) = self.profiles.values().find(|profile| {
+            profile
+                .get_configuration()
+                .get_macro_ids()
+                .contains(&macro_id)
+        }) {
+            return Err(ProfileManagerError::MacroInUse(
+                macro_id.to_string(),
+                profile.get_id().to_string(),
+            ));
+        }
+
+        self.macros.remove(macro_id);
+        Ok(())
+    }
+
+    /// Check that every macro bound in a configuration is in the library and that a device of
+    /// the given model can store them.
+    pub fn check_macro_references(
+        &self,
+        device_model: &DeviceModel,
+        configuration: &ProfileConfiguration,
+    ) -> Result<(), ProfileManagerError> {
+        let limits = MacroLimits::for_model(device_model)
+            .ok_or_else(|| ProfileManagerError::UnknownModel(device_model.to_string()))?;
+        let mut macro_ids = configuration.get_macro_ids();
+        macro_ids.sort();
+        macro_ids.dedup();
+        if macro_ids.len() > limits.max_macros {
+            return Err(ConfigurationError::out_of_range(
+                "macros",
+                macro_ids.len(),
+                &(0..=limits.max_macros),
+            )
+            .into());
+        }
+
+        macro_ids.into_iter().try_for_each(|macro_id| {
+            self.get_macro(macro_id)?.validate(&limits)?;
+            Ok(())
+        })
+    }
+
+    /// The macros bound in a configuration, as they must be uploaded alongside it.
+    pub fn get_configuration_macros(&self, configuration: &ProfileConfiguration) -> Vec<Macro> {
+        configuration
+            .get_macro_ids()
+            .into_iter()
+            .filter_map(|macro_id| self.macros.get(macro_id))
+            .cloned()
+            .collect()
     }
 }
 
@@ -269,6 +795,7 @@ impl Default for ProfileManager {
         Self {
             device_profile_map: HashMap::new(),
             profiles: HashMap::new(),
+            macros: HashMap::new(),
         }
     }
 }
@@ -278,25 +805,627 @@ impl Store for ProfileManager {
         "profiles.json"
     }
 
+    fn migrations() -> &'static [Migration] {
+        &[migrate_unversioned_profiles]
+    }
+
     #[instrument(skip_all)]
     fn from_storage(storage_manager: &StorageManager) -> Result<Self, StorageManagerError> {
-        let pm = storage_manager.read_from_storage::<ProfileManager>(Self::storage_path());
-
-        if let Err(StorageManagerError::CorruptionError(_)) = pm {
-            warn!("Corrupted device configuration file. Attempting to delete the file.");
-            storage_manager
-                .delete_from_storage(Self::storage_path())
-                .or_else(|e| {
-                    error!(e=%e, "Encountered a non-derializable file at {}. Unable to delete the file.", Self::storage_path());
-                    Err(e)
-                })?;
-        }
-        pm
+        storage_manager.read_or_recover::<ProfileManager>(Self::storage_path())
     }
 
     #[instrument(skip(storage_manager))]
     fn to_storage(&self, storage_manager: &StorageManager) -> Result<(), StorageManagerError> {
-        let ser = serde_json::to_string(self)?;
-        storage_manager.write_to_storage(Self::storage_path(), ser)
+        storage_manager.write_store(self)
+    }
+}
+
+/// Version 2 is the first versioned schema. Unversioned stores come in every shape the store
+/// had before: devices with exactly four slots stored as an array with the factory profile in
+/// the first one, profiles belonging to a single device, and profiles without metadata. Those
+/// are named after their slot, numbered where a device already has a profile of that name, and
+/// unassigned ones are named "Profile".
+fn migrate_unversioned_profiles(mut data: Value) -> Result<Value, String> {
+    let store = data
+        .as_object_mut()
+        .ok_or_else(|| "The store is not an object".to_string())?;
+    store.entry("macros").or_insert_with(|| json!({}));
+
+    let device_profile_map = store
+        .entry("device_profile_map")
+        .or_insert_with(|| json!({}));
+    let mut devices = vec![];
+    for (device_id, profile_slots) in device_profile_map
+        .as_object_mut()
+        .into_iter()
+        .flat_map(|map| map.iter_mut())
+    {
+        if let Value::Array(tetrad) = profile_slots {
+            *profile_slots = json!({"slots": tetrad, "default_slot": 0});
+        }
+        let default_slot = profile_slots["default_slot"].as_u64();
+        let slots = profile_slots["slots"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .enumerate()
+            .filter_map(|(slot, profile_id)| {
+                let name = match default_slot == Some(slot as u64) {
+                    true => "Default".to_string(),
+                    false => format!("Profile {}", slot + 1),
+                };
+                profile_id
+                    .as_str()
+                    .map(|profile_id| (profile_id.to_string(), name))
+            })
+            .collect::<Vec<_>>();
+        devices.push((device_id.to_string(), slots));
+    }
+    devices.sort();
+
+    let profiles = store.entry("profiles").or_insert_with(|| json!({}));
+    let mut names = profiles
+        .as_object()
+        .into_iter()
+        .flatten()
+        .filter_map(|(profile_id, profile)| {
+            let name = profile["metadata"]["name"].as_str()?;
+            Some((profile_id.to_string(), name.trim().to_lowercase()))
+        })
+        .collect::<HashMap<_, _>>();
+    let mut unnamed = profiles
+        .as_object()
+        .into_iter()
+        .flatten()
+        .filter(|(profile_id, _)| !names.contains_key(*profile_id))
+        .map(|(profile_id, _)| profile_id.to_string())
+        .collect::<Vec<_>>();
+    unnamed.sort();
+
+    let unique_name = |name: &str, taken: &HashSet<String>| {
+        (1..)
+            .map(|n| match n {
+                1 => name.to_string(),
+                n => format!("{name} ({n})"),
+            })
+            .find(|candidate| !taken.contains(&candidate.to_lowercase()))
+            .unwrap()
+    };
+    let mut named = vec![];
+    for (_, slots) in devices {
+        let mut taken = slots
+            .iter()
+            .filter_map(|(profile_id, _)| names.get(profile_id).cloned())
+            .collect::<HashSet<_>>();
+        for (profile_id, name) in slots {
+            if names.contains_key(&profile_id) || !unnamed.contains(&profile_id) {
+                continue;
+            }
+            let name = unique_name(&name, &taken);
+            taken.insert(name.to_lowercase());
+            names.insert(profile_id.to_string(), name.to_lowercase());
+            named.push((profile_id, name));
+        }
+    }
+    let mut taken = HashSet::new();
+    for profile_id in unnamed {
+        if !names.contains_key(&profile_id) {
+            let name = unique_name("Profile", &taken);
+            taken.insert(name.to_lowercase());
+            named.push((profile_id, name));
+        }
+    }
+
+    for (profile_id, name) in named {
+        if let Some(profile) = profiles
+            .get_mut(&profile_id)
+            .and_then(|profile| profile.as_object_mut())
+        {
+            profile.insert("metadata".to_string(), json!({"name": name}));
+        }
+    }
+    for profile in profiles
+        .as_object_mut()
+        .into_iter()
+        .flat_map(|profiles| profiles.values_mut())
+        .filter_map(|profile| profile.as_object_mut())
+    {
+        profile.remove("device_id");
+    }
+    Ok(data)
+}
+
+/// Deserialize each entry of a JSON object on its own, counting the entries that cannot be.
+fn recover_entries<T: serde::de::DeserializeOwned>(
+    value: &serde_json::Value,
+    discarded: &mut usize,
+) -> HashMap<String, T> {
+    value
+        .as_object()
+        .into_iter()
+        .flatten()
+        .filter_map(|(key, entry)| match T::deserialize(entry) {
+            Ok(entry) => Some((key.to_string(), entry)),
+            Err(e) => {
+                warn!(e=%e, "Discarding the unreadable entry {key}.");
+                *discarded += 1;
+                None
+            }
+        })
+        .collect()
+}
+
+impl Recover for ProfileManager {
+    fn recover(value: &serde_json::Value) -> Recovered<Self> {
+        let mut discarded = 0;
+        let profiles: HashMap<ProfileId, Profile> =
+            recover_entries(&value["profiles"], &mut discarded);
+        let macros: HashMap<MacroId, Macro> = recover_entries(&value["macros"], &mut discarded);
+        let mut device_profile_map: HashMap<DeviceId, ProfileSlots> =
+            recover_entries(&value["device_profile_map"], &mut discarded);
+
+        // Slots referencing a discarded profile are emptied.
+        for profile_slots in device_profile_map.values_mut() {
+            for slot in profile_slots.slots.iter_mut() {
+                if slot
+                    .as_ref()
+                    .is_some_and(|profile_id| !profiles.contains_key(profile_id))
+                {
+                    *slot = None;
+                }
+            }
+        }
+
+        Recovered {
+            recovered: profiles.len() + macros.len() + device_profile_map.len(),
+            discarded,
+            store: ProfileManager::new(device_profile_map, profiles, macros),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ProfileManager, ProfileManagerError, ProfileSelector};
+    use crate::{
+        device::{capabilities::DeviceCapabilities, DeviceModel},
+        profile::{
+            keycode::Keycode,
+            macros::{Macro, MacroEvent, MacroLimits, MacroStep, RepeatMode},
+            metadata::ProfileMetadata,
+            mouse_profile::{ButtonAction, ButtonBinding, MouseButton, MouseProfile},
+            Profile, ProfileConfiguration,
+        },
+        storage_manager::{schema, Recover},
+    };
+
+    #[test]
+    fn resolves_profile_selectors() {
+        let mut pm = ProfileManager::default();
+        let device_id = "device".to_string();
+        let profile_id = pm
+            .register_device(&device_id, DeviceModel::new("M1"))
+            .unwrap();
+
+        let resolved = pm
+            .resolve_device_profile(&device_id, &ProfileSelector::Slot(0))
+            .unwrap();
+        assert_eq!(resolved, profile_id);
+
+        let resolved = pm
+            .resolve_device_profile(&device_id, &ProfileSelector::Id(profile_id.clone()))
+            .unwrap();
+        assert_eq!(resolved, profile_id);
+
+        assert!(matches!(
+            pm.resolve_device_profile(&device_id, &ProfileSelector::Slot(1)),
+            Err(ProfileManagerError::EmptyProfileSlot(_, 1))
+        ));
+        assert!(matches!(
+            pm.resolve_device_profile(&device_id, &ProfileSelector::Slot(4)),
+            Err(ProfileManagerError::InvalidProfileSlot(_, 4))
+        ));
+    }
+
+    #[test]
+    fn rejects_profiles_of_other_devices() {
+        let mut pm = ProfileManager::default();
+        let device_id = "device".to_string();
+        let other_id = "other".to_string();
+        pm.register_device(&device_id, DeviceModel::new("M1"))
+            .unwrap();
+        let other_profile = pm
+            .register_device(&other_id, DeviceModel::new("K1"))
+            .unwrap();
+
+        assert!(matches!(
+            pm.resolve_device_profile(&device_id, &ProfileSelector::Id(other_profile)),
+            Err(ProfileManagerError::ProfileNotAssociated(_, _))
+        ));
+    }
+
+    #[test]
+    fn shares_library_profiles_between_devices() {
+        let mut pm = ProfileManager::default();
+        let first = "first".to_string();
+        let second = "second".to_string();
+        let first_default = pm.register_device(&first, DeviceModel::new("M1")).unwrap();
+        let second_default = pm.register_device(&second, DeviceModel::new("M1")).unwrap();
+
+        let profile = Profile::new(ProfileConfiguration::Mouse(MouseProfile::new(1600)));
+        let profile_id = profile.get_id().to_string();
+        pm.add_profile(profile.clone()).unwrap();
+        assert!(pm.get_profile_assignments(&profile_id).is_empty());
+        assert!(matches!(
+            pm.add_profile(profile.clone()),
+            Err(ProfileManagerError::ProfileAlreadyExists(_))
+        ));
+        assert!(matches!(
+            pm.insert_profile(&first, profile),
+            Err(ProfileManagerError::ProfileAlreadyExists(_))
+        ));
+
+        assert_eq!(pm.assign_profile(&first, &profile_id, 1).unwrap(), None);
+        assert_eq!(
+            pm.assign_profile(&second, &profile_id, 0).unwrap(),
+            Some(second_default.clone())
+        );
+        assert!(pm.get_profile(&second_default).is_ok());
+        assert_eq!(pm.get_profile_assignments(&profile_id).len(), 2);
+        assert!(matches!(
+            pm.assign_profile(&first, &profile_id, 2),
+            Err(ProfileManagerError::ProfileAlreadyAssigned(_, _))
+        ));
+
+        pm.unassign_profile(&first, &first_default).unwrap();
+        assert!(pm.get_profile(&first_default).is_ok());
+        pm.delete_profile(&first_default).unwrap();
+        assert!(pm.get_profile(&first_default).is_err());
+
+        assert!(matches!(
+            pm.delete_profile(&profile_id),
+            Err(ProfileManagerError::ProfileInUse(_, _))
+        ));
+        pm.delete_device(&first, &DeviceModel::new("M1")).unwrap();
+        pm.unassign_profile(&second, &profile_id).unwrap();
+        pm.delete_profile(&profile_id).unwrap();
+    }
+
+    #[test]
+    fn maintains_profile_metadata() {
+        let mut pm = ProfileManager::default();
+        let device_id = "device".to_string();
+        let other_id = "other".to_string();
+        let default_id = pm
+            .register_device(&device_id, DeviceModel::new("M1"))
+            .unwrap();
+        pm.register_device(&other_id, DeviceModel::new("M1"))
+            .unwrap();
+
+        let default = pm.get_profile(&default_id).unwrap().get_metadata();
+        assert_eq!(default.name, "Default");
+        assert_eq!(default.revision, 1);
+        assert!(default.created_ms > 0);
+
+        let configuration = ProfileConfiguration::Mouse(MouseProfile::new(1600));
+        let gaming =
+            Profile::new(configuration.clone()).with_metadata(ProfileMetadata::new("Gaming"));
+        let gaming_id = gaming.get_id().to_string();
+        pm.insert_profile(&device_id, gaming).unwrap();
+
+        let duplicate =
+            Profile::new(configuration.clone()).with_metadata(ProfileMetadata::new(" gaming"));
+        assert!(matches!(
+            pm.insert_profile(&device_id, duplicate.clone()),
+            Err(ProfileManagerError::DuplicateProfileName(_, _))
+        ));
+        pm.insert_profile(&other_id, duplicate).unwrap();
+        assert!(matches!(
+            pm.insert_profile(
+                &device_id,
+                Profile::new(configuration.clone()).with_metadata(ProfileMetadata::new(""))
+            ),
+            Err(ProfileManagerError::ConfigurationError(_))
+        ));
+
+        let mut metadata = ProfileMetadata::new("Gaming");
+        metadata.revision = 41;
+        metadata.created_ms = 1;
+        let revised = Profile::new(configuration).with_metadata(metadata);
+        let revised_id = revised.get_id().to_string();
+        pm.overwrite_profile(&device_id, &gaming_id, revised)
+            .unwrap();
+
+        let created_ms = pm
+            .get_profile(&default_id)
+            .unwrap()
+            .get_metadata()
+            .created_ms;
+        let revised = pm.get_profile(&revised_id).unwrap().get_metadata();
+        assert_eq!(revised.revision, 2);
+        assert!(revised.created_ms >= created_ms);
+        assert!(revised.modified_ms >= revised.created_ms);
+
+        assert!(matches!(
+            pm.overwrite_profile(
+                &device_id,
+                &revised_id,
+                Profile::new(ProfileConfiguration::Mouse(MouseProfile::new(800)))
+                    .with_metadata(ProfileMetadata::new("default"))
+            ),
+            Err(ProfileManagerError::DuplicateProfileName(_, _))
+        ));
+    }
+
+    #[test]
+    fn imports_profiles() {
+        let mut pm = ProfileManager::default();
+        let device_id = "device".to_string();
+        let model = DeviceModel::new("M1");
+        pm.register_device(&device_id, model.clone()).unwrap();
+        let limits = MacroLimits::for_model(&model).unwrap();
+        let imported = || {
+            Profile::new(ProfileConfiguration::Mouse(MouseProfile::new(800)))
+                .with_metadata(ProfileMetadata::new("Default"))
+        };
+
+        let profile = imported();
+        let profile_id = profile.get_id().to_string();
+        let slot = pm
+            .import_profile(&device_id, profile, vec![], &limits)
+            .unwrap();
+        assert_eq!(slot, Some(1));
+        let metadata = pm.get_profile(&profile_id).unwrap().get_metadata();
+        assert_eq!(metadata.name, "Default (2)");
+
+        // Once the slots are taken, imported profiles only land in the library.
+        while pm
+            .import_profile(&device_id, imported(), vec![], &limits)
+            .unwrap()
+            .is_some()
+        {}
+        let slots = pm
+            .get_device_profile_ids(&device_id)
+            .unwrap()
+            .get_slots()
+            .len();
+        assert_eq!(pm.get_profiles().len(), slots + 1);
+
+        let too_many = (0..=limits.max_macros)
+            .map(|_| Macro::new("noop", vec![], RepeatMode::Once))
+            .collect();
+        assert!(matches!(
+            pm.import_profile(&device_id, imported(), too_many, &limits),
+            Err(ProfileManagerError::InsufficientMacroSlots(_))
+        ));
+    }
+
+    #[test]
+    fn recovers_valid_entries() {
+        let mut pm = ProfileManager::default();
+        let device_id = "device".to_string();
+        let default_id = pm
+            .register_device(&device_id, DeviceModel::new("M1"))
+            .unwrap();
+
+        let mut value = serde_json::to_value(&pm).unwrap();
+        value["profiles"][&default_id]["configuration"] = serde_json::json!("broken");
+        value["macros"]["broken"] = serde_json::json!(null);
+
+        let recovered = ProfileManager::recover(&value);
+        assert_eq!((recovered.recovered, recovered.discarded), (1, 2));
+        // The slot of the discarded profile is emptied rather than left dangling.
+        let slots = recovered.store.get_device_profile_ids(&device_id).unwrap();
+        assert!(slots.iter().all(|profile_id| profile_id.is_none()));
+    }
+
+    #[test]
+    fn loads_every_stored_format() {
+        let fixtures = [
+            include_str!("../../fixtures/storage/profiles.v1.json"),
+            include_str!("../../fixtures/storage/profiles.v1-slots.json"),
+            include_str!("../../fixtures/storage/profiles.v1-library.json"),
+            include_str!("../../fixtures/storage/profiles.v2.json"),
+        ];
+        for fixture in fixtures {
+            let pm: ProfileManager = schema::from_document(fixture).unwrap();
+            for profile in pm.get_profiles() {
+                profile.get_metadata().validate().unwrap();
+            }
+            let reloaded: ProfileManager =
+                schema::from_document(&schema::to_document(&pm).unwrap()).unwrap();
+            assert_eq!(
+                serde_json::to_value(&reloaded).unwrap(),
+                serde_json::to_value(&pm).unwrap()
+            );
+        }
+
+        // Profiles of the formats without metadata are named after their slot.
+        for fixture in &fixtures[..2] {
+            let pm: ProfileManager = schema::from_document(fixture).unwrap();
+            let names = pm
+                .get_device_profile_ids(&"d069ed5e-3f58-421d-8ab1-b50ea21846e3".to_string())
+                .unwrap()
+                .iter()
+                .flatten()
+                .map(|profile_id| {
+                    pm.get_profile(profile_id)
+                        .unwrap()
+                        .get_metadata()
+                        .name
+                        .clone()
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(names, vec!["Default", "Profile 2"]);
+        }
+
+        let pm: ProfileManager = schema::from_document(fixtures[3]).unwrap();
+        let profile = pm
+            .get_profiles()
+            .into_iter()
+            .find(|profile| profile.get_metadata().name == "Editing")
+            .unwrap();
+        assert_eq!(
+            pm.get_configuration_macros(profile.get_configuration())
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn names_profiles_without_metadata() {
+        let mut value: serde_json::Value = serde_json::from_str(include_str!(
+            "../../fixtures/storage/profiles.v1-library.json"
+        ))
+        .unwrap();
+        let device_id = "d069ed5e-3f58-421d-8ab1-b50ea21846e3";
+        let profiles = value["profiles"].as_object_mut().unwrap();
+        let mut unassigned = profiles.values().next().unwrap().clone();
+        unassigned["id"] = serde_json::json!("unassigned");
+        profiles.insert("unassigned".to_string(), unassigned);
+        for profile in profiles.values_mut() {
+            profile.as_object_mut().unwrap().remove("metadata");
+        }
+        // A second device whose default slot already holds a profile named "Default".
+        let named = value["profiles"]["2d31d3cc-d25e-42ef-8cad-fb4cbe0497e7"].clone();
+        let mut renamed = named.clone();
+        renamed["id"] = serde_json::json!("named");
+        renamed["metadata"] = serde_json::json!({"name": "Default"});
+        value["profiles"]["named"] = renamed;
+        let mut unnamed = named;
+        unnamed["id"] = serde_json::json!("unnamed");
+        value["profiles"]["unnamed"] = unnamed;
+        value["device_profile_map"]["other"] =
+            serde_json::json!({"slots": ["unnamed", "named"], "default_slot": 0});
+
+        let pm: ProfileManager = schema::from_document(&value.to_string()).unwrap();
+        let names = |device_id: &str| {
+            pm.get_device_profile_ids(&device_id.to_string())
+                .unwrap()
+                .iter()
+                .flatten()
+                .map(|profile_id| {
+                    pm.get_profile(profile_id)
+                        .unwrap()
+                        .get_metadata()
+                        .name
+                        .clone()
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(device_id), vec!["Default", "Profile 2"]);
+        assert_eq!(names("other"), vec!["Default (2)", "Default"]);
+        assert_eq!(
+            pm.get_profile(&"unassigned".to_string())
+                .unwrap()
+                .get_metadata()
+                .name,
+            "Profile"
+        );
+    }
+
+    #[test]
+    fn migrates_profile_tetrads() {
+        let json = r#"{
+            "device_profile_map": {"device": ["default", null, "custom", null]},
+            "profiles": {}
+        }"#;
+        let mut pm: ProfileManager = schema::from_document(json).unwrap();
+        let device_id = "device".to_string();
+
+        let slots = pm.get_device_profile_ids(&device_id).unwrap();
+        assert_eq!(slots.get_slots().len(), 4);
+        assert_eq!(slots.get_default_slot(), 0);
+        assert_eq!(
+            pm.get_device_first_available_profile(&device_id).unwrap(),
+            "default"
+        );
+
+        let mut capabilities = DeviceCapabilities::for_model(&DeviceModel::new("M1"))
+            .unwrap()
+            .clone();
+        capabilities.profile_slots = 2;
+        capabilities.default_profile_slot = 2;
+        pm.fit_device_slots(&device_id, &capabilities).unwrap();
+        let slots = pm.get_device_profile_ids(&device_id).unwrap();
+        assert_eq!(slots.get_slots().len(), 3);
+        assert_eq!(slots.get_default_slot(), 2);
+        assert_eq!(slots.get(0), Some(&Some("custom".to_string())));
+
+        capabilities.profile_slots = 6;
+        pm.fit_device_slots(&device_id, &capabilities).unwrap();
+        let slots = pm.get_device_profile_ids(&device_id).unwrap();
+        assert_eq!(slots.get_slots().len(), 6);
+        assert_eq!(
+            pm.get_device_first_available_profile(&device_id).unwrap(),
+            "default"
+        );
+
+        let json = r#"{
+            "device_profile_map": {"device": {"slots": [null, null], "default_slot": 2}},
+            "profiles": {}
+        }"#;
+        assert!(schema::from_document::<ProfileManager>(json).is_err());
+
+        let json = schema::to_document(&pm).unwrap();
+        let pm: ProfileManager = schema::from_document(&json).unwrap();
+        assert_eq!(pm.get_device_profile_ids(&device_id).unwrap(), slots);
+    }
+
+    #[test]
+    fn keeps_bound_macros() {
+        let mut pm = ProfileManager::default();
+        let device_id = "device".to_string();
+        pm.register_device(&device_id, DeviceModel::new("M1"))
+            .unwrap();
+
+        let recorded = Macro::new(
+            "copy",
+            vec![MacroStep {
+                event: MacroEvent::KeyPress(Keycode::C),
+                delay_ms: 0,
+            }],
+            RepeatMode::Once,
+        );
+        let macro_id = recorded.get_id().to_string();
+        let limits = MacroLimits::for_model(&DeviceModel::new("M1")).unwrap();
+        pm.insert_macro(recorded, &limits).unwrap();
+
+        let mut buttons = MouseProfile::default().get_buttons().to_vec();
+        buttons.retain(|binding| binding.button != MouseButton::Back);
+        buttons.push(ButtonBinding {
+            button: MouseButton::Back,
+            action: ButtonAction::Macro(macro_id.clone()),
+        });
+        let configuration =
+            ProfileConfiguration::Mouse(MouseProfile::default().with_buttons(buttons));
+        pm.check_macro_references(&DeviceModel::new("M1"), &configuration)
+            .unwrap();
+        assert_eq!(pm.get_configuration_macros(&configuration).len(), 1);
+
+        let profile = Profile::new(configuration);
+        let profile_id = profile.get_id().to_string();
+        pm.insert_profile(&device_id, profile).unwrap();
+        assert!(matches!(
+            pm.delete_macro(&macro_id),
+            Err(ProfileManagerError::MacroInUse(_, _))
+        ));
+
+        // The macros of a deleted device's profiles stay in the library for other devices.
+        let other = "other".to_string();
+        let other_default = pm.register_device(&other, DeviceModel::new("M1")).unwrap();
+        pm.delete_device(&device_id, &DeviceModel::new("M1"))
+            .unwrap();
+        assert!(pm.get_macro(&macro_id).is_ok());
+        pm.assign_profile(&other, &profile_id, 1).unwrap();
+
+        pm.unassign_profile(&other, &profile_id).unwrap();
+        pm.delete_profile(&profile_id).unwrap();
+        pm.delete_macro(&macro_id).unwrap();
+        assert!(pm.get_macros().is_empty());
+
+        // Factory profiles are recreated on registration, so they leave with their device.
+        pm.delete_device(&other, &DeviceModel::new("M1")).unwrap();
+        assert!(pm.get_profile(&other_default).is_err());
     }
 }
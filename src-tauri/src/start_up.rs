@@ -1,16 +1,21 @@
 use core::panic;
 use std::time::Instant;
-use tracing::{error, info, instrument};
+use tracing::{error, info, instrument, warn};
 
+#[cfg(debug_assertions)]
+use crate::device::{
+    discovery::{DeviceEnumerator, DiscoveredDevice},
+    simulated::SimulatedBackend,
+};
 use crate::{
-    device::device_manager::DeviceManager,
+    device::{capabilities, device_manager::DeviceManager, discovery::DiscoveryService, identity},
     profile::profile_manager::ProfileManager,
     state::{ApplicationState, ApplicationStateBuilder},
-    storage_manager::{StorageManager, Store},
+    storage_manager::{BackupPolicy, StorageManager, Store},
 };
 
 #[instrument]
-pub(crate) fn run() -> ApplicationState {
+pub(crate) fn run() -> (ApplicationState, DiscoveryService) {
     info!("Start up procedure started");
     let t_s = Instant::now();
     let storage_manager = StorageManager::new();
@@ -22,53 +27,73 @@ pub(crate) fn run() -> ApplicationState {
             panic!("FATAL ERROR: Start up failure. Could not create local storage directory.");
         })
         .unwrap();
+    let backup_policy = BackupPolicy::from_storage(&storage_manager).unwrap_or_default();
+    let storage_manager = storage_manager.with_backup_policy(backup_policy);
 
-    #[cfg(debug_assertions)]
-    let (device_manager, profile_manager) = debug_manager_creation(&storage_manager);
+    capabilities::init_registry(&storage_manager);
 
-    #[cfg(not(debug_assertions))]
-    let (device_manager, profile_manager) = (
-        DeviceManager::from_storage(&storage_manager).unwrap_or_default(),
-        ProfileManager::from_storage(&storage_manager).unwrap_or_default()
-    );
+    let mut device_manager = DeviceManager::from_storage(&storage_manager).unwrap_or_default();
+    let mut profile_manager = ProfileManager::from_storage(&storage_manager).unwrap_or_default();
 
-    device_manager.to_storage(&storage_manager).unwrap();
-    profile_manager.to_storage(&storage_manager).unwrap();
+    #[cfg(debug_assertions)]
+    let enumerator: Box<dyn DeviceEnumerator> = Box::new(SimulatedBackend::with_default_devices());
 
+    #[cfg(not(debug_assertions))]
+    let enumerator = crate::device::discovery::platform_enumerator();
 
-    let t_e = (Instant::now() - t_s).as_micros();
-    info!("Start up procedure complete. Start up time: {t_e} μs");
+    let attached = enumerator.enumerate();
+    identity::migrate_legacy_devices(&attached, &mut device_manager, &mut profile_manager);
+    fit_profile_slots(&device_manager, &mut profile_manager);
 
-    ApplicationStateBuilder::new()
+    #[cfg_attr(not(debug_assertions), allow(unused_mut))]
+    let mut state = ApplicationStateBuilder::new()
         .with_device_manager(device_manager)
         .with_storage_manager(storage_manager)
         .with_profile_manager(profile_manager)
-        .build()
-}
-
+        .build();
 
-#[cfg(debug_assertions)]
-fn debug_manager_creation(storage_manager: &StorageManager) -> (DeviceManager, ProfileManager){
-    use crate::device::{mouse::Mouse, Device, DeviceModel, DeviceType};
-    let mut device_manager = DeviceManager::from_storage(&storage_manager).unwrap_or_default();
-    let mut profile_manager = ProfileManager::from_storage(&storage_manager).unwrap_or_default();
+    #[cfg(debug_assertions)]
+    debug_device_registration(&attached, &mut state);
 
-    let mut device_one = Device::builder()
-        .with_device_type(DeviceType::Mouse(Mouse { dpi: 800 }))
-        .with_model(DeviceModel::M1);
+    // Fails when a corrupt store could not be quarantined, which is then kept as it is.
+    if let Err(e) = state.persist() {
+        error!(e=%e, "Could not persist the state at start up.");
+    }
 
-    let id = profile_manager
-        .register_device(
-            device_one.get_id(), 
-            device_one.get_model().clone().unwrap()
-        )
-        .unwrap();
+    let t_e = (Instant::now() - t_s).as_micros();
+    info!("Start up procedure complete. Start up time: {t_e} μs");
 
-    device_one = device_one.with_active_profile(id);
+    (state, DiscoveryService::new(enumerator))
+}
 
-    let id = device_one.get_id().clone();
-    device_manager.insert_device(&id, device_one.build())
-        .unwrap();
-    (device_manager, profile_manager)
+/// Fit the profile slots of every registered device to its model, which migrates devices
+/// stored with the four slots every device used to have.
+pub(crate) fn fit_profile_slots(
+    device_manager: &DeviceManager,
+    profile_manager: &mut ProfileManager,
+) {
+    for device in device_manager.get_devices() {
+        let Some(capabilities) = device.get_model().get_capabilities() else {
+            continue;
+        };
+        if let Err(e) = profile_manager.fit_device_slots(device.get_id(), capabilities) {
+            warn!(e=%e, "Could not fit the profile slots of {}.", device.get_id());
+        }
+    }
 }
 
+/// Register the devices of the [`SimulatedBackend`] so that development builds have devices to
+/// work with without any hardware attached. The backend is handed to the discovery service
+/// which attaches the devices as if they had been plugged in.
+#[cfg(debug_assertions)]
+fn debug_device_registration(attached: &[DiscoveredDevice], state: &mut ApplicationState) {
+    for discovered in attached {
+        if state
+            .get_device_manager()
+            .get_device(&discovered.device_id())
+            .is_err()
+        {
+            state.register_device(discovered).unwrap();
+        }
+    }
+}
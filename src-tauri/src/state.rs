@@ -1,8 +1,36 @@
+use std::cmp::Reverse;
+
+use thiserror::Error;
+use tracing::{error, info, instrument, warn};
+
 use crate::{
-    device::device_manager::DeviceManager, profile::profile_manager::ProfileManager,
-    storage_manager::StorageManager,
+    archive::{ArchiveError, ConfigurationArchive, RestorePreview},
+    device::{
+        capabilities,
+        device_manager::{DeviceManager, DeviceManagerError},
+        discovery::DiscoveredDevice,
+        identity, ConnectionState, Device, DeviceId,
+    },
+    profile::profile_manager::{ProfileManager, ProfileManagerError},
+    start_up,
+    storage_manager::{BackupInfo, StorageManager, StorageManagerError, Store},
 };
 
+#[derive(Debug, Error)]
+pub(crate) enum StateError {
+    #[error(transparent)]
+    DeviceManagerError(#[from] DeviceManagerError),
+
+    #[error(transparent)]
+    ProfileManagerError(#[from] ProfileManagerError),
+
+    #[error(transparent)]
+    ArchiveError(#[from] ArchiveError),
+
+    #[error("StorageManagerError: {0}")]
+    StorageManagerError(#[from] StorageManagerError),
+}
+
 #[derive(Debug, Default)]
 pub(crate) struct ApplicationState {
     device_manager: DeviceManager,
@@ -46,6 +74,228 @@ impl ApplicationState {
     pub(crate) fn get_profile_manager_mut(&mut self) -> &mut ProfileManager {
         &mut self.profile_manager
     }
+
+    /// Write the device and profile stores to disk.
+    pub(crate) fn persist(&self) -> Result<(), StateError> {
+        self.profile_manager.to_storage(&self.storage_manager)?;
+        self.device_manager.to_storage(&self.storage_manager)?;
+        Ok(())
+    }
+
+    /// Register a discovered device along with its default profile and persist both stores.
+    /// If either store cannot be written the registration is rolled back, so a device is never
+    /// stored without its profiles or the other way around.
+    #[instrument(skip(self))]
+    pub(crate) fn register_device(
+        &mut self,
+        discovered: &DiscoveredDevice,
+    ) -> Result<Device, StateError> {
+        let device_id = discovered.device_id();
+        if self.device_manager.get_device(&device_id).is_ok() {
+            return Err(DeviceManagerError::DeviceAlreadyRegistered(device_id).into());
+        }
+
+        let model = discovered.get_model().clone();
+        let device_type = model
+            .default_device_type()
+            .ok_or_else(|| ProfileManagerError::UnknownModel(model.to_string()))?;
+        let had_profiles = self
+            .profile_manager
+            .get_device_profile_ids(&device_id)
+            .is_ok();
+
+        // Profiles left behind by a previous registration of the same device are reused.
+        let profile_id = if had_profiles {
+            self.profile_manager
+                .get_device_first_available_profile(&device_id)
+                .or_else(|_| {
+                    self.profile_manager
+                        .insert_device_default_profile(&device_id, &model)
+                })?
+        } else {
+            self.profile_manager
+                .register_device(&device_id, model.clone())?
+        };
+
+        let device = Device::builder()
+            .with_hardware_identity(discovered.get_identity().clone())
+            .with_device_type(device_type)
+            .with_model(model.clone())
+            .with_active_profile(profile_id.to_string())
+            .build();
+        self.device_manager
+            .insert_device(&device_id, device.clone())?;
+
+        if let Err(e) = self.persist() {
+            error!(e=%e, "Could not persist the registration of {device_id}. Rolling back.");
+            self.device_manager.delete_device(&device_id)?;
+            if !had_profiles {
+                // Also deletes the factory profile created for the device.
+                self.profile_manager.delete_device(&device_id, &model)?;
+            }
+            self.persist()?;
+            return Err(e);
+        }
+
+        Ok(device)
+    }
+
+    /// Re-key the legacy devices among those attached and persist them, so that a legacy
+    /// device plugged in after start up keeps its profiles instead of being registered again.
+    pub(crate) fn migrate_legacy_devices(
+        &mut self,
+        attached: &[DiscoveredDevice],
+    ) -> Result<(), StateError> {
+        let migrated = identity::migrate_legacy_devices(
+            attached,
+            &mut self.device_manager,
+            &mut self.profile_manager,
+        );
+        if !migrated.is_empty() {
+            self.persist()?;
+        }
+        Ok(())
+    }
+
+    /// Apply the active profile of a device to the hardware. Returns the device with its
+    /// updated [`SyncStatus`][SyncStatus].
+    ///
+    /// [SyncStatus]: crate::device::sync::SyncStatus
+    pub(crate) fn sync_active_profile(
+        &mut self,
+        device_id: &DeviceId,
+    ) -> Result<Device, StateError> {
+        let profile_id = self
+            .device_manager
+            .get_device(device_id)?
+            .get_active_profile();
+        let configuration = self
+            .profile_manager
+            .get_profile(profile_id)?
+            .get_configuration()
+            .clone();
+        let macros = self
+            .profile_manager
+            .get_configuration_macros(&configuration);
+
+        self.device_manager
+            .sync_device(device_id, &configuration, &macros)?;
+        Ok(self.device_manager.get_device(device_id)?.clone())
+    }
+
+    /// Replace the configuration with the one held by an archive and return what changed.
+    #[instrument(skip_all)]
+    pub(crate) fn restore_archive(
+        &mut self,
+        archive: &ConfigurationArchive,
+    ) -> Result<RestorePreview, StateError> {
+        let preview = archive.preview(
+            &self.device_manager,
+            &self.profile_manager,
+            &self.storage_manager,
+        )?;
+        let (device_manager, profile_manager) = archive.stores()?;
+        let model_definitions = archive.model_definitions()?;
+
+        // The definitions are written once the stores are, so a refused restore leaves none.
+        self.replace_stores(device_manager, profile_manager)?;
+        for (file_name, contents) in model_definitions {
+            self.storage_manager
+                .write_file(
+                    &format!("{}/{file_name}", capabilities::USER_MODELS_DIR),
+                    contents.as_bytes(),
+                )
+                .map_err(|e| ArchiveError::IOError(e.to_string()))?;
+        }
+        Ok(preview)
+    }
+
+    /// The backups of the device and profile stores, newest first.
+    pub(crate) fn list_backups(&self) -> Result<Vec<BackupInfo>, StateError> {
+        let mut backups = [
+            DeviceManager::storage_path(),
+            ProfileManager::storage_path(),
+        ]
+        .into_iter()
+        .map(|filename| self.storage_manager.list_backups(filename))
+        .collect::<Result<Vec<_>, _>>()?
+        .concat();
+        backups.sort_by_key(|backup| Reverse(backup.created_ms));
+        Ok(backups)
+    }
+
+    /// Go back to a backup of the device or the profile store. The backup is read the way the
+    /// store is loaded at start up, and one that cannot be is refused before anything is
+    /// replaced. The version being replaced is itself backed up.
+    #[instrument(skip(self))]
+    pub(crate) fn restore_backup(&mut self, file: &str, created_ms: u64) -> Result<(), StateError> {
+        let current_devices = || {
+            DeviceManager::new(
+                self.device_manager
+                    .get_devices()
+                    .into_iter()
+                    .map(|device| (device.get_id().to_string(), device.clone()))
+                    .collect(),
+            )
+        };
+
+        let (device_manager, profile_manager) = if file == DeviceManager::storage_path() {
+            let device_manager = self
+                .storage_manager
+                .read_backup::<DeviceManager>(file, created_ms)?;
+            (device_manager, self.profile_manager.clone())
+        } else if file == ProfileManager::storage_path() {
+            let profile_manager = self
+                .storage_manager
+                .read_backup::<ProfileManager>(file, created_ms)?;
+            (current_devices(), profile_manager)
+        } else {
+            return Err(StorageManagerError::UnknownStore(file.to_string()).into());
+        };
+
+        self.replace_stores(device_manager, profile_manager)
+    }
+
+    /// Persist restored stores and make them the running ones. The stores are written before
+    /// the running state is touched, so a failed write leaves the application as it was. The
+    /// slots of devices missing from the restored devices are dropped, and attached devices are
+    /// synced with their restored profiles.
+    fn replace_stores(
+        &mut self,
+        device_manager: DeviceManager,
+        mut profile_manager: ProfileManager,
+    ) -> Result<(), StateError> {
+        start_up::fit_profile_slots(&device_manager, &mut profile_manager);
+        let forgotten = profile_manager
+            .forget_unregistered_devices(|device_id| device_manager.get_device(device_id).is_ok());
+        if !forgotten.is_empty() {
+            info!("Forgot the profile slots of unregistered devices {forgotten:?}.");
+        }
+
+        profile_manager.to_storage(&self.storage_manager)?;
+        if let Err(e) = device_manager.to_storage(&self.storage_manager) {
+            error!(e=%e, "Could not write the restored devices. Rolling back.");
+            self.persist()?;
+            return Err(e.into());
+        }
+
+        self.profile_manager = profile_manager;
+        self.device_manager.restore_devices(device_manager);
+
+        let connected: Vec<DeviceId> = self
+            .device_manager
+            .get_devices()
+            .into_iter()
+            .filter(|device| device.get_connection_state() == ConnectionState::Connected)
+            .map(|device| device.get_id().to_string())
+            .collect();
+        for device_id in connected {
+            if let Err(e) = self.sync_active_profile(&device_id) {
+                warn!(e=%e, "Could not sync {device_id} with its restored profile.");
+            }
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug)]
@@ -87,3 +337,177 @@ impl ApplicationStateBuilder {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{ApplicationState, ApplicationStateBuilder, StateError};
+    use crate::{
+        archive::ConfigurationArchive,
+        device::{
+            device_manager::{DeviceManager, DeviceManagerError},
+            discovery::DiscoveredDevice,
+            simulated::SimulatedDevice,
+            Device, DeviceModel,
+        },
+        storage_manager::StorageManager,
+    };
+
+    /// A state whose storage is its own, so that tests do not see each other's files.
+    fn hermetic_state() -> ApplicationState {
+        ApplicationStateBuilder::new()
+            .with_storage_manager(StorageManager::in_memory())
+            .build()
+    }
+
+    #[test]
+    fn registers_discovered_device_with_default_profile() {
+        let mut state = hermetic_state();
+        state
+            .get_storage_manager()
+            .initailize_storage_directory()
+            .unwrap();
+
+        let simulated = SimulatedDevice::new(DeviceModel::new("K1"), "register-test");
+        let discovered = DiscoveredDevice::new(
+            DeviceModel::new("K1"),
+            simulated.get_identity(),
+            "simulated:register-test".to_string(),
+        );
+
+        let device = state.register_device(&discovered).unwrap();
+        assert_eq!(device.get_id(), &discovered.device_id());
+        assert!(state
+            .get_profile_manager()
+            .get_profile(device.get_active_profile())
+            .is_ok());
+
+        assert!(matches!(
+            state.register_device(&discovered),
+            Err(StateError::DeviceManagerError(
+                DeviceManagerError::DeviceAlreadyRegistered(_)
+            ))
+        ));
+    }
+
+    #[test]
+    fn migrates_legacy_devices_plugged_in_later() {
+        let mut state = hermetic_state();
+        let profile_id = state
+            .get_profile_manager_mut()
+            .register_device(&"legacy".to_string(), DeviceModel::new("M1"))
+            .unwrap();
+        let legacy = Device::builder()
+            .with_id("legacy".to_string())
+            .with_model(DeviceModel::new("M1"))
+            .with_device_type(DeviceModel::new("M1").default_device_type().unwrap())
+            .with_active_profile(profile_id.to_string())
+            .build();
+        state
+            .get_device_manager_mut()
+            .insert_device(&"legacy".to_string(), legacy)
+            .unwrap();
+
+        let simulated = SimulatedDevice::new(DeviceModel::new("M1"), "legacy-test");
+        let discovered = DiscoveredDevice::new(
+            DeviceModel::new("M1"),
+            simulated.get_identity(),
+            "simulated:legacy-test".to_string(),
+        );
+        state
+            .migrate_legacy_devices(std::slice::from_ref(&discovered))
+            .unwrap();
+
+        let device = state
+            .get_device_manager()
+            .get_device(&discovered.device_id())
+            .unwrap();
+        assert_eq!(device.get_active_profile(), &profile_id);
+        assert!(state
+            .get_profile_manager()
+            .get_device_profile_ids(&discovered.device_id())
+            .is_ok());
+        assert!(state.register_device(&discovered).is_err());
+    }
+
+    #[test]
+    fn restores_configuration_archive() {
+        let mut state = hermetic_state();
+        state
+            .get_storage_manager()
+            .initailize_storage_directory()
+            .unwrap();
+
+        let simulated = SimulatedDevice::new(DeviceModel::new("M1"), "archive-test");
+        let discovered = DiscoveredDevice::new(
+            DeviceModel::new("M1"),
+            simulated.get_identity(),
+            "simulated:archive-test".to_string(),
+        );
+        let device = state.register_device(&discovered).unwrap();
+        let archive = ConfigurationArchive::new(
+            state.get_device_manager(),
+            state.get_profile_manager(),
+            state.get_storage_manager(),
+        )
+        .unwrap();
+
+        state
+            .get_device_manager_mut()
+            .delete_device(device.get_id())
+            .unwrap();
+        let preview = state.restore_archive(&archive).unwrap();
+        assert_eq!(preview.devices.added.len(), 1);
+        assert!(preview.profiles.added.is_empty());
+        assert!(state
+            .get_device_manager()
+            .get_device(device.get_id())
+            .is_ok());
+    }
+
+    #[test]
+    fn restores_only_valid_backups() {
+        let mut state = hermetic_state();
+        state
+            .get_storage_manager()
+            .initailize_storage_directory()
+            .unwrap();
+        state.persist().unwrap();
+
+        let simulated = SimulatedDevice::new(DeviceModel::new("K1"), "backup-test");
+        let discovered = DiscoveredDevice::new(
+            DeviceModel::new("K1"),
+            simulated.get_identity(),
+            "simulated:backup-test".to_string(),
+        );
+        state.register_device(&discovered).unwrap();
+
+        let backup = state
+            .list_backups()
+            .unwrap()
+            .into_iter()
+            .find(|backup| backup.file == "device.json")
+            .unwrap();
+        let backed_up = state
+            .get_storage_manager()
+            .read_backup::<DeviceManager>("device.json", backup.created_ms)
+            .unwrap();
+        state
+            .restore_backup("device.json", backup.created_ms)
+            .unwrap();
+        assert_eq!(
+            state.get_device_manager().get_devices().len(),
+            backed_up.get_devices().len()
+        );
+        // The backup predates the device, whose slots go with it.
+        assert!(state
+            .get_profile_manager()
+            .get_device_profile_ids(&discovered.device_id())
+            .is_err());
+
+        let storage_manager = state.get_storage_manager();
+        let corrupt = storage_manager.backup_path("profiles.json", 1);
+        storage_manager.write_to_storage(&corrupt, "{").unwrap();
+        assert!(state.restore_backup("profiles.json", 1).is_err());
+        assert!(state.restore_backup("settings.json", 1).is_err());
+    }
+}
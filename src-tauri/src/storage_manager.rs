@@ -1,14 +1,43 @@
 use bytes::Bytes;
-use directories::BaseDirs;
-use serde::de::DeserializeOwned;
-use std::{fs, io};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use specta::Type;
+use std::{
+    cmp::Reverse,
+    collections::HashSet,
+    fs::{self, File},
+    io::{self, Write},
+    path::Path,
+    sync::Mutex,
+    time::Duration,
+};
 
 use thiserror::Error;
-use tracing::{error, info, instrument};
+use tracing::{error, info, instrument, warn};
+
+use crate::profile::metadata::unix_time_ms;
+
+pub(crate) mod backend;
+pub(crate) mod file_format;
+pub(crate) mod schema;
+
+use backend::{FileSystemBackend, MemoryBackend, StorageBackend, TempDirBackend};
+use schema::Migration;
+
+/// Corrupt files are moved to this directory of the storage directory.
+pub(crate) const QUARANTINE_DIR: &str = "quarantine";
+
+/// Previous versions of each file are kept in a directory of this directory named after it.
+pub(crate) const BACKUP_DIR: &str = "backups";
 
 pub(crate) trait Store {
     fn storage_path() -> &'static str;
 
+    /// The migrations from each schema version of the stored document to the next, starting
+    /// with the unversioned documents written before stores carried a version.
+    fn migrations() -> &'static [Migration] {
+        &[]
+    }
+
     fn from_storage(storage_manager: &StorageManager) -> Result<Self, StorageManagerError>
     where
         Self: Sized;
@@ -16,11 +45,143 @@ pub(crate) trait Store {
     fn to_storage(&self, storage_manager: &StorageManager) -> Result<(), StorageManagerError>;
 }
 
+/// A store that can salvage the entries of a file it cannot read as a whole.
+pub(crate) trait Recover: Sized {
+    /// Rebuild the store from the entries of `value` that are valid on their own.
+    fn recover(value: &serde_json::Value) -> Recovered<Self>;
+}
+
+/// A store rebuilt from a corrupt file, with the number of entries kept and dropped.
+#[derive(Debug)]
+pub(crate) struct Recovered<T> {
+    pub store: T,
+    pub recovered: usize,
+    pub discarded: usize,
+}
+
+/// Tells the user that a stored file could not be read and what was made of it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Type)]
+pub(crate) struct RecoveryNotice {
+    pub file: String,
+    /// Where the corrupt file was moved, for recovering the rest by hand. `None` when it could
+    /// not be moved, it is then left in place and not overwritten until the next start.
+    pub quarantine_path: Option<String>,
+    pub recovered: usize,
+    /// `None` when the file was not JSON at all, so its entries could not be counted.
+    pub discarded: Option<usize>,
+}
+
+/// Replace the file at `path` with `buffer` so that a crash or power loss leaves either the old
+/// or the new contents, never a mix. The buffer is written and flushed to a temporary file next
+/// to the target, which is then renamed over it, and the rename is flushed by syncing the
+/// directory.
+pub(crate) fn write_atomically(path: &Path, buffer: &[u8]) -> io::Result<()> {
+    write_atomically_with(path, buffer, |file, buffer| file.write_all(buffer))
+}
+
+fn write_atomically_with(
+    path: &Path,
+    buffer: &[u8],
+    write: impl FnOnce(&mut File, &[u8]) -> io::Result<()>,
+) -> io::Result<()> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "No file name"))?;
+    let temp_path = path.with_file_name(format!(".{}.tmp", file_name.to_string_lossy()));
+
+    let written = File::create(&temp_path).and_then(|mut file| {
+        write(&mut file, buffer)?;
+        file.sync_all()
+    });
+    if let Err(e) = written.and_then(|_| fs::rename(&temp_path, path)) {
+        let _ = fs::remove_file(&temp_path);
+        return Err(e);
+    }
+
+    sync_parent_dir(path)
+}
+
+/// Directories cannot be opened as files on Windows, where the rename is durable once it
+/// returns.
+#[cfg(unix)]
+fn sync_parent_dir(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => File::open(dir)?.sync_all(),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(not(unix))]
+fn sync_parent_dir(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// How many previous versions of each file are kept, and for how long. The policy is stored
+/// with the files it applies to and can be changed from the interface.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Type)]
+pub(crate) struct BackupPolicy {
+    pub max_count: usize,
+    pub max_age_days: u32,
+}
+
+impl BackupPolicy {
+    fn max_age(&self) -> Duration {
+        Duration::from_secs(u64::from(self.max_age_days) * 24 * 60 * 60)
+    }
+}
+
+impl Default for BackupPolicy {
+    fn default() -> Self {
+        Self {
+            max_count: 10,
+            max_age_days: 30,
+        }
+    }
+}
+
+impl Store for BackupPolicy {
+    fn storage_path() -> &'static str {
+        "backup_policy.json"
+    }
+
+    fn from_storage(storage_manager: &StorageManager) -> Result<Self, StorageManagerError> {
+        storage_manager.read_store(Self::storage_path())
+    }
+
+    fn to_storage(&self, storage_manager: &StorageManager) -> Result<(), StorageManagerError> {
+        storage_manager.write_store(self)
+    }
+}
+
+/// A previous version of a stored file.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Type)]
+pub(crate) struct BackupInfo {
+    pub file: String,
+    /// When the version was replaced, which also identifies the backup.
+    pub created_ms: u64,
+    pub size: u64,
+}
+
 #[derive(Debug, Error)]
 pub(crate) enum StorageManagerError {
     #[error("IO error: {0}")]
     IOError(#[from] io::Error),
 
+    #[error("Backup not found: {0} of {1}")]
+    BackupNotFound(u64, String),
+
+    #[error("Unknown store: {0}")]
+    UnknownStore(String),
+
+    #[error("Held file: {0} is corrupt and could not be quarantined")]
+    HeldFile(String),
+
+    #[error("Unsupported schema version: {0}")]
+    UnsupportedSchemaVersion(u32),
+
+    #[error("Migration error: {0}")]
+    MigrationError(String),
+
     #[error("Corruption error: {0}")]
     CorruptionError(#[from] serde_json::Error),
 
@@ -31,24 +192,70 @@ pub(crate) enum StorageManagerError {
 #[cfg_attr(any(test, debug_assertions), allow(dead_code))]
 #[derive(Debug)]
 pub(crate) struct StorageManager {
-    base_dirs: BaseDirs,
+    backend: Box<dyn StorageBackend>,
+    backup_policy: BackupPolicy,
+    recovery_notices: Mutex<Vec<RecoveryNotice>>,
+    /// Corrupt files that could not be quarantined, which are kept as they are.
+    held_files: Mutex<HashSet<String>>,
 }
 
 impl StorageManager {
+    /// Storage in the user's local data directory.
     pub(crate) fn new() -> Self {
+        Self::with_backend(FileSystemBackend::user_data())
+    }
+
+    pub(crate) fn with_backend(backend: impl StorageBackend + 'static) -> Self {
         Self {
-            base_dirs: BaseDirs::new().unwrap(),
+            backend: Box::new(backend),
+            backup_policy: BackupPolicy::default(),
+            recovery_notices: Mutex::new(vec![]),
+            held_files: Mutex::new(HashSet::new()),
         }
     }
 
-    #[cfg(any(test, debug_assertions))]
-    pub(crate) fn get_storage_dir_path(&self) -> std::path::PathBuf {
-        self.base_dirs.data_local_dir().join("crabby").join("test")
+    /// Storage that lives as long as the storage manager and is shared with nothing else.
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub(crate) fn in_memory() -> Self {
+        Self::with_backend(MemoryBackend::default())
+    }
+
+    /// Storage in a fresh temporary directory, removed along with the storage manager.
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub(crate) fn temporary() -> Self {
+        Self::with_backend(TempDirBackend::new())
+    }
+
+    pub(crate) fn with_backup_policy(mut self, backup_policy: BackupPolicy) -> Self {
+        self.backup_policy = backup_policy;
+        self
+    }
+
+    pub(crate) fn get_backup_policy(&self) -> BackupPolicy {
+        self.backup_policy
+    }
+
+    /// Store a new [`BackupPolicy`], which applies from the next write of each file.
+    pub(crate) fn set_backup_policy(
+        &mut self,
+        backup_policy: BackupPolicy,
+    ) -> Result<(), StorageManagerError> {
+        backup_policy.to_storage(self)?;
+        self.backup_policy = backup_policy;
+        Ok(())
     }
 
-    #[cfg(not(any(test, debug_assertions)))]
-    pub(crate) fn get_storage_dir_path(&self) -> std::path::PathBuf {
-        self.base_dirs.data_local_dir().join("crabby")
+    /// The storage directory, `None` when the files are not kept on disk.
+    pub(crate) fn get_storage_dir_path(&self) -> Option<&Path> {
+        self.backend.root()
+    }
+
+    /// Where a file is, for showing to the user: its path when the storage is on disk.
+    fn describe(&self, filename: &str) -> String {
+        match self.get_storage_dir_path() {
+            Some(dir) => dir.join(filename).to_string_lossy().to_string(),
+            None => filename.to_string(),
+        }
     }
 
     #[instrument]
@@ -56,10 +263,8 @@ impl StorageManager {
         &self,
         filename: &str,
     ) -> Result<T, StorageManagerError> {
-        let path = self.get_storage_dir_path().join(filename);
-        let file = fs::read_to_string(&path).map_err(|e| {
-            let path_str_rep = path.to_str();
-            info!(e=%e, "Could not read file at path: {path_str_rep:?}");
+        let file = self.read_file(filename).map_err(|e| {
+            info!(e=%e, "Could not read file: {}", self.describe(filename));
             e
         })?;
 
@@ -70,13 +275,15 @@ impl StorageManager {
         Ok(out)
     }
 
+    /// Write a file, keeping its previous version as a backup. A file is only backed up when
+    /// its contents change, and a failed backup does not prevent the write. A corrupt file that
+    /// could not be quarantined is never written.
     #[instrument(skip(buffer))]
     pub(crate) fn write_to_storage(
         &self,
         filename: &str,
         buffer: impl TryInto<Bytes>,
     ) -> Result<(), StorageManagerError> {
-        let path = self.get_storage_dir_path().join(filename);
         let buffer: Bytes = buffer.try_into().map_err(|_| {
             error!("Could not convert buffer to &[u8].");
             StorageManagerError::ConversionError(anyhow::anyhow!(
@@ -84,39 +291,228 @@ impl StorageManager {
             ))
         })?;
 
-        fs::write(path, buffer)?;
+        if self
+            .held_files
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .contains(filename)
+        {
+            return Err(StorageManagerError::HeldFile(filename.to_string()));
+        }
+        if let Err(e) = self.back_up(filename, &buffer) {
+            warn!(e=%e, "Could not back up {filename}.");
+        }
+        self.write_file(filename, &buffer)
+    }
+
+    /// Read a file as text, as it is stored.
+    pub(crate) fn read_file(&self, filename: &str) -> Result<String, StorageManagerError> {
+        let contents = self.backend.read(filename)?;
+        String::from_utf8(contents).map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("{filename}: {e}")).into()
+        })
+    }
+
+    /// Replace a file without backing it up.
+    pub(crate) fn write_file(
+        &self,
+        filename: &str,
+        contents: &[u8],
+    ) -> Result<(), StorageManagerError> {
+        Ok(self.backend.write(filename, contents)?)
+    }
+
+    /// The names of the files in a directory of the storage, in no particular order.
+    pub(crate) fn list_files(&self, dir: &str) -> Result<Vec<String>, StorageManagerError> {
+        Ok(self.backend.list(dir)?)
+    }
+
+    /// The path of a backup relative to the storage directory, for reading it like any other
+    /// stored file.
+    pub(crate) fn backup_path(&self, filename: &str, created_ms: u64) -> String {
+        format!("{BACKUP_DIR}/{filename}/{created_ms}.json")
+    }
+
+    /// Copy the current version of a file to its backups before it is replaced by `buffer`,
+    /// then drop the backups the [`BackupPolicy`] no longer keeps.
+    fn back_up(&self, filename: &str, buffer: &[u8]) -> Result<(), StorageManagerError> {
+        let current = match self.backend.read(filename) {
+            Ok(current) => current,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        if current == buffer {
+            return Ok(());
+        }
+
+        self.write_file(&self.backup_path(filename, unix_time_ms()), &current)?;
+        self.prune_backups(filename)
+    }
+
+    fn prune_backups(&self, filename: &str) -> Result<(), StorageManagerError> {
+        let oldest_ms =
+            unix_time_ms().saturating_sub(self.backup_policy.max_age().as_millis() as u64);
+        for (position, backup) in self.list_backups(filename)?.iter().enumerate() {
+            if position >= self.backup_policy.max_count || backup.created_ms < oldest_ms {
+                self.delete_from_storage(&self.backup_path(filename, backup.created_ms))?;
+            }
+        }
         Ok(())
     }
 
+    /// The backups of a file, newest first.
+    pub(crate) fn list_backups(
+        &self,
+        filename: &str,
+    ) -> Result<Vec<BackupInfo>, StorageManagerError> {
+        let mut backups = self
+            .list_files(&format!("{BACKUP_DIR}/{filename}"))?
+            .into_iter()
+            .filter_map(|name| {
+                let created_ms = name.strip_suffix(".json")?.parse().ok()?;
+                let size = self
+                    .backend
+                    .read(&self.backup_path(filename, created_ms))
+                    .ok()?
+                    .len();
+                Some(BackupInfo {
+                    file: filename.to_string(),
+                    created_ms,
+                    size: size as u64,
+                })
+            })
+            .collect::<Vec<_>>();
+        backups.sort_by_key(|backup| Reverse(backup.created_ms));
+        Ok(backups)
+    }
+
+    /// Read a backup of a store, migrating it like the store itself. A backup that cannot be
+    /// read is an error, it is never recovered from.
+    pub(crate) fn read_backup<T: Store + DeserializeOwned>(
+        &self,
+        filename: &str,
+        created_ms: u64,
+    ) -> Result<T, StorageManagerError> {
+        match self.read_store(&self.backup_path(filename, created_ms)) {
+            Err(StorageManagerError::IOError(e)) if e.kind() == io::ErrorKind::NotFound => Err(
+                StorageManagerError::BackupNotFound(created_ms, filename.to_string()),
+            ),
+            result => result,
+        }
+    }
+
+    /// Read a store written by [`write_store`][Self::write_store], migrating older schema
+    /// versions.
+    pub(crate) fn read_store<T: Store + DeserializeOwned>(
+        &self,
+        filename: &str,
+    ) -> Result<T, StorageManagerError> {
+        schema::from_document(&self.read_file(filename)?)
+    }
+
+    /// Write a store in an envelope carrying its schema version.
+    pub(crate) fn write_store<T: Store + Serialize>(
+        &self,
+        store: &T,
+    ) -> Result<(), StorageManagerError> {
+        self.write_to_storage(T::storage_path(), schema::to_document(store)?)
+    }
+
+    /// Read a store, falling back to the entries that can be recovered when the file is corrupt.
+    /// A corrupt file is moved to the quarantine directory rather than deleted, and a
+    /// [`RecoveryNotice`] is kept for the user. A file that cannot be moved is held instead: the
+    /// recovered entries are used but never written over it.
+    #[instrument(skip(self))]
+    pub(crate) fn read_or_recover<T: Store + DeserializeOwned + Recover>(
+        &self,
+        filename: &str,
+    ) -> Result<T, StorageManagerError> {
+        match self.read_store::<T>(filename) {
+            Err(
+                e @ (StorageManagerError::CorruptionError(_)
+                | StorageManagerError::UnsupportedSchemaVersion(_)
+                | StorageManagerError::MigrationError(_)),
+            ) => {
+                warn!(e=%e, "Unreadable configuration file {filename}. Recovering what is valid.");
+            }
+            result => return result,
+        }
+
+        let contents = self.read_file(filename)?;
+        let quarantine_path = match self.quarantine(filename) {
+            Ok(quarantine_path) => Some(self.describe(&quarantine_path)),
+            Err(e) => {
+                error!(e=%e, "Could not quarantine {filename}. Leaving it in place.");
+                self.held_files
+                    .lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner())
+                    .insert(filename.to_string());
+                None
+            }
+        };
+
+        let (store, recovered, discarded) = match schema::salvage::<T>(&contents) {
+            Some(value) => {
+                let recovered = T::recover(&value);
+                (
+                    recovered.store,
+                    recovered.recovered,
+                    Some(recovered.discarded),
+                )
+            }
+            None => {
+                let recovered = T::recover(&serde_json::Value::Null);
+                (recovered.store, 0, None)
+            }
+        };
+        info!("Recovered {recovered} entries of {filename}, discarded {discarded:?}.");
+
+        self.recovery_notices
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .push(RecoveryNotice {
+                file: filename.to_string(),
+                quarantine_path,
+                recovered,
+                discarded,
+            });
+        Ok(store)
+    }
+
+    /// Move a file to the quarantine directory, under a name stamped with the current time so
+    /// that earlier quarantined files are kept. Returns its new name in the storage.
+    pub(crate) fn quarantine(&self, filename: &str) -> Result<String, StorageManagerError> {
+        let quarantined = format!("{QUARANTINE_DIR}/{}-{filename}", unix_time_ms());
+        self.backend.rename(filename, &quarantined)?;
+        Ok(quarantined)
+    }
+
+    /// The notices of the files recovered since the last call.
+    pub(crate) fn take_recovery_notices(&self) -> Vec<RecoveryNotice> {
+        std::mem::take(
+            &mut *self
+                .recovery_notices
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner()),
+        )
+    }
+
     pub(crate) fn delete_from_storage(&self, filename: &str) -> Result<(), StorageManagerError> {
-        let path = self.get_storage_dir_path().join(filename);
-        fs::remove_file(path)?;
+        self.backend.remove(filename)?;
         Ok(())
     }
 
     fn delete_storage(&self) -> Result<(), StorageManagerError> {
-        let path = self.get_storage_dir_path();
-        fs::remove_dir_all(path)?;
+        self.backend.destroy()?;
         Ok(())
     }
 
     #[instrument]
     pub(crate) fn initailize_storage_directory(&self) -> Result<(), StorageManagerError> {
-        let path = self.get_storage_dir_path();
-        match path.try_exists() {
-            Ok(true) => Ok(()),
-            Ok(false) => {
-                fs::create_dir(path).map_err(|e| {
-                    error!(e=%e, "Could not initialize directory.");
-                    e
-                })?;
-                Ok(())
-            }
-            Err(e) => {
-                error!(e=%e, "Could not determine if application's data storage directory exists.");
-                Err(e.into())
-            }
-        }
+        self.backend.initialize().map_err(|e| {
+            error!(e=%e, "Could not initialize the application's data storage.");
+            e.into()
+        })
     }
 }
 
@@ -133,22 +529,22 @@ mod tests {
         DeviceModel, DeviceType,
     };
 
-    use super::StorageManager;
-    use std::collections::hash_map::HashMap;
+    use super::{write_atomically_with, BackupPolicy, StorageManager, StorageManagerError, Store};
+    use std::{collections::hash_map::HashMap, fs, io, io::Write, thread, time::Duration};
     use tracing::info;
 
     macro_rules! devices {
         () => {{
             let device_one = Device::builder()
                 .with_id("d069ed5e-3f58-421d-8ab1-b50ea21846e3".to_string())
-                .with_model(DeviceModel::M1)
+                .with_model(DeviceModel::new("M1"))
                 .with_device_type(DeviceType::Mouse(Mouse { dpi: 800 }))
                 .with_active_profile("asd".to_string())
                 .build();
 
             let device_two = Device::builder()
                 .with_id("6693616e-76c6-41e6-853f-0e5db1d2857f".to_string())
-                .with_model(DeviceModel::K1)
+                .with_model(DeviceModel::new("K1"))
                 .with_device_type(DeviceType::Keyboard(Keyboard {}))
                 .with_active_profile("dsa".to_string())
                 .build();
@@ -164,25 +560,26 @@ mod tests {
 
     #[test]
     fn can_create_and_delete_storage_dir() {
-        let sm = StorageManager::new();
-        info!("{:?}", sm.get_storage_dir_path());
+        for sm in [StorageManager::in_memory(), StorageManager::temporary()] {
+            info!("{:?}", sm.get_storage_dir_path());
 
-        // creates
-        assert!(sm.initailize_storage_directory().is_ok());
-        // already exists so does nothing
-        assert!(sm.initailize_storage_directory().is_ok());
+            // creates
+            assert!(sm.initailize_storage_directory().is_ok());
+            // already exists so does nothing
+            assert!(sm.initailize_storage_directory().is_ok());
 
-        // check delete
-        assert!(sm.delete_storage().is_ok());
+            // check delete
+            assert!(sm.delete_storage().is_ok());
 
-        // delete fails when storage does not exist
-        assert!(sm.delete_storage().is_err());
+            // delete fails when storage does not exist
+            assert!(sm.delete_storage().is_err());
+        }
     }
 
     #[test]
     fn can_read_write_struct() {
         let config = DeviceManager::new(devices!());
-        let sm = StorageManager::new();
+        let sm = StorageManager::in_memory();
 
         sm.initailize_storage_directory().unwrap();
 
@@ -202,7 +599,7 @@ mod tests {
     fn can_overwrite_file() {
         let config_one = DeviceManager::default();
         let config_two = DeviceManager::new(devices!());
-        let sm = StorageManager::new();
+        let sm = StorageManager::in_memory();
 
         assert!(sm.initailize_storage_directory().is_ok());
 
@@ -235,7 +632,7 @@ mod tests {
     #[test]
     fn can_delete_file() {
         let config = DeviceManager::default();
-        let sm = StorageManager::new();
+        let sm = StorageManager::in_memory();
 
         sm.initailize_storage_directory().unwrap();
 
@@ -244,4 +641,143 @@ mod tests {
         assert!(sm.write_to_storage("devices.json", serialized).is_ok());
         assert!(sm.delete_from_storage("devices.json").is_ok());
     }
+
+    #[test]
+    fn interrupted_write_keeps_previous_contents() {
+        let config = DeviceManager::new(devices!());
+        let sm = StorageManager::temporary();
+        sm.initailize_storage_directory().unwrap();
+
+        let serialized = serde_json::to_string(&config).unwrap();
+        sm.write_to_storage("interrupted.json", serialized).unwrap();
+
+        // The process dies half way through writing the replacement.
+        let path = sm.get_storage_dir_path().unwrap().join("interrupted.json");
+        let result = write_atomically_with(&path, b"{\"devices\": {}}", |file, buffer| {
+            file.write_all(&buffer[..buffer.len() / 2])?;
+            Err(io::Error::new(io::ErrorKind::Interrupted, "crash"))
+        });
+        assert!(result.is_err());
+
+        let deserialized = sm.read_from_storage::<DeviceManager>("interrupted.json");
+        assert_eq!(deserialized.unwrap(), config);
+        assert!(!sm
+            .get_storage_dir_path()
+            .unwrap()
+            .join(".interrupted.json.tmp")
+            .exists());
+    }
+
+    #[test]
+    fn leftover_temporary_file_is_replaced() {
+        let config = DeviceManager::new(devices!());
+        let sm = StorageManager::temporary();
+        sm.initailize_storage_directory().unwrap();
+
+        // A crash before the rename leaves a truncated temporary file behind.
+        let temp_path = sm
+            .get_storage_dir_path()
+            .unwrap()
+            .join(".leftover.json.tmp");
+        fs::write(&temp_path, "{\"devi").unwrap();
+
+        let serialized = serde_json::to_string(&config).unwrap();
+        sm.write_to_storage("leftover.json", serialized).unwrap();
+
+        let deserialized = sm.read_from_storage::<DeviceManager>("leftover.json");
+        assert_eq!(deserialized.unwrap(), config);
+        assert!(!temp_path.exists());
+    }
+
+    #[test]
+    fn quarantines_and_recovers_corrupt_file() {
+        let sm = StorageManager::temporary();
+        sm.initailize_storage_directory().unwrap();
+
+        let mut value = serde_json::to_value(DeviceManager::new(devices!())).unwrap();
+        value["devices"]["broken"] = serde_json::json!({"id": "broken"});
+        sm.write_to_storage("corrupt.json", value.to_string())
+            .unwrap();
+
+        let recovered = sm.read_or_recover::<DeviceManager>("corrupt.json").unwrap();
+        assert_eq!(recovered, DeviceManager::new(devices!()));
+        assert!(sm.read_file("corrupt.json").is_err());
+
+        let notices = sm.take_recovery_notices();
+        let notice = notices
+            .iter()
+            .find(|notice| notice.file == "corrupt.json")
+            .unwrap();
+        assert_eq!((notice.recovered, notice.discarded), (2, Some(1)));
+        assert_eq!(
+            fs::read_to_string(notice.quarantine_path.as_ref().unwrap()).unwrap(),
+            value.to_string()
+        );
+
+        // A file that is not JSON at all is quarantined as well, nothing can be recovered.
+        sm.write_to_storage("truncated.json", "{\"devices\": {\"d0")
+            .unwrap();
+        let recovered = sm
+            .read_or_recover::<DeviceManager>("truncated.json")
+            .unwrap();
+        assert_eq!(recovered, DeviceManager::default());
+        assert!(sm
+            .take_recovery_notices()
+            .iter()
+            .any(|notice| notice.file == "truncated.json" && notice.discarded.is_none()));
+
+        // A file that cannot be quarantined is recovered from but kept as it is.
+        let quarantine_dir = sm
+            .get_storage_dir_path()
+            .unwrap()
+            .join(super::QUARANTINE_DIR);
+        fs::remove_dir_all(&quarantine_dir).unwrap();
+        fs::write(&quarantine_dir, "").unwrap();
+        sm.write_to_storage("held.json", value.to_string()).unwrap();
+        let recovered = sm.read_or_recover::<DeviceManager>("held.json").unwrap();
+        assert_eq!(recovered, DeviceManager::new(devices!()));
+        assert!(sm
+            .take_recovery_notices()
+            .iter()
+            .any(|notice| notice.file == "held.json" && notice.quarantine_path.is_none()));
+        assert!(matches!(
+            sm.write_to_storage("held.json", "{}"),
+            Err(StorageManagerError::HeldFile(_))
+        ));
+        assert_eq!(sm.read_file("held.json").unwrap(), value.to_string());
+    }
+
+    #[test]
+    fn keeps_rolling_backups() {
+        let mut sm = StorageManager::in_memory();
+        sm.initailize_storage_directory().unwrap();
+        sm.set_backup_policy(BackupPolicy {
+            max_count: 2,
+            ..BackupPolicy::default()
+        })
+        .unwrap();
+        assert_eq!(
+            BackupPolicy::from_storage(&sm).unwrap(),
+            sm.get_backup_policy()
+        );
+
+        for version in 0..4 {
+            sm.write_to_storage("rolling.json", format!("[{version}]"))
+                .unwrap();
+            // Unchanged contents are not backed up again.
+            sm.write_to_storage("rolling.json", format!("[{version}]"))
+                .unwrap();
+            thread::sleep(Duration::from_millis(2));
+        }
+
+        let backups = sm.list_backups("rolling.json").unwrap();
+        assert_eq!(backups.len(), 2);
+        assert!(backups[0].created_ms > backups[1].created_ms);
+        let newest = sm.backup_path("rolling.json", backups[0].created_ms);
+        assert_eq!(sm.read_file(&newest).unwrap(), "[2]");
+        assert!(matches!(
+            sm.read_backup::<DeviceManager>("rolling.json", 1),
+            Err(StorageManagerError::BackupNotFound(1, _))
+        ));
+    }
 }
@@ -0,0 +1,321 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Debug,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use directories::BaseDirs;
+use uuid::Uuid;
+
+use super::write_atomically;
+
+/// Where a [`StorageManager`][StorageManager] keeps its files. Files are named by their path
+/// relative to the root of the storage, directories separated by `/`.
+///
+/// [StorageManager]: super::StorageManager
+pub(crate) trait StorageBackend: Debug + Send + Sync {
+    /// Create the storage if it does not exist yet.
+    fn initialize(&self) -> io::Result<()>;
+
+    fn read(&self, name: &str) -> io::Result<Vec<u8>>;
+
+    /// Replace a file, creating the directories leading to it. A crash during the write leaves
+    /// either the old or the new contents.
+    fn write(&self, name: &str, contents: &[u8]) -> io::Result<()>;
+
+    fn rename(&self, from: &str, to: &str) -> io::Result<()>;
+
+    fn remove(&self, name: &str) -> io::Result<()>;
+
+    /// The names of the files directly in a directory. A missing directory holds no files.
+    fn list(&self, dir: &str) -> io::Result<Vec<String>>;
+
+    /// Remove the storage along with every file in it.
+    fn destroy(&self) -> io::Result<()>;
+
+    /// The directory holding the files, for backends that keep them on disk.
+    fn root(&self) -> Option<&Path>;
+}
+
+/// Keeps the files in a directory of the file system.
+#[derive(Debug)]
+pub(crate) struct FileSystemBackend {
+    root: PathBuf,
+}
+
+impl FileSystemBackend {
+    pub(crate) fn new(root: PathBuf) -> Self {
+        Self { root }
+    }
+
+    /// The application's directory in the user's local data directory. Development builds and
+    /// tests use a directory of their own.
+    pub(crate) fn user_data() -> Self {
+        let root = BaseDirs::new().unwrap().data_local_dir().join("crabby");
+        if cfg!(any(test, debug_assertions)) {
+            Self::new(root.join("test"))
+        } else {
+            Self::new(root)
+        }
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.root.join(name)
+    }
+}
+
+impl StorageBackend for FileSystemBackend {
+    fn initialize(&self) -> io::Result<()> {
+        fs::create_dir_all(&self.root)
+    }
+
+    fn read(&self, name: &str) -> io::Result<Vec<u8>> {
+        fs::read(self.path(name))
+    }
+
+    fn write(&self, name: &str, contents: &[u8]) -> io::Result<()> {
+        let path = self.path(name);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        write_atomically(&path, contents)
+    }
+
+    fn rename(&self, from: &str, to: &str) -> io::Result<()> {
+        let to = self.path(to);
+        if let Some(dir) = to.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::rename(self.path(from), to)
+    }
+
+    fn remove(&self, name: &str) -> io::Result<()> {
+        fs::remove_file(self.path(name))
+    }
+
+    fn list(&self, dir: &str) -> io::Result<Vec<String>> {
+        let entries = match fs::read_dir(self.path(dir)) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e),
+        };
+        let mut names = entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().is_file())
+            .filter_map(|entry| entry.file_name().to_str().map(str::to_string))
+            .collect::<Vec<_>>();
+        names.sort();
+        Ok(names)
+    }
+
+    fn destroy(&self) -> io::Result<()> {
+        fs::remove_dir_all(&self.root)
+    }
+
+    fn root(&self) -> Option<&Path> {
+        Some(&self.root)
+    }
+}
+
+/// Keeps the files in memory, for tests that should not touch the disk.
+#[cfg_attr(not(test), allow(dead_code))]
+#[derive(Debug, Default)]
+pub(crate) struct MemoryBackend {
+    /// `None` until the storage is initialized or first written to.
+    files: Mutex<Option<BTreeMap<String, Vec<u8>>>>,
+}
+
+impl MemoryBackend {
+    fn with_files<T>(
+        &self,
+        f: impl FnOnce(&mut Option<BTreeMap<String, Vec<u8>>>) -> io::Result<T>,
+    ) -> io::Result<T> {
+        f(&mut self
+            .files
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()))
+    }
+}
+
+fn not_found(name: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("{name} does not exist"))
+}
+
+impl StorageBackend for MemoryBackend {
+    fn initialize(&self) -> io::Result<()> {
+        self.with_files(|files| {
+            files.get_or_insert_with(BTreeMap::new);
+            Ok(())
+        })
+    }
+
+    fn read(&self, name: &str) -> io::Result<Vec<u8>> {
+        self.with_files(|files| {
+            files
+                .as_ref()
+                .and_then(|files| files.get(name))
+                .cloned()
+                .ok_or_else(|| not_found(name))
+        })
+    }
+
+    fn write(&self, name: &str, contents: &[u8]) -> io::Result<()> {
+        self.with_files(|files| {
+            files
+                .get_or_insert_with(BTreeMap::new)
+                .insert(name.to_string(), contents.to_vec());
+            Ok(())
+        })
+    }
+
+    fn rename(&self, from: &str, to: &str) -> io::Result<()> {
+        self.with_files(|files| {
+            let files = files.as_mut().ok_or_else(|| not_found(from))?;
+            let contents = files.remove(from).ok_or_else(|| not_found(from))?;
+            files.insert(to.to_string(), contents);
+            Ok(())
+        })
+    }
+
+    fn remove(&self, name: &str) -> io::Result<()> {
+        self.with_files(|files| {
+            files
+                .as_mut()
+                .and_then(|files| files.remove(name))
+                .map(|_| ())
+                .ok_or_else(|| not_found(name))
+        })
+    }
+
+    fn list(&self, dir: &str) -> io::Result<Vec<String>> {
+        let prefix = format!("{}/", dir.trim_end_matches('/'));
+        self.with_files(|files| {
+            Ok(files
+                .iter()
+                .flatten()
+                .filter_map(|(name, _)| name.strip_prefix(&prefix))
+                .filter(|name| !name.contains('/'))
+                .map(str::to_string)
+                .collect())
+        })
+    }
+
+    fn destroy(&self) -> io::Result<()> {
+        self.with_files(|files| files.take().map(|_| ()).ok_or_else(|| not_found("storage")))
+    }
+
+    fn root(&self) -> Option<&Path> {
+        None
+    }
+}
+
+/// Keeps the files in a directory of its own in the system's temporary directory, removed
+/// when the backend is dropped. For tests that need real files.
+#[cfg_attr(not(test), allow(dead_code))]
+#[derive(Debug)]
+pub(crate) struct TempDirBackend {
+    inner: FileSystemBackend,
+}
+
+#[cfg_attr(not(test), allow(dead_code))]
+impl TempDirBackend {
+    pub(crate) fn new() -> Self {
+        Self {
+            inner: FileSystemBackend::new(
+                std::env::temp_dir().join(format!("crabby-{}", Uuid::new_v4())),
+            ),
+        }
+    }
+}
+
+impl Drop for TempDirBackend {
+    fn drop(&mut self) {
+        let _ = self.inner.destroy();
+    }
+}
+
+impl StorageBackend for TempDirBackend {
+    fn initialize(&self) -> io::Result<()> {
+        self.inner.initialize()
+    }
+
+    fn read(&self, name: &str) -> io::Result<Vec<u8>> {
+        self.inner.read(name)
+    }
+
+    fn write(&self, name: &str, contents: &[u8]) -> io::Result<()> {
+        self.inner.write(name, contents)
+    }
+
+    fn rename(&self, from: &str, to: &str) -> io::Result<()> {
+        self.inner.rename(from, to)
+    }
+
+    fn remove(&self, name: &str) -> io::Result<()> {
+        self.inner.remove(name)
+    }
+
+    fn list(&self, dir: &str) -> io::Result<Vec<String>> {
+        self.inner.list(dir)
+    }
+
+    fn destroy(&self) -> io::Result<()> {
+        self.inner.destroy()
+    }
+
+    fn root(&self) -> Option<&Path> {
+        self.inner.root()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MemoryBackend, StorageBackend, TempDirBackend};
+
+    fn backends() -> Vec<Box<dyn StorageBackend>> {
+        vec![
+            Box::new(MemoryBackend::default()),
+            Box::new(TempDirBackend::new()),
+        ]
+    }
+
+    #[test]
+    fn backends_behave_alike() {
+        for backend in backends() {
+            backend.initialize().unwrap();
+            assert!(backend.read("missing.json").is_err());
+            assert!(backend.list("backups").unwrap().is_empty());
+
+            backend.write("a.json", b"a").unwrap();
+            backend.write("backups/a.json/1.json", b"1").unwrap();
+            backend.write("backups/a.json/2.json", b"2").unwrap();
+            assert_eq!(backend.read("a.json").unwrap(), b"a");
+            assert_eq!(
+                backend.list("backups/a.json").unwrap(),
+                vec!["1.json", "2.json"]
+            );
+            assert!(backend.list("backups").unwrap().is_empty());
+
+            backend.rename("a.json", "quarantine/a.json").unwrap();
+            assert!(backend.read("a.json").is_err());
+            backend.remove("quarantine/a.json").unwrap();
+            assert!(backend.remove("quarantine/a.json").is_err());
+
+            backend.destroy().unwrap();
+            assert!(backend.destroy().is_err());
+        }
+    }
+
+    #[test]
+    fn temporary_directory_is_removed_on_drop() {
+        let backend = TempDirBackend::new();
+        backend.write("a.json", b"a").unwrap();
+        let root = backend.root().unwrap().to_path_buf();
+        assert!(root.exists());
+
+        drop(backend);
+        assert!(!root.exists());
+    }
+}
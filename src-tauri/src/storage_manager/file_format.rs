@@ -0,0 +1,69 @@
+use std::{io, path::Path};
+
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::Value;
+
+use super::write_atomically;
+
+/// A format of the files the user exports, identified by a header in the file rather than by
+/// its name.
+pub(crate) struct FileFormat {
+    /// The `format` of the header.
+    pub name: &'static str,
+    /// What a file of the format is to the user.
+    pub description: &'static str,
+    pub extension: &'static str,
+    /// The newest version of the format. Files of newer versions are refused.
+    pub version: u32,
+    /// Where the header is in the file, as a JSON pointer.
+    pub header: &'static str,
+}
+
+/// Why a file could not be read as a [`FileFormat`].
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum FileFormatError {
+    Malformed(String),
+    UnsupportedVersion(u32),
+}
+
+#[derive(Deserialize)]
+struct Header {
+    format: String,
+    format_version: u32,
+}
+
+impl FileFormat {
+    /// Read a file of the format. The header is checked before the rest so that files of newer
+    /// versions are reported as such rather than as malformed.
+    pub(crate) fn parse<T: DeserializeOwned>(&self, json: &str) -> Result<T, FileFormatError> {
+        let malformed = |e: serde_json::Error| FileFormatError::Malformed(e.to_string());
+
+        let document: Value = serde_json::from_str(json).map_err(malformed)?;
+        let header = document
+            .pointer(self.header)
+            .cloned()
+            .ok_or_else(|| FileFormatError::Malformed(format!("Not a {}", self.description)))?;
+        let header: Header = serde_json::from_value(header).map_err(malformed)?;
+        if header.format != self.name {
+            return Err(FileFormatError::Malformed(format!(
+                "Not a {} ({})",
+                self.description, header.format
+            )));
+        }
+        if header.format_version > self.version {
+            return Err(FileFormatError::UnsupportedVersion(header.format_version));
+        }
+
+        serde_json::from_value(document).map_err(malformed)
+    }
+
+    /// Write a file of the format, adding the extension of the format to paths without one.
+    pub(crate) fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        let path = if path.extension().is_none() {
+            path.with_extension(self.extension)
+        } else {
+            path.to_path_buf()
+        };
+        write_atomically(&path, contents)
+    }
+}
@@ -0,0 +1,164 @@
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Value};
+
+use super::{StorageManagerError, Store};
+
+/// Turns the contents of a store of one schema version into the contents of the next version.
+pub(crate) type Migration = fn(Value) -> Result<Value, String>;
+
+/// The schema version of documents written before stores were versioned. Those documents are
+/// the store itself rather than an envelope.
+pub(crate) const UNVERSIONED: u32 = 1;
+
+/// The schema version written by a store, one past the version its last migration reads.
+pub(crate) fn current_version<T: Store>() -> u32 {
+    UNVERSIONED + T::migrations().len() as u32
+}
+
+/// Serialize a store in an envelope carrying its schema version.
+pub(crate) fn to_document<T: Store + Serialize>(store: &T) -> Result<String, serde_json::Error> {
+    serde_json::to_string(&json!({
+        "schema_version": current_version::<T>(),
+        "data": store,
+    }))
+}
+
+/// Deserialize a store of any schema version up to the current one, migrating older versions.
+pub(crate) fn from_document<T: Store + DeserializeOwned>(
+    contents: &str,
+) -> Result<T, StorageManagerError> {
+    let document = serde_json::from_str(contents)?;
+    Ok(serde_json::from_value(migrate::<T>(document)?)?)
+}
+
+/// The contents of a document brought to the current schema version.
+pub(crate) fn migrate<T: Store>(document: Value) -> Result<Value, StorageManagerError> {
+    let (version, mut data) = unwrap_envelope(document);
+    if version > current_version::<T>() {
+        return Err(StorageManagerError::UnsupportedSchemaVersion(version));
+    }
+    if version < UNVERSIONED {
+        return Err(StorageManagerError::MigrationError(format!(
+            "There is no schema version {version}"
+        )));
+    }
+
+    for (from, migration) in T::migrations()
+        .iter()
+        .enumerate()
+        .skip((version - UNVERSIONED) as usize)
+    {
+        data = migration(data).map_err(|e| {
+            StorageManagerError::MigrationError(format!(
+                "Version {} to {}: {e}",
+                from as u32 + UNVERSIONED,
+                from as u32 + UNVERSIONED + 1
+            ))
+        })?;
+    }
+    Ok(data)
+}
+
+/// The contents of a document that could not be read, as far along the migrations as they
+/// go, for recovering what can be from them.
+pub(crate) fn salvage<T: Store>(contents: &str) -> Option<Value> {
+    let document: Value = serde_json::from_str(contents).ok()?;
+    migrate::<T>(document.clone())
+        .ok()
+        .or_else(|| Some(unwrap_envelope(document).1))
+}
+
+fn unwrap_envelope(document: Value) -> (u32, Value) {
+    match document {
+        Value::Object(mut envelope)
+            if envelope.len() == 2
+                && envelope.contains_key("data")
+                && envelope.get("schema_version").is_some_and(Value::is_u64) =>
+        {
+            let version = envelope["schema_version"].as_u64().unwrap_or_default();
+            let data = envelope.remove("data").unwrap_or_default();
+            (u32::try_from(version).unwrap_or(u32::MAX), data)
+        }
+        data => (UNVERSIONED, data),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+    use serde_json::{json, Value};
+
+    use super::{from_document, migrate, to_document, Migration};
+    use crate::storage_manager::{StorageManager, StorageManagerError, Store};
+
+    /// A store that renamed a field in version 2 and added one in version 3.
+    #[derive(Debug, PartialEq, Deserialize, Serialize)]
+    struct Counter {
+        count: u32,
+        step: u32,
+    }
+
+    impl Store for Counter {
+        fn storage_path() -> &'static str {
+            "counter.json"
+        }
+
+        fn migrations() -> &'static [Migration] {
+            &[
+                |mut data| {
+                    let total = data["total"].take();
+                    data["count"] = total;
+                    Ok(data)
+                },
+                |mut data| {
+                    data["step"] = json!(1);
+                    Ok(data)
+                },
+            ]
+        }
+
+        fn from_storage(_: &StorageManager) -> Result<Self, StorageManagerError> {
+            Err(StorageManagerError::UnknownStore(
+                Self::storage_path().to_string(),
+            ))
+        }
+
+        fn to_storage(&self, _: &StorageManager) -> Result<(), StorageManagerError> {
+            Err(StorageManagerError::UnknownStore(
+                Self::storage_path().to_string(),
+            ))
+        }
+    }
+
+    #[test]
+    fn runs_the_migration_chain() {
+        let expected = Counter { count: 4, step: 1 };
+        assert_eq!(
+            from_document::<Counter>(r#"{"total": 4}"#).unwrap(),
+            expected
+        );
+        assert_eq!(
+            from_document::<Counter>(r#"{"schema_version": 2, "data": {"count": 4}}"#).unwrap(),
+            expected
+        );
+
+        let document = to_document(&expected).unwrap();
+        assert_eq!(
+            serde_json::from_str::<Value>(&document).unwrap()["schema_version"],
+            json!(3)
+        );
+        assert_eq!(from_document::<Counter>(&document).unwrap(), expected);
+    }
+
+    #[test]
+    fn refuses_newer_versions() {
+        assert!(matches!(
+            migrate::<Counter>(json!({"schema_version": 4, "data": {}})),
+            Err(StorageManagerError::UnsupportedSchemaVersion(4))
+        ));
+        assert!(matches!(
+            migrate::<Counter>(json!({"schema_version": 0, "data": {}})),
+            Err(StorageManagerError::MigrationError(_))
+        ));
+    }
+}
@@ -18,6 +18,56 @@ export function getConnectedDevices() {
     return invoke()<Device[]>("get_connected_devices")
 }
 
+/**
+ * Attached devices that have not been registered yet.
+ */
+export function getUnregisteredDevices() {
+    return invoke()<DiscoveredDevice[]>("get_unregistered_devices")
+}
+
+/**
+ * Register an attached device, creating its default profile. The device is connected and
+ * synced by the discovery service shortly after.
+ */
+export function registerDevice(deviceId: string) {
+    return invoke()<Device>("register_device", { deviceId })
+}
+
+/**
+ * Render `frame_count` frames of a lighting effect on the lights of a device, starting at
+ * `start_ms` and `interval_ms` apart. Key presses drive the reactive effect.
+ */
+export function previewLighting(deviceId: string, lighting: Lighting, presses: LedPress[], startMs: number, intervalMs: number, frameCount: number) {
+    return invoke()<LightingFrame[]>("preview_lighting", { deviceId,lighting,presses,startMs,intervalMs,frameCount })
+}
+
+/**
+ * Every macro in the library.
+ */
+export function getMacros() {
+    return invoke()<Macro[]>("get_macros")
+}
+
+/**
+ * Store a macro in the library, replacing the macro with the same id. The macro must fit
+ * the device it is edited for. Devices whose active profile plays the macro are synced again.
+ */
+export function insertMacro(deviceId: string, newMacro: Macro) {
+    return invoke()<null>("insert_macro", { deviceId,newMacro })
+}
+
+/**
+ * Turn a sequence of captured input events into a macro and store it in the library. The
+ * macro must fit the device it is recorded for.
+ */
+export function recordMacro(deviceId: string, name: string, repeat: RepeatMode, delayMode: DelayMode, events: RecordedEvent[]) {
+    return invoke()<Macro>("record_macro", { deviceId,name,repeat,delayMode,events })
+}
+
+export function deleteMacro(macroId: string) {
+    return invoke()<null>("delete_macro", { macroId })
+}
+
 export function getProfile(profileId: string) {
     return invoke()<Profile>("get_profile", { profileId })
 }
@@ -26,6 +76,17 @@ export function getActiveProfile(deviceId: string) {
     return invoke()<Profile>("get_active_profile", { deviceId })
 }
 
+/**
+ * Make one of the device's profiles active, persist the change and apply the profile to the
+ * hardware. Returns the device with its updated sync status.
+ */
+export function setActiveProfile(deviceId: string, selector: ProfileSelector) {
+    return invoke()<Device>("set_active_profile", { deviceId,selector })
+}
+
+/**
+ * The profile in each of the device's slots, `None` for empty slots.
+ */
 export function getDeviceProfileIds(deviceId: string) {
     return invoke()<(string | null)[]>("get_device_profile_ids", { deviceId })
 }
@@ -38,16 +99,273 @@ export function overwriteProfile(deviceId: string, profileId: string, profile: P
     return invoke()<null>("overwrite_profile", { deviceId,profileId,profile })
 }
 
+/**
+ * Remove a profile from the slots of a device. The profile stays in the library. If it was
+ * the active profile, another profile of the device is activated.
+ */
 export function deleteProfile(deviceId: string, profileId: string) {
     return invoke()<null>("delete_profile", { deviceId,profileId })
 }
 
-export type Device = { id: string; model: DeviceModel; device_type: DeviceType; active_profile: string }
-export type ProfileConfiguration = { Mouse: MouseProfile } | { Keyboard: KeyboardProfile }
+/**
+ * Every profile of the library, including profiles not assigned to any device.
+ */
+export function getLibraryProfiles() {
+    return invoke()<Profile[]>("get_library_profiles")
+}
+
+/**
+ * Add a profile to the library without assigning it to a device.
+ */
+export function addLibraryProfile(profile: Profile) {
+    return invoke()<null>("add_library_profile", { profile })
+}
+
+/**
+ * Assign a profile of the library to a slot of a device. The profile must suit the device's
+ * model. If the slot held the active profile, the assigned profile becomes active.
+ */
+export function assignProfile(deviceId: string, profileId: string, slot: number) {
+    return invoke()<null>("assign_profile", { deviceId,profileId,slot })
+}
+
+/**
+ * Delete a profile from the library. The profile must not be assigned to any device.
+ */
+export function deleteLibraryProfile(profileId: string) {
+    return invoke()<null>("delete_library_profile", { profileId })
+}
+
+/**
+ * Write a profile of the library to a `.crabby-profile` file, along with the macros it plays
+ * back. The profile is described for the model of the given device.
+ */
+export function exportProfile(deviceId: string, profileId: string, path: string) {
+    return invoke()<null>("export_profile", { deviceId,profileId,path })
+}
+
+/**
+ * Read a `.crabby-profile` file and add its profile and macros for a device. Every reason the
+ * profile cannot be used on the device is reported. Ids are replaced, so a file can be
+ * imported any number of times.
+ */
+export function importProfile(deviceId: string, path: string) {
+    return invoke()<ImportReport>("import_profile", { deviceId,path })
+}
+
+/**
+ * Write the devices, profiles, macros and user model definitions to a single
+ * `.crabby-archive` file, for moving the configuration to another installation.
+ */
+export function exportConfigurationArchive(path: string) {
+    return invoke()<null>("export_configuration_archive", { path })
+}
+
+/**
+ * What restoring an archive would change, without changing anything.
+ */
+export function previewConfigurationArchive(path: string) {
+    return invoke()<RestorePreview>("preview_configuration_archive", { path })
+}
+
+/**
+ * Replace the whole configuration with the one held by an archive. Attached devices are synced
+ * with their restored profiles.
+ */
+export function restoreConfigurationArchive(path: string) {
+    return invoke()<RestorePreview>("restore_configuration_archive", { path })
+}
+
+/**
+ * The stored files that were corrupt at start up and what could be recovered from them. The
+ * notices are handed out once, the interface asks for them when it has loaded since events
+ * sent during start up would be missed.
+ */
+export function takeRecoveryNotices() {
+    return invoke()<RecoveryNotice[]>("take_recovery_notices")
+}
+
+/**
+ * The previous versions of the device and profile stores, newest first.
+ */
+export function listBackups() {
+    return invoke()<BackupInfo[]>("list_backups")
+}
+
+/**
+ * Go back to a previous version of a store, as listed by [`list_backups`]. Attached devices
+ * are synced with their restored profiles.
+ */
+export function restoreBackup(file: string, createdMs: number) {
+    return invoke()<null>("restore_backup", { file,createdMs })
+}
+
+/**
+ * How many previous versions of each store are kept, and for how long.
+ */
+export function getBackupPolicy() {
+    return invoke()<BackupPolicy>("get_backup_policy")
+}
+
+/**
+ * Change how many previous versions of each store are kept, from the next write of each.
+ */
+export function setBackupPolicy(backupPolicy: BackupPolicy) {
+    return invoke()<null>("set_backup_policy", { backupPolicy })
+}
+
+/**
+ * A supported device found attached to the machine.
+ */
+export type DiscoveredDevice = { model: DeviceModel; identity: HardwareIdentity; location: string }
+export type LedColour = { led: Led; colour: Rgb }
+/**
+ * A 24-bit RGB colour.
+ */
+export type Rgb = { r: number; g: number; b: number }
+export type Device = { id: string; model: DeviceModel; device_type: DeviceType; active_profile: string; connection_state?: ConnectionState; hardware_identity?: HardwareIdentity | null; sync_status?: SyncStatus }
+/**
+ * A physical mouse button.
+ */
+export type MouseButton = "Left" | "Right" | "Middle" | "Back" | "Forward" | "Dpi"
+export type LightingDirection = "LeftToRight" | "RightToLeft" | "TopToBottom" | "BottomToTop"
+export type Profile = { id: string; metadata?: ProfileMetadata; configuration: ProfileConfiguration }
+export type ConnectionState = "Connected" | "Disconnected"
+export type Keycode = "A" | "B" | "C" | "D" | "E" | "F" | "G" | "H" | "I" | "J" | "K" | "L" | "M" | "N" | "O" | "P" | "Q" | "R" | "S" | "T" | "U" | "V" | "W" | "X" | "Y" | "Z" | "Digit1" | "Digit2" | "Digit3" | "Digit4" | "Digit5" | "Digit6" | "Digit7" | "Digit8" | "Digit9" | "Digit0" | "Enter" | "Escape" | "Backspace" | "Tab" | "Space" | "Minus" | "Equal" | "LeftBracket" | "RightBracket" | "Backslash" | "Semicolon" | "Quote" | "Grave" | "Comma" | "Period" | "Slash" | "CapsLock" | "F1" | "F2" | "F3" | "F4" | "F5" | "F6" | "F7" | "F8" | "F9" | "F10" | "F11" | "F12" | "PrintScreen" | "ScrollLock" | "Pause" | "Insert" | "Home" | "PageUp" | "Delete" | "End" | "PageDown" | "Right" | "Left" | "Down" | "Up" | "Menu" | "LeftCtrl" | "LeftShift" | "LeftAlt" | "LeftGui" | "RightCtrl" | "RightShift" | "RightAlt" | "RightGui"
+/**
+ * The identity of a physical device as reported by the hardware. A [`DeviceId`] derived from
+ * it stays the same across reboots and USB ports, so profiles remain attached to the device.
+ */
+export type HardwareIdentity = { vendor_id: number; product_id: number; serial: string | null; fingerprint: string | null }
+/**
+ * A set of key bindings. Keys without a binding do nothing on the base layer and are
+ * transparent on every other layer.
+ */
+export type Layer = { bindings: KeyBinding[] }
 export type DeviceType = { Mouse: Mouse } | { Keyboard: Keyboard }
-export type MouseProfile = { dpi: number }
-export type DeviceModel = "M1" | "K1"
-export type KeyboardProfile<> = null
-export type Profile = { id: string; device_id: string; configuration: ProfileConfiguration }
+/**
+ * A file of the archive as listed in the manifest.
+ */
+export type ArchiveEntry = { name: string; size: number; checksum: number }
+/**
+ * Tells the user that a stored file could not be read and what was made of it.
+ */
+export type RecoveryNotice = { file: string; quarantine_path: string | null; recovered: number; discarded: number | null }
+export type KeyboardProfile = { layers: Layer[]; lighting: Lighting }
+/**
+ * Modifiers held down together with a key. Encoded as the HID modifier byte.
+ */
+export type Modifiers = { left_ctrl: boolean; left_shift: boolean; left_alt: boolean; left_gui: boolean; right_ctrl: boolean; right_shift: boolean; right_alt: boolean; right_gui: boolean }
+/**
+ * What a key does when pressed.
+ */
+export type KeyAction = "None" | "Transparent" | { Key: Keycode } | { Combo: { modifiers: Modifiers; key: Keycode | null } } | { LayerMomentary: number } | { LayerToggle: number } | { Media: MediaKey } | { Macro: string }
+/**
+ * A previous version of a stored file.
+ */
+export type BackupInfo = { file: string; created_ms: number; size: number }
+export type ProfileConfiguration = { Mouse: MouseProfile } | { Keyboard: KeyboardProfile }
+/**
+ * How the pauses between the steps of a recorded macro are chosen.
+ */
+export type DelayMode = "Recorded" | { Fixed: number }
+export type ButtonBinding = { button: MouseButton; action: ButtonAction }
+export type ArchiveManifest = { format: string; format_version: number; app_version: string; created_ms: number; entries: ArchiveEntry[] }
+export type MediaKey = "PlayPause" | "Stop" | "NextTrack" | "PreviousTrack" | "Mute" | "VolumeUp" | "VolumeDown"
+/**
+ * An input event captured while recording. Timestamps are in milliseconds from any fixed
+ * origin and must not decrease.
+ */
+export type RecordedEvent = { event: MacroEvent; timestamp_ms: number }
+/**
+ * A press of a key, used to preview the reactive effect.
+ */
+export type LedPress = { led: Led; timestamp_ms: number }
+export type MacroEvent = { KeyPress: Keycode } | { KeyRelease: Keycode } | { ButtonPress: MouseButton } | { ButtonRelease: MouseButton }
+/**
+ * What the user knows a profile by. The timestamps and the revision are maintained by the
+ * [`ProfileManager`][ProfileManager], values sent along with a profile are ignored.
+ * 
+ * [ProfileManager]: super::profile_manager::ProfileManager
+ */
+export type ProfileMetadata = { name: string; description?: string; colour?: Rgb | null; icon?: string | null; created_ms?: number; modified_ms?: number; revision?: number }
+/**
+ * Refers to one of a device's profiles, either by its [`ProfileId`] or by the index of the
+ * [`ProfileSlots`] slot it occupies.
+ */
+export type ProfileSelector = { Id: string } | { Slot: number }
 export type Keyboard<> = null
+export type PollingRate = "Hz125" | "Hz250" | "Hz500" | "Hz1000" | "Hz2000" | "Hz4000" | "Hz8000"
+/**
+ * The effect restoring an archive would have on the current configuration.
+ */
+export type RestorePreview = { manifest: ArchiveManifest; devices: Changes; profiles: Changes; macros: Changes; model_definitions: Changes }
+/**
+ * A light that can be coloured on its own: a zone of a mouse or a key of a keyboard.
+ */
+export type Led = { Zone: LightingZone } | { Key: { row: number; col: number } }
+/**
+ * The name of a device model. What a model is and what it supports is described by its
+ * definition, see [`capabilities`]. Models without a definition are kept as they are, so that
+ * devices of unknown models survive in the configuration.
+ */
+export type DeviceModel = string
+/**
+ * The outcome of an import, for telling the user what happened to the profile.
+ */
+export type ImportReport = { profile: Profile; slot: number | null; notes: string[] }
+/**
+ * What a restore adds, removes and replaces, by the names the user knows things by.
+ */
+export type Changes = { added: string[]; removed: string[]; changed: string[] }
+/**
+ * An event followed by a pause before the next step is played.
+ */
+export type MacroStep = { event: MacroEvent; delay_ms: number }
+/**
+ * A sensitivity level the user can cycle through with the DPI button. X and Y sensitivity are
+ * set separately.
+ */
+export type DpiStage = { x: number; y: number; colour: Rgb }
+export type ScrollDirection = "Up" | "Down" | "Left" | "Right"
+/**
+ * A lighting zone of a mouse.
+ */
+export type LightingZone = "ScrollWheel" | "Logo" | "Underglow"
+export type RepeatMode = "Once" | "WhileHeld" | "Toggle"
+/**
+ * The colour of every light at a point in time.
+ */
+export type LightingFrame = { timestamp_ms: number; colours: LedColour[] }
+/**
+ * Whether the configuration stored on a device matches its active profile.
+ */
+export type SyncStatus = "InSync" | "Pending" | { Failed: string }
 export type Mouse = { dpi: number }
+/**
+ * What a mouse button does when pressed.
+ */
+export type ButtonAction = "Disabled" | { Click: MouseButton } | { Keystroke: { modifiers: Modifiers; key: Keycode | null } } | "DpiUp" | "DpiDown" | "DpiCycle" | { ProfileSlot: number } | { Scroll: ScrollDirection } | { Macro: string }
+export type LightingEffect = "Off" | "Static" | "Breathing" | "Wave" | "Reactive" | "SpectrumCycle"
+/**
+ * The lighting of a profile. `speed` ranges from 1 (one cycle every ten seconds) to 10 (one
+ * cycle per second) and `brightness` is a percentage.
+ */
+export type Lighting = { effect: LightingEffect; speed: number; brightness: number; direction: LightingDirection; palette: Rgb[]; colours: LedColour[] }
+/**
+ * The action of the key at a position of the keyboard matrix.
+ */
+export type KeyBinding = { row: number; col: number; action: KeyAction }
+export type MouseProfile = { dpi_stages: DpiStage[]; active_dpi_stage: number; polling_rate: PollingRate; lift_off_distance_mm: number; angle_snapping: boolean; motion_sync: boolean; debounce_ms: number; buttons: ButtonBinding[]; allow_unbound_primary: boolean; lighting: Lighting }
+/**
+ * How many previous versions of each file are kept, and for how long. The policy is stored
+ * with the files it applies to and can be changed from the interface.
+ */
+export type BackupPolicy = { max_count: number; max_age_days: number }
+/**
+ * A sequence of key and mouse events that a key or button plays back when pressed. Macros
+ * are kept in the library alongside profiles and referenced from them by [`MacroId`], so a
+ * profile plays the same macros on every device it is assigned to. A device stores the
+ * macros of its active profile.
+ */
+export type Macro = { id: string; name: string; steps: MacroStep[]; repeat: RepeatMode }
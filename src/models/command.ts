@@ -7,8 +7,10 @@ import {
     insertProfile,
     overwriteProfile,
     deleteProfile,
+    takeRecoveryNotices,
     Device,
     Profile,
+    RecoveryNotice,
 } from "../bindings";
 
 type ProfileManagerError = {
@@ -141,6 +143,10 @@ export class Command {
         return this.runCommand(() => deleteProfile(deviceId, profileId));
     }
 
+    static async takeRecoveryNotices(): Promise<RecoveryNotice[] | CommandError> {
+        return this.runCommand(() => takeRecoveryNotices());
+    }
+
     private static async runCommand<T>(fn: () => Promise<T>): Promise<T | CommandError> {
         console.log(`Command Invoked: ${fn}`);
         try {
@@ -1,15 +1,54 @@
 import React from "react";
 import { GlobalInitialState, GlobalReducer } from "@models/global";
+import { Command, isCommandError } from "@models/command";
+import { RecoveryNotice } from "bindings";
 import { LandingPage } from "./landing_page/landing_page";
 
 function App(): React.ReactElement {
     const [globalState, globalDispatch] = React.useReducer(GlobalReducer, GlobalInitialState);
+    const [recoveryNotices, setRecoveryNotices] = React.useState<RecoveryNotice[]>([]);
+
+    React.useEffect(() => {
+        /* The notices are handed out once, so they are appended
+         * rather than replaced when the effect runs twice.
+         */
+        Command.takeRecoveryNotices().then(res => {
+            if(isCommandError(res)) {
+                return;
+            }
+            setRecoveryNotices(notices => [...notices, ...res]);
+        });
+    }, []);
 
     return (
-         <LandingPage
-             globalState={globalState}
-             globalDispatch={globalDispatch}
-         />
+        <>
+            {
+                recoveryNotices.map(notice => (
+                    <div key={notice.file} role="alert" className="d-alert d-alert-warning">
+                        <span>
+                            {`${notice.file} was corrupt, ${notice.recovered} entries were recovered. `}
+                            {
+                                notice.quarantine_path ?
+                                    `The file was moved to ${notice.quarantine_path}.` :
+                                    "The file could not be moved, changes to it are not saved."
+                            }
+                        </span>
+                        <button
+                            className="d-btn d-btn-sm"
+                            onClick={() => setRecoveryNotices(notices =>
+                                notices.filter(other => other !== notice)
+                            )}
+                        >
+                            Dismiss
+                        </button>
+                    </div>
+                ))
+            }
+            <LandingPage
+                globalState={globalState}
+                globalDispatch={globalDispatch}
+            />
+        </>
     );
 }
 